@@ -1,42 +1,617 @@
-use crate::instructions;
-use crate::instructions::Instruction;
-use std::collections::HashMap;
-use std::env;
-use std::fmt::format;
+// src/assembler/mod.rs
+//
+// Two-pass assembler for RISA16 source. Pass 1 reads the source a line at a
+// time, evaluating conditional blocks and splicing in included files and
+// macro expansions, and gives every label its address; pass 2 emits the
+// bytes. Errors are collected rather than stopping at the first one.
+//
+// A line splits into words at whitespace and commas, except inside a string
+// or parentheses. Each word is one operand, so an expression with spaces in
+// it must be parenthesised, `movimm r0 (table + 4)`, or written without
+// them, `movimm r0 table+4`; left bare, its words count as extra operands and
+// the line is an error. Only the single operand of .equ, .set, .org and
+// .align runs to the end of the line.
+
+use crate::expr::{Env, Expr};
+use crate::json::Json;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// what the debugger needs to map addresses back to the source
+#[derive(Clone, Debug, Default)]
+pub struct DebugInfo {
+    pub symbols: HashMap<String, u16>,  // label -> address
+    pub lines: BTreeMap<u16, Location>, // instruction address -> where it was written
+    pub files: Vec<String>,             // source names, indexed by Location::file
+}
+
+// where in the source an instruction came from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub file: usize,   // index into DebugInfo::files
+    pub line: usize,   // 1-based
+    pub column: usize, // 1-based, of the mnemonic
+    pub text: String,  // the instruction as written, without label or comment
+}
+
+impl DebugInfo {
+    pub fn line_for(&self, addr: u16) -> Option<usize> {
+        self.lines.get(&addr).map(|l| l.line)
+    }
+
+    pub fn location(&self, addr: u16) -> Option<&Location> {
+        self.lines.get(&addr)
+    }
+
+    pub fn file_name(&self, location: &Location) -> &str {
+        self.files.get(location.file).map_or("", |f| f.as_str())
+    }
+
+    // `prog.asm:17: load r1 0x2000`, or `line 17: ...` for unnamed source
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let location = self.location(addr)?;
+        match self.file_name(location) {
+            "" => Some(format!("line {}: {}", location.line, location.text)),
+            file => Some(format!("{}:{}: {}", file, location.line, location.text)),
+        }
+    }
+
+    // first label defined at addr, in name order so the choice is stable
+    pub fn symbol_at(&self, addr: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .filter(|(_, a)| **a == addr)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+}
+
+// error codes; a code keeps its meaning, so tools can match on it
+const SYNTAX: &str = "E001"; // malformed line, unknown instruction or directive
+const OPERAND: &str = "E002"; // operand of the wrong kind or out of range
+const UNDEFINED: &str = "E003"; // name used but never defined
+const DUPLICATE: &str = "E004"; // name defined twice
+const LAYOUT: &str = "E005"; // overlapping regions, or past the address space
+const MACRO: &str = "E006"; // macro definition or call
+const INCLUDE: &str = "E007"; // .include or .incbin file
+const CONDITIONAL: &str = "E008"; // unbalanced .if blocks
+
+// one problem in the source, and where it is
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub file: String,       // source name, empty for unnamed source
+    pub line: usize,        // 1-based; 0 for a problem with no line, such as an unreadable file
+    pub column: usize,      // 1-based, where the span starts
+    pub span: usize,        // characters the problem covers
+    pub code: &'static str, // e.g. E002
+    pub message: String,
+    pub notes: Vec<String>, // the macro calls the line came from, innermost first
+    pub text: String,       // the line as written, up to any comment
+}
+
+impl fmt::Display for AsmError {
+    // `prog.asm:3: message`, as one line
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.file.as_str(), self.line) {
+            (_, 0) => write!(f, "{}", self.message)?,
+            ("", line) => write!(f, "line {}: {}", line, self.message)?,
+            (file, line) => write!(f, "{}:{}: {}", file, line, self.message)?,
+        }
+        if !self.notes.is_empty() {
+            write!(f, " ({})", self.notes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl AsmError {
+    // an error about a whole file, or about no source at all
+    fn unlocated(file: &str, code: &'static str, message: String) -> AsmError {
+        AsmError {
+            file: file.to_string(),
+            line: 0,
+            column: 0,
+            span: 0,
+            code,
+            message,
+            notes: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    // the error with the line it is on and a caret under the span:
+    //
+    //   error[E002]: Register out of bounds
+    //    --> prog.asm:3:12
+    //     |
+    //   3 |     mov r1 r16
+    //     |            ^^^
+    pub fn render(&self) -> String {
+        let mut out = format!("error[{}]: {}\n", self.code, self.message);
+        let file = match self.file.as_str() {
+            "" => "<source>",
+            file => file,
+        };
+        if self.line == 0 {
+            if !self.file.is_empty() {
+                out += &format!(" --> {}\n", file);
+            }
+            return out;
+        }
+
+        let number = self.line.to_string();
+        let gutter = " ".repeat(number.len());
+        // tabs before the span are kept so the caret lines up
+        let indent: String = self
+            .text
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        out += &format!("{}--> {}:{}:{}\n", gutter, file, self.line, self.column);
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", number, self.text.trim_end());
+        out += &format!("{} | {}{}\n", gutter, indent, "^".repeat(self.span.max(1)));
+        for note in self.notes.iter() {
+            out += &format!("{} = note: {}\n", gutter, note);
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> Json {
+        let notes: Vec<Json> = self.notes.iter().map(|n| n.as_str().into()).collect();
+        Json::object(vec![
+            ("file", self.file.as_str().into()),
+            ("line", self.line.into()),
+            ("column", self.column.into()),
+            ("span", self.span.into()),
+            ("code", self.code.into()),
+            ("message", self.message.as_str().into()),
+            ("notes", notes.into()),
+        ])
+    }
+}
+
+// every problem found in one run of the assembler, by file in the order the
+// files were first read, then by line, so an included file's errors come
+// after those of the file that includes it
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmErrors {
+    pub errors: Vec<AsmError>,
+}
+
+impl fmt::Display for AsmErrors {
+    // one error per line
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines: Vec<String> = self.errors.iter().map(|e| e.to_string()).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+impl From<AsmErrors> for String {
+    fn from(errors: AsmErrors) -> String {
+        errors.to_string()
+    }
+}
+
+impl AsmErrors {
+    // every error rendered, with a count at the end
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for error in self.errors.iter() {
+            out += &error.render();
+            out += "\n";
+        }
+        match self.errors.len() {
+            1 => out += "1 error\n",
+            n => out += &format!("{} errors\n", n),
+        }
+        out
+    }
+
+    // JSON Lines: one object per error
+    pub fn to_json_lines(&self) -> String {
+        self.errors
+            .iter()
+            .map(|e| format!("{}\n", e.to_json()))
+            .collect()
+    }
+
+    // every error, as render or to_json_lines writes them
+    pub fn report(&self, format: ErrorFormat) -> String {
+        match format {
+            ErrorFormat::Text => self.render(),
+            ErrorFormat::Json => self.to_json_lines(),
+        }
+    }
+}
+
+// how errors are reported: rendered for people, or as JSON Lines for tools
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorFormat {
+    Text,
+    Json,
+}
+
+pub fn assemble(src: &str) -> Result<Vec<u8>, AsmErrors> {
+    assemble_with_debug(src).map(|(bytecode, _)| bytecode)
+}
+
+pub fn assemble_with_debug(src: &str) -> Result<(Vec<u8>, DebugInfo), AsmErrors> {
+    assemble_named("", src)
+}
+
+// as assemble_with_debug, with name recorded as the source's file name
+pub fn assemble_named(name: &str, src: &str) -> Result<(Vec<u8>, DebugInfo), AsmErrors> {
+    assemble_with_config(name, src, &Config::default())
+}
+
+// settings that change how a program is assembled
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub fill: u8,                    // byte for gaps left by .org and .align
+    pub include_paths: Vec<PathBuf>, // searched by .include and .incbin after the file's directory
+    pub defines: Vec<(String, i64)>, // constants defined before the first line, as by -D
+}
+
+// assembles a string; .include and .incbin are errors, as there are no
+// files to read
+pub fn assemble_with_config(
+    name: &str,
+    src: &str,
+    config: &Config,
+) -> Result<(Vec<u8>, DebugInfo), AsmErrors> {
+    let mut reader = Reader::new(config, false);
+    reader.read(name, src);
+    link(reader)
+}
+
+// assembles the file at path, reading the files it includes
+pub fn assemble_file(path: &Path, config: &Config) -> Result<(Vec<u8>, DebugInfo), AsmErrors> {
+    let mut reader = Reader::new(config, true);
+    let name = path.display().to_string();
+    let src = reader.loader.open(path).map_err(|e| AsmErrors {
+        errors: vec![AsmError::unlocated(&name, INCLUDE, e)],
+    })?;
+    reader.read(&name, &src);
+    reader.loader.stack.pop();
+    link(reader)
+}
+
+// runs pass 2 over the lines pass 1 kept. Lines that could not be loaded
+// or expanded stop assembly before it, as a missing file or macro would
+// make errors of everything that uses it; otherwise both passes report
+// every error they find.
+fn link(reader: Reader) -> Result<(Vec<u8>, DebugInfo), AsmErrors> {
+    let Reader {
+        loader,
+        conditions,
+        mut assembler,
+        kept,
+        errors,
+        mut problems,
+        ..
+    } = reader;
+    let files = loader.files;
+
+    // by file and line, whichever pass found them
+    let failed = |mut errors: Vec<AsmError>| {
+        errors.sort_by_key(|e| (files.iter().position(|f| *f == e.file), e.line, e.column));
+        Err(AsmErrors { errors })
+    };
+
+    if !errors.is_empty() {
+        return failed(errors);
+    }
+    for block in conditions.open.iter() {
+        let message = format!("{} without .endif", block.line.tokens[0].to_lowercase());
+        problems.push(located(
+            &files,
+            &block.line,
+            Problem::new(CONDITIONAL, message),
+        ));
+    }
+
+    // emit bytecode; constants are evaluated again in order, now that every
+    // label is known
+    assembler.pc = 0;
+    assembler.symbols.complete = true;
+    for (line, next) in kept.iter() {
+        if let Err(problem) = assembler.emit(line) {
+            problems.push(located(&files, line, problem));
+            assembler.pc = *next;
+        }
+    }
+    if !problems.is_empty() {
+        return failed(problems);
+    }
+
+    let Assembler {
+        symbols,
+        image,
+        lines,
+        ..
+    } = assembler;
+    let debug = DebugInfo {
+        symbols: symbols.labels,
+        lines,
+        files,
+    };
+
+    Ok((image.bytes, debug))
+}
+
+// an error in one line, before it is given a place in the source
+struct Problem {
+    code: &'static str,
+    message: String,
+    token: Option<usize>, // the word of the line it is about, or None for the whole line
+}
+
+impl Problem {
+    fn new(code: &'static str, message: impl Into<String>) -> Problem {
+        Problem {
+            code,
+            message: message.into(),
+            token: None,
+        }
+    }
+
+    // about word i of the line, unless it already names a word
+    fn on(mut self, i: usize) -> Problem {
+        self.token = self.token.or(Some(i));
+        self
+    }
+
+    // for a problem found among a line's operands: their word numbers
+    // become the line's, first being the word of the first operand
+    fn shift(mut self, first: usize) -> Problem {
+        self.token = self.token.map(|i| i + first);
+        self
+    }
+}
+
+// `prog.asm:3`, or `line 3` for unnamed source
+fn site(files: &[String], file: usize, number: usize) -> String {
+    match files.get(file).map_or("", |f| f.as_str()) {
+        "" => format!("line {}", number),
+        file => format!("{}:{}", file, number),
+    }
+}
 
-pub fn assemble(src: &str) -> Result<Vec<u8>, String> {
-    // --snip--
-    let mut bytecode: Vec<u8> = Vec::new();
-    let contents = src;
-    let tokens: Vec<Vec<String>> = tokenize(contents.to_string());
+// problem located at line, and at the macro calls it came from
+fn located(files: &[String], line: &Line, problem: Problem) -> AsmError {
+    let name = |file: usize| files.get(file).map_or("", |f| f.as_str());
 
-    let mut pc: u16 = 0;
-    let mut label_table: HashMap<String, u16> = HashMap::new();
+    // the word the problem is about, or everything from the first word to
+    // the end of the last
+    let last = line.tokens.len() - 1;
+    let (first, last) = match problem.token {
+        Some(i) if i <= last => (i, i),
+        _ => (0, last),
+    };
+    let start = line.offsets[first];
+    let end = line.offsets[last] + line.tokens[last].len();
 
-    // create label table
-    for line_tokens in tokens.iter() {
+    let notes = line
+        .calls
+        .iter()
+        .enumerate()
+        .map(|(i, (name, file, call))| {
+            let joiner = if i == 0 { "in" } else { "from" };
+            format!(
+                "{} macro `{}` called at {}",
+                joiner,
+                name,
+                site(files, *file, *call)
+            )
+        })
+        .collect();
+
+    AsmError {
+        file: name(line.file).to_string(),
+        line: line.number,
+        column: line.text[..start].chars().count() + 1,
+        span: line.text[start..end].chars().count(),
+        code: problem.code,
+        message: problem.message,
+        notes,
+        text: line.text.clone(),
+    }
+}
+
+// an .if, .ifdef or .ifndef block the current line is in
+struct Block {
+    line: Line,   // where it was opened
+    parent: bool, // whether the enclosing block is being assembled
+    active: bool, // whether the current branch is being assembled
+    taken: bool,  // whether any branch so far was
+    other: bool,  // whether .else was seen
+}
+
+// the conditional blocks open at the current line, innermost last
+#[derive(Default)]
+struct Conditions {
+    open: Vec<Block>,
+}
+
+impl Conditions {
+    // whether the lines here are assembled
+    fn active(&self) -> bool {
+        self.open.last().is_none_or(|b| b.active)
+    }
+
+    // handles .if expr, .ifdef name, .ifndef name, .elif expr, .else and
+    // .endif, returning false for any other line. Conditions are evaluated
+    // as pass 1 reads the source, so they can only use names defined above
+    // them; inside a skipped block they are not evaluated at all. A directive in error
+    // still opens or closes its block, and a condition in error is false
+    // with every branch after it, so one mistake does not cascade.
+    fn directive(&mut self, line: &Line, symbols: &Symbols) -> Result<bool, Problem> {
+        let idx = line.tokens[0].ends_with(':') as usize;
+        let Some(directive) = line.tokens.get(idx).map(|w| w.to_lowercase()) else {
+            return Ok(false);
+        };
+        let args = &line.tokens[idx + 1..];
+
+        let condition = || -> Result<bool, Problem> {
+            match directive.as_str() {
+                ".if" | ".elif" if args.is_empty() => Err(Problem::new(
+                    SYNTAX,
+                    format!("{} expects a condition", directive),
+                )),
+                ".if" | ".elif" => Ok(operand(&args.join(" "), symbols)? != 0),
+                _ => match args {
+                    [name] => Ok(symbols.defined(name) == (directive == ".ifdef")),
+                    _ => Err(Problem::new(
+                        SYNTAX,
+                        format!("{} expects a name", directive),
+                    )),
+                },
+            }
+        };
+
+        let mut result = Ok(true);
+        match directive.as_str() {
+            ".if" | ".ifdef" | ".ifndef" => {
+                let parent = self.active();
+                let taken = match parent {
+                    true => condition(),
+                    false => Ok(false),
+                };
+                self.open.push(Block {
+                    line: line.clone(),
+                    parent,
+                    active: matches!(taken, Ok(true)),
+                    taken: !matches!(taken, Ok(false)),
+                    other: false,
+                });
+                result = taken.map(|_| true);
+            }
+            ".elif" => {
+                let Some(block) = self.open.last() else {
+                    return Err(Problem::new(CONDITIONAL, ".elif without .if"));
+                };
+                if block.other {
+                    return Err(Problem::new(CONDITIONAL, ".elif after .else"));
+                }
+                let taken = match block.parent && !block.taken {
+                    true => condition(),
+                    false => Ok(false),
+                };
+                let block = self.open.last_mut().unwrap();
+                block.active = matches!(taken, Ok(true));
+                block.taken |= !matches!(taken, Ok(false));
+                result = taken.map(|_| true);
+            }
+            ".else" | ".endif" if !args.is_empty() => {
+                result = Err(
+                    Problem::new(SYNTAX, format!("{} expects no operands", directive)).on(idx + 1),
+                );
+            }
+            _ => {}
+        }
+        match directive.as_str() {
+            ".else" => {
+                let Some(block) = self.open.last_mut() else {
+                    return Err(Problem::new(CONDITIONAL, ".else without .if"));
+                };
+                if block.other {
+                    return Err(Problem::new(CONDITIONAL, ".else after .else"));
+                }
+                block.active = block.parent && !block.taken;
+                block.other = true;
+            }
+            ".endif" => {
+                self.open
+                    .pop()
+                    .ok_or(Problem::new(CONDITIONAL, ".endif without .if"))?;
+            }
+            ".if" | ".ifdef" | ".ifndef" | ".elif" => {}
+            _ => return Ok(false),
+        }
+
+        if idx == 1 {
+            let message = format!("{} cannot have a label", directive);
+            return Err(Problem::new(SYNTAX, message).on(0));
+        }
+        result
+    }
+}
+
+// the state the two passes share
+struct Assembler {
+    symbols: Symbols,
+    image: Image,
+    pc: u16,
+    lines: BTreeMap<u16, Location>,
+}
+
+impl Assembler {
+    // pass 1: defines the line's label and moves the PC past it; files
+    // name the label's site
+    fn size(&mut self, line: &Line, files: &[String]) -> Result<(), Problem> {
+        let line_tokens = &line.tokens;
         let mut idx = 0;
 
         if line_tokens[0].ends_with(":") {
             let label = line_tokens[0].trim_end_matches(':').to_string();
-            label_table.insert(label, pc);
+            let site = site(files, line.file, line.number);
+            self.symbols
+                .define_label(label, self.pc, site)
+                .map_err(|e| Problem::new(DUPLICATE, e).on(0))?;
             idx = 1;
         }
 
         // label only line
         if idx >= line_tokens.len() {
-            continue;
+            return Ok(());
+        }
+
+        let instr = line_tokens[idx].to_lowercase();
+        let args = &line_tokens[idx + 1..];
+        let defined = self.symbols.define_constant(&instr, args);
+        if defined.map_err(|p| p.shift(idx + 1))? {
+            return Ok(());
         }
+        let placed = placement(&instr, args, self.pc, &self.symbols);
+        if let Some(addr) = placed.map_err(|p| p.shift(idx + 1))? {
+            self.pc = addr;
+            return Ok(());
+        }
+
+        // data directive or instruction
+        let length = match instr.starts_with('.') {
+            true if instr == ".incbin" => included(line)?.len(),
+            true => data_bytes(&instr, args, &self.symbols)
+                .map_err(|p| p.shift(idx + 1))?
+                .len(),
+            false => find_instr_length(instr.to_string())
+                .map_err(|e| Problem::new(SYNTAX, e).on(idx))? as usize,
+        };
+        self.advance(length)
+    }
 
-        // instruction
-        let instr = &line_tokens[idx];
-        pc += find_instr_length(instr.to_string())?;
+    // moves the PC past length bytes
+    fn advance(&mut self, length: usize) -> Result<(), Problem> {
+        self.pc = u16::try_from(length)
+            .ok()
+            .and_then(|length| self.pc.checked_add(length))
+            .ok_or(Problem::new(
+                LAYOUT,
+                "program does not fit in the address space",
+            ))?;
+        Ok(())
     }
 
-    // emit bytecode
-    for line_tokens in tokens.iter() {
+    // pass 2: places the line's bytes
+    fn emit(&mut self, line: &Line) -> Result<(), Problem> {
+        let line_tokens = &line.tokens;
         let mut idx = 0;
 
         if line_tokens[0].ends_with(":") {
@@ -44,135 +619,172 @@ pub fn assemble(src: &str) -> Result<Vec<u8>, String> {
         }
 
         if idx >= line_tokens.len() {
-            continue;
+            return Ok(());
         }
 
-        let mut instr = (&line_tokens[idx]).to_string();
+        let mut instr = line_tokens[idx].to_string();
         instr = instr.to_lowercase();
+
+        let args = &line_tokens[idx + 1..];
+        let defined = self.symbols.define_constant(&instr, args);
+        if defined.map_err(|p| p.shift(idx + 1))? {
+            return Ok(());
+        }
+        let placed = placement(&instr, args, self.pc, &self.symbols);
+        if let Some(addr) = placed.map_err(|p| p.shift(idx + 1))? {
+            self.pc = addr;
+            return Ok(());
+        }
+
+        // data is not code, so it gets no line table entry
+        if instr.starts_with('.') {
+            let data = match instr.as_str() {
+                ".incbin" => included(line)?.to_vec(),
+                _ => data_bytes(&instr, args, &self.symbols).map_err(|p| p.shift(idx + 1))?,
+            };
+            self.image
+                .place(self.pc, &data)
+                .map_err(|e| Problem::new(LAYOUT, e))?;
+            return self.advance(data.len());
+        }
+
+        let offset = line.offsets[idx];
+        let location = Location {
+            file: line.file,
+            line: line.number,
+            column: line.text[..offset].chars().count() + 1,
+            text: line.text[offset..].trim_end().to_string(),
+        };
+        self.lines.insert(self.pc, location);
+
+        let mut code: Vec<u8> = Vec::new();
         match instr.as_str() {
             "movimm" => {
-                bytecode.push(0x01);
+                code.push(0x01);
 
                 if line_tokens.len() - idx != 3 {
-                    return Err("movimm expects 2 operands".into());
+                    return Err(Problem::new(SYNTAX, "movimm expects 2 operands"));
                 }
 
                 // push register
-                let reg = parse_register(&line_tokens[idx + 1])?;
-                bytecode.push(reg);
+                let reg = register(line_tokens, idx + 1)?;
+                code.push(reg);
 
                 // push immediate
 
-                let imm: u16 = parse_u16(&line_tokens[idx + 2])?;
+                let imm: u16 =
+                    word(&line_tokens[idx + 2], &self.symbols).map_err(|p| p.on(idx + 2))?;
                 let imm_hi: u8 = (imm >> 8) as u8;
                 let imm_lo: u8 = imm as u8;
 
-                bytecode.push(imm_hi);
-                bytecode.push(imm_lo);
+                code.push(imm_hi);
+                code.push(imm_lo);
             }
 
             "mov" => {
-                bytecode.push(0x02);
+                code.push(0x02);
 
                 if line_tokens.len() - idx != 3 {
-                    return Err("mov expects 2 operands".into());
+                    return Err(Problem::new(SYNTAX, "mov expects 2 operands"));
                 }
 
                 // push dest register
-                let dest_reg = parse_register(&line_tokens[idx + 1])?;
-                bytecode.push(dest_reg);
+                let dest_reg = register(line_tokens, idx + 1)?;
+                code.push(dest_reg);
 
                 // push src register
-                let src_reg = parse_register(&line_tokens[idx + 2])?;
-                bytecode.push(src_reg);
+                let src_reg = register(line_tokens, idx + 2)?;
+                code.push(src_reg);
             }
 
             "load" => {
-                bytecode.push(0x03);
+                code.push(0x03);
 
                 if line_tokens.len() - idx != 3 {
-                    return Err("load expects 2 operands".into());
+                    return Err(Problem::new(SYNTAX, "load expects 2 operands"));
                 }
 
                 // push register
-                let reg = parse_register(&line_tokens[idx + 1])?;
-                bytecode.push(reg);
+                let reg = register(line_tokens, idx + 1)?;
+                code.push(reg);
 
                 // push address
-                let addr: u16 = parse_u16(&line_tokens[idx + 2])?;
+                let addr: u16 =
+                    word(&line_tokens[idx + 2], &self.symbols).map_err(|p| p.on(idx + 2))?;
                 let addr_hi: u8 = (addr >> 8) as u8;
                 let addr_lo: u8 = addr as u8;
 
-                bytecode.push(addr_hi);
-                bytecode.push(addr_lo);
+                code.push(addr_hi);
+                code.push(addr_lo);
             }
 
             "store" => {
-                bytecode.push(0x04);
+                code.push(0x04);
 
                 if line_tokens.len() - idx != 3 {
-                    return Err("store expects 2 operands".into());
+                    return Err(Problem::new(SYNTAX, "store expects 2 operands"));
                 }
 
                 // push address
-                let addr: u16 = parse_u16(&line_tokens[idx + 1])?;
+                let addr: u16 =
+                    word(&line_tokens[idx + 1], &self.symbols).map_err(|p| p.on(idx + 1))?;
                 let addr_hi: u8 = (addr >> 8) as u8;
                 let addr_lo: u8 = addr as u8;
 
-                bytecode.push(addr_hi);
-                bytecode.push(addr_lo);
+                code.push(addr_hi);
+                code.push(addr_lo);
 
                 // push register
-                let reg = parse_register(&line_tokens[idx + 2])?;
-                bytecode.push(reg);
+                let reg = register(line_tokens, idx + 2)?;
+                code.push(reg);
             }
 
             "add" => {
-                bytecode.push(0x05);
+                code.push(0x05);
 
                 if line_tokens.len() - idx != 3 {
-                    return Err("add expects 2 operands".into());
+                    return Err(Problem::new(SYNTAX, "add expects 2 operands"));
                 }
 
                 // push dest register
-                let dest_reg = parse_register(&line_tokens[idx + 1])?;
-                bytecode.push(dest_reg);
+                let dest_reg = register(line_tokens, idx + 1)?;
+                code.push(dest_reg);
 
                 // push source register
-                let src_reg = parse_register(&line_tokens[idx + 2])?;
-                bytecode.push(src_reg);
+                let src_reg = register(line_tokens, idx + 2)?;
+                code.push(src_reg);
             }
 
             "sub" => {
-                bytecode.push(0x06);
+                code.push(0x06);
 
                 if line_tokens.len() - idx != 3 {
-                    return Err("sub expects 2 operands".into());
+                    return Err(Problem::new(SYNTAX, "sub expects 2 operands"));
                 }
 
                 // push dest register
-                let dest_reg = parse_register(&line_tokens[idx + 1])?;
-                bytecode.push(dest_reg);
+                let dest_reg = register(line_tokens, idx + 1)?;
+                code.push(dest_reg);
 
                 // push source register
-                let src_reg = parse_register(&line_tokens[idx + 2])?;
-                bytecode.push(src_reg);
+                let src_reg = register(line_tokens, idx + 2)?;
+                code.push(src_reg);
             }
 
             "cmp" => {
-                bytecode.push(0x07);
+                code.push(0x07);
 
                 if line_tokens.len() - idx != 3 {
-                    return Err("cmp expects 2 operands".into());
+                    return Err(Problem::new(SYNTAX, "cmp expects 2 operands"));
                 }
 
                 // push dest register
-                let reg_a = parse_register(&line_tokens[idx + 1])?;
-                bytecode.push(reg_a);
+                let reg_a = register(line_tokens, idx + 1)?;
+                code.push(reg_a);
 
                 // push source register
-                let reg_b = parse_register(&line_tokens[idx + 2])?;
-                bytecode.push(reg_b);
+                let reg_b = register(line_tokens, idx + 2)?;
+                code.push(reg_b);
             }
 
             "jmp" | "jmpz" | "jmpnz" => {
@@ -183,42 +795,157 @@ pub fn assemble(src: &str) -> Result<Vec<u8>, String> {
                     _ => unreachable!(),
                 };
 
-                bytecode.push(opcode);
+                code.push(opcode);
 
                 if line_tokens.len() - idx != 2 {
-                    return Err("jump operations expects 1 operand".into());
+                    return Err(Problem::new(SYNTAX, "jump operations expects 1 operand"));
                 }
 
-                let target = &line_tokens[idx + 1];
-                let addr: u16 = if let Ok(num) = parse_u16(target) {
-                    num
-                } else {
-                    *label_table
-                        .get(target)
-                        .ok_or("jmp operand references label that does not exist")?
-                };
+                let addr: u16 =
+                    word(&line_tokens[idx + 1], &self.symbols).map_err(|p| p.on(idx + 1))?;
 
                 let addr_hi: u8 = (addr >> 8) as u8;
                 let addr_lo: u8 = addr as u8;
 
-                bytecode.push(addr_hi);
-                bytecode.push(addr_lo);
+                code.push(addr_hi);
+                code.push(addr_lo);
+            }
+
+            "rdcycle" | "rdinstret" => {
+                let opcode = match instr.as_str() {
+                    "rdcycle" => 0x0B,
+                    "rdinstret" => 0x0C,
+                    _ => unreachable!(),
+                };
+
+                code.push(opcode);
+
+                if line_tokens.len() - idx != 2 {
+                    return Err(Problem::new(SYNTAX, format!("{} expects 1 operand", instr)));
+                }
+
+                // push register
+                let reg = register(line_tokens, idx + 1)?;
+                code.push(reg);
+            }
+
+            "in" => {
+                code.push(0x0D);
+
+                if line_tokens.len() - idx != 3 {
+                    return Err(Problem::new(SYNTAX, "in expects 2 operands"));
+                }
+
+                // push register
+                let reg = register(line_tokens, idx + 1)?;
+                code.push(reg);
+
+                // push port
+                let port = port(&line_tokens[idx + 2], &self.symbols).map_err(|p| p.on(idx + 2))?;
+                code.push(port);
+            }
+
+            "out" => {
+                code.push(0x0E);
+
+                if line_tokens.len() - idx != 3 {
+                    return Err(Problem::new(SYNTAX, "out expects 2 operands"));
+                }
+
+                // push port
+                let port = port(&line_tokens[idx + 1], &self.symbols).map_err(|p| p.on(idx + 1))?;
+                code.push(port);
+
+                // push register
+                let reg = register(line_tokens, idx + 2)?;
+                code.push(reg);
             }
 
             "halt" => {
-                bytecode.push(0xFF);
+                code.push(0xFF);
             }
 
-            _ => return Err(format!("ERROR: Unknown Operand: {}", instr)),
+            _ => {
+                let message = format!("unknown instruction `{}`", instr);
+                return Err(Problem::new(SYNTAX, message).on(idx));
+            }
         }
+
+        self.image
+            .place(self.pc, &code)
+            .map_err(|e| Problem::new(LAYOUT, e))?;
+        self.advance(code.len())
     }
+}
 
-    Ok(bytecode)
+// the assembled program, which starts at address 0 and grows as lines are
+// placed; bytes nothing was placed at hold the fill byte
+struct Image {
+    bytes: Vec<u8>,
+    used: Vec<bool>, // whether each byte was placed, to catch overlaps
+    fill: u8,
 }
 
-fn find_instr_length(mut instruction: String) -> Result<u16, String> {
-    instruction = instruction.to_lowercase();
-    match instruction.as_str() {
+impl Image {
+    fn place(&mut self, addr: u16, data: &[u8]) -> Result<(), String> {
+        let (start, end) = (addr as usize, addr as usize + data.len());
+        if end > 1 << 16 {
+            return Err("program does not fit in the address space".to_string());
+        }
+        if end > self.bytes.len() {
+            self.bytes.resize(end, self.fill);
+            self.used.resize(end, false);
+        }
+
+        if let Some(i) = self.used[start..end].iter().position(|u| *u) {
+            return Err(format!(
+                "{:#06X} is assembled twice, placed regions overlap",
+                start + i
+            ));
+        }
+        self.bytes[start..end].copy_from_slice(data);
+        self.used[start..end].fill(true);
+        Ok(())
+    }
+}
+
+// where .org and .align move the PC to; None for any other line. Words in
+// problems are numbered from the first operand.
+fn placement(
+    directive: &str,
+    args: &[String],
+    pc: u16,
+    symbols: &Symbols,
+) -> Result<Option<u16>, Problem> {
+    // the operand is the rest of the line
+    let operand = || -> Result<u16, Problem> {
+        match args {
+            [] => Err(Problem::new(
+                SYNTAX,
+                format!("{} expects 1 operand", directive),
+            )),
+            _ => unsigned(&args.join(" "), symbols),
+        }
+    };
+
+    match directive {
+        ".org" => Ok(Some(operand()?)),
+        ".align" => {
+            let n = operand()?;
+            if n == 0 {
+                return Err(Problem::new(OPERAND, ".align needs a nonzero alignment"));
+            }
+            pc.checked_next_multiple_of(n).map(Some).ok_or(Problem::new(
+                LAYOUT,
+                "program does not fit in the address space",
+            ))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn find_instr_length(instruction: String) -> Result<u16, String> {
+    match instruction.to_lowercase().as_str() {
         "movimm" => Ok(4),
         "mov" => Ok(3),
         "load" => Ok(4),
@@ -229,12 +956,134 @@ fn find_instr_length(mut instruction: String) -> Result<u16, String> {
         "jmp" => Ok(3),
         "jmpz" => Ok(3),
         "jmpnz" => Ok(3),
+        "rdcycle" => Ok(2),
+        "rdinstret" => Ok(2),
+        "in" => Ok(3),
+        "out" => Ok(3),
         "halt" => Ok(1),
-        _ => return Err(format!("ERROR: Unknown Operand: {}", instruction)),
+        _ => Err(format!("unknown instruction `{}`", instruction)),
+    }
+}
+
+// the bytes a data directive emits. Pass 1 only needs their number, and
+// labels may not be defined yet, so values stand in as 0 until pass 2;
+// counts must be known in both passes. Words in problems are numbered from
+// the first operand.
+fn data_bytes(directive: &str, args: &[String], symbols: &Symbols) -> Result<Vec<u8>, Problem> {
+    let value = |i: usize, bits: u32| -> Result<u16, Problem> {
+        let value = match (symbols.complete, bits) {
+            (false, _) => Ok(0),
+            (true, 8) => byte(&args[i], symbols).map(|b| b as u16),
+            (true, _) => word(&args[i], symbols),
+        };
+        value.map_err(|p| p.on(i))
+    };
+    let count = |i: usize| -> Result<usize, Problem> {
+        let Some(token) = args.get(i) else {
+            return Err(Problem::new(
+                SYNTAX,
+                format!("{} expects a count", directive),
+            ));
+        };
+        unsigned(token, symbols)
+            .map(|n| n as usize)
+            .map_err(|p| p.on(i))
+    };
+
+    let mut data: Vec<u8> = Vec::new();
+    match directive {
+        ".byte" | ".word" | ".ascii" | ".asciz" if args.is_empty() => {
+            return Err(Problem::new(
+                SYNTAX,
+                format!("{} expects at least 1 operand", directive),
+            ));
+        }
+        ".byte" => {
+            for i in 0..args.len() {
+                data.push(value(i, 8)? as u8);
+            }
+        }
+        ".word" => {
+            for i in 0..args.len() {
+                data.extend(value(i, 16)?.to_be_bytes());
+            }
+        }
+        ".ascii" | ".asciz" => {
+            for (i, token) in args.iter().enumerate() {
+                data.extend(parse_string(token).map_err(|e| Problem::new(SYNTAX, e).on(i))?);
+                if directive == ".asciz" {
+                    data.push(0);
+                }
+            }
+        }
+        ".fill" => {
+            if args.len() > 2 {
+                let message = ".fill expects a count and an optional byte";
+                return Err(Problem::new(SYNTAX, message).on(2));
+            }
+            let byte = match args.get(1) {
+                Some(token) => byte(token, symbols).map_err(|p| p.on(1))?,
+                None => 0,
+            };
+            data.resize(count(0)?, byte);
+        }
+        ".zero" => {
+            if args.len() > 1 {
+                return Err(Problem::new(SYNTAX, ".zero expects a count").on(1));
+            }
+            data.resize(count(0)?, 0);
+        }
+        _ => {
+            return Err(Problem::new(
+                SYNTAX,
+                format!("unknown directive {}", directive),
+            ));
+        }
+    }
+
+    Ok(data)
+}
+
+// a double-quoted string literal, with \n \t \r \0 \\ \" and \xHH escapes
+fn parse_string(token: &str) -> Result<Vec<u8>, String> {
+    let inner = token
+        .strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .ok_or(format!("expected a string, found `{}`", token))?;
+
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        let escaped = match chars.next() {
+            Some('n') => b'\n',
+            Some('t') => b'\t',
+            Some('r') => b'\r',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 2)
+                    .ok_or(format!("invalid escape \\x{} in {}", hex, token))?
+            }
+            Some(other) => return Err(format!("invalid escape \\{} in {}", other, token)),
+            None => return Err(format!("unterminated string {}", token)),
+        };
+        bytes.push(escaped);
     }
+
+    Ok(bytes)
 }
 
-fn parse_register(token: &str) -> Result<u8, String> {
+pub(crate) fn parse_register(token: &str) -> Result<u8, String> {
     if !token.starts_with('r') {
         return Err("Expected register (r0–r15)".into());
     }
@@ -250,34 +1099,762 @@ fn parse_register(token: &str) -> Result<u8, String> {
     Ok(num)
 }
 
-fn parse_u16(token: &str) -> Result<u16, String> {
-    if token.starts_with("0x") {
-        u16::from_str_radix(&token[2..], 16).map_err(|_| "Invalid hex number".into())
+pub(crate) fn parse_u16(token: &str) -> Result<u16, String> {
+    if let Some(hex) = token.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).map_err(|_| "Invalid hex number".into())
     } else {
         token.parse::<u16>().map_err(|_| "Invalid number".into())
     }
 }
 
-fn tokenize(contents: String) -> Vec<Vec<String>> {
-    let mut tokenized_lines: Vec<Vec<String>> = Vec::new();
-    for line in contents.lines() {
-        let line = line.trim();
-        if line.is_empty() {
+// labels and .equ/.set constants, the names operands can use
+#[derive(Default)]
+struct Symbols {
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    kinds: HashMap<String, &'static str>, // constant -> the directive defining it
+    sites: HashMap<String, String>,       // label -> where it is defined, e.g. `line 3`
+    complete: bool,                       // every label is known, in pass 2
+}
+
+impl Env for Symbols {
+    fn lookup(&self, name: &str) -> Option<i64> {
+        match self.constants.get(name) {
+            Some(value) => Some(*value),
+            None => self.labels.get(name).map(|addr| *addr as i64),
+        }
+    }
+
+    fn read_memory(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+}
+
+impl Symbols {
+    // whether name is a label or constant defined so far
+    fn defined(&self, name: &str) -> bool {
+        self.labels.contains_key(name) || self.kinds.contains_key(name)
+    }
+
+    // site is where the label is, for the error if it is defined again
+    fn define_label(&mut self, label: String, addr: u16, site: String) -> Result<(), String> {
+        if self.kinds.contains_key(&label) {
+            return Err(format!("`{}` is already defined as a constant", label));
+        }
+        if let Some(first) = self.sites.get(&label) {
+            return Err(format!("`{}` is already defined at {}", label, first));
+        }
+        self.labels.insert(label.clone(), addr);
+        self.sites.insert(label, site);
+        Ok(())
+    }
+
+    // handles `.equ NAME value` and `.set NAME value`, returning false for
+    // any other line. .equ defines a name once, .set may redefine it. Pass 1
+    // skips values that use labels defined further on; pass 2 evaluates
+    // every definition again in order. Words in problems are numbered from
+    // the name.
+    fn define_constant(&mut self, directive: &str, args: &[String]) -> Result<bool, Problem> {
+        let kind = match directive {
+            ".equ" => ".equ",
+            ".set" => ".set",
+            _ => return Ok(false),
+        };
+        // the value is the rest of the line
+        let expects = || Problem::new(SYNTAX, format!("{} expects a name and a value", kind));
+        let [name, value @ ..] = args else {
+            return Err(expects());
+        };
+        if value.is_empty() {
+            return Err(expects());
+        }
+        let value = value.join(" ");
+
+        if !self.complete {
+            if !is_name(name) {
+                let message = format!("invalid constant name `{}`", name);
+                return Err(Problem::new(SYNTAX, message).on(0));
+            }
+            if self.labels.contains_key(name) {
+                let message = format!("`{}` is already defined as a label", name);
+                return Err(Problem::new(DUPLICATE, message).on(0));
+            }
+            match self.kinds.get(name.as_str()) {
+                Some(previous) if kind == ".equ" || *previous == ".equ" => {
+                    let message = format!("`{}` is already defined", name);
+                    return Err(Problem::new(DUPLICATE, message).on(0));
+                }
+                _ => self.kinds.insert(name.clone(), kind),
+            };
+        }
+
+        match operand(&value, self) {
+            Ok(value) => self.constants.insert(name.clone(), value),
+            Err(problem) if self.complete => return Err(problem),
+            Err(_) => self.constants.remove(name),
+        };
+        Ok(true)
+    }
+}
+
+// an operand expression, such as 0x10, table+4, (end - start) / 2 or hi(msg)
+fn operand(token: &str, symbols: &Symbols) -> Result<i64, Problem> {
+    let expr = Expr::parse(token)
+        .map_err(|e| Problem::new(SYNTAX, format!("invalid operand `{}`: {}", token, e)))?;
+    if expr.reads_memory() {
+        return Err(Problem::new(
+            OPERAND,
+            format!("`{}` reads memory, which only exists at run time", token),
+        ));
+    }
+    expr.check_names(symbols)
+        .map_err(|e| Problem::new(UNDEFINED, e))?;
+    expr.eval_checked(symbols)
+        .map_err(|e| Problem::new(OPERAND, format!("`{}`: {}", token, e)))
+}
+
+// an operand that must fit in range, e.g. "16 bits"
+fn bounded(
+    token: &str,
+    symbols: &Symbols,
+    range: RangeInclusive<i64>,
+    fit: &str,
+) -> Result<i64, Problem> {
+    let value = operand(token, symbols)?;
+    if !range.contains(&value) {
+        return Err(Problem::new(
+            OPERAND,
+            format!("`{}` is {}, which does not fit in {}", token, value, fit),
+        ));
+    }
+    Ok(value)
+}
+
+// a 16-bit value; negative values are stored in two's complement
+fn word(token: &str, symbols: &Symbols) -> Result<u16, Problem> {
+    bounded(token, symbols, -0x8000..=0xFFFF, "16 bits").map(|v| v as u16)
+}
+
+fn byte(token: &str, symbols: &Symbols) -> Result<u8, Problem> {
+    bounded(token, symbols, -0x80..=0xFF, "8 bits").map(|v| v as u8)
+}
+
+// an address or count
+fn unsigned(token: &str, symbols: &Symbols) -> Result<u16, Problem> {
+    bounded(token, symbols, 0..=0xFFFF, "16 bits unsigned").map(|v| v as u16)
+}
+
+fn port(token: &str, symbols: &Symbols) -> Result<u8, Problem> {
+    bounded(token, symbols, 0..=0xFF, "a port number (0-255)").map(|v| v as u8)
+}
+
+// word i of tokens as a register
+fn register(tokens: &[String], i: usize) -> Result<u8, Problem> {
+    parse_register(&tokens[i]).map_err(|e| Problem::new(OPERAND, e).on(i))
+}
+
+// one source line with code on it
+#[derive(Clone)]
+struct Line {
+    number: usize,                      // 1-based; in a macro body, the definition's line
+    text: String,                       // the line up to any comment
+    tokens: Vec<String>,                // its words
+    offsets: Vec<usize>,                // byte offset of each word in text
+    file: usize,                        // index into the list of files read
+    calls: Vec<(String, usize, usize)>, // macros expanded from, innermost first, and call file and line
+    binary: Option<Arc<Vec<u8>>>,       // the contents of an .incbin file
+}
+
+// splits the source into words, keeping where each one was; a string
+// literal is one word, quotes included
+fn tokenize(file: usize, contents: &str) -> Vec<Line> {
+    contents
+        .lines()
+        .enumerate()
+        .filter_map(|(number, line)| tokenize_line(file, number + 1, line))
+        .collect()
+}
+
+// None for a line with nothing but a comment
+fn tokenize_line(file: usize, number: usize, line: &str) -> Option<Line> {
+    let mut text = line;
+    let mut tokens: Vec<String> = Vec::new();
+    let mut offsets: Vec<usize> = Vec::new();
+
+    // words end at whitespace or commas, except inside a string or
+    // parentheses, so `(end - start) / 2` is one operand
+    let mut start: Option<usize> = None;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut depth = 0;
+    let ends = line.char_indices().chain([(line.len(), ' ')]);
+    for (i, c) in ends {
+        if quoted && i < line.len() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {}
+            }
             continue;
         }
 
-        let mut current_line: Vec<String> = Vec::new();
-        for word in line.split_whitespace() {
-            if word.starts_with("//") {
-                break;
+        let separator = i == line.len() || (depth == 0 && (c.is_whitespace() || c == ','));
+        match (separator, start) {
+            (false, _) => {
+                start = start.or(Some(i));
+                quoted = c == '"';
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth > 0 => depth -= 1,
+                    _ => {}
+                }
+            }
+            (true, Some(s)) => {
+                start = None;
+                if line[s..i].starts_with("//") {
+                    text = &line[..s];
+                    break;
+                }
+                tokens.push(line[s..i].to_string());
+                offsets.push(s);
+            }
+            _ => {}
+        }
+    }
+
+    if tokens.is_empty() {
+        return None;
+    }
+    Some(Line {
+        number,
+        text: text.to_string(),
+        tokens,
+        offsets,
+        file,
+        calls: Vec::new(),
+        binary: None,
+    })
+}
+
+impl Line {
+    // the line's label without the rest of it
+    fn label_only(&self) -> Line {
+        Line {
+            text: self.tokens[0].clone(),
+            tokens: vec![self.tokens[0].clone()],
+            offsets: vec![0],
+            binary: None,
+            ..self.clone()
+        }
+    }
+}
+
+// the contents of an .incbin line, read when the source was loaded
+fn included(line: &Line) -> Result<&[u8], Problem> {
+    line.binary
+        .as_deref()
+        .map(|b| b.as_slice())
+        .ok_or(Problem::new(SYNTAX, ".incbin expects a file name"))
+}
+
+// reads the source and runs pass 1 over it a line at a time. Conditional
+// blocks are evaluated first, so a line in a skipped block is never
+// included, defined as a macro or expanded; .include splices its file in
+// where it stands, macro calls their bodies, and every other line is sized
+// and kept for pass 2.
+struct Reader<'a> {
+    loader: Loader<'a>,
+    expander: Expander,
+    conditions: Conditions,
+    assembler: Assembler,
+    kept: Vec<(Line, u16)>,  // lines for pass 2, each with the PC after it
+    errors: Vec<AsmError>,   // lines that could not be loaded or expanded
+    problems: Vec<AsmError>, // pass 1 errors
+}
+
+impl<'a> Reader<'a> {
+    fn new(config: &'a Config, disk: bool) -> Self {
+        let mut symbols = Symbols::default();
+        let mut errors: Vec<AsmError> = Vec::new();
+        for (name, value) in config.defines.iter() {
+            if !is_name(name) {
+                let message = format!("invalid constant name `{}`", name);
+                errors.push(AsmError::unlocated("", OPERAND, message));
+            }
+            symbols.constants.insert(name.clone(), *value);
+            symbols.kinds.insert(name.clone(), "-D");
+        }
+
+        Self {
+            loader: Loader {
+                config,
+                files: Vec::new(),
+                stack: Vec::new(),
+                disk,
+            },
+            expander: Expander {
+                macros: HashMap::new(),
+                expansions: 0,
+            },
+            conditions: Conditions::default(),
+            assembler: Assembler {
+                symbols,
+                image: Image {
+                    bytes: Vec::new(),
+                    used: Vec::new(),
+                    fill: config.fill,
+                },
+                pc: 0,
+                lines: BTreeMap::new(),
+            },
+            kept: Vec::new(),
+            errors,
+            problems: Vec::new(),
+        }
+    }
+
+    // the lines of src, named name. None once macros nest too deeply, as
+    // going on would repeat that error for every level
+    fn read(&mut self, name: &str, src: &str) -> Option<()> {
+        let file = self.loader.files.len();
+        self.loader.files.push(name.to_string());
+        self.feed(tokenize(file, src), 0)
+    }
+
+    // lines of a file, or of a macro expansion depth calls deep
+    fn feed(&mut self, lines: Vec<Line>, depth: usize) -> Option<()> {
+        let mut lines = lines.into_iter();
+
+        while let Some(line) = lines.next() {
+            match self.conditions.directive(&line, &self.assembler.symbols) {
+                Ok(false) if self.conditions.active() => {}
+                Ok(_) => continue,
+                Err(problem) => {
+                    self.problem(&line, problem);
+                    continue;
+                }
+            }
+
+            let idx = line.tokens[0].ends_with(':') as usize;
+            let word = line.tokens.get(idx).map(|w| w.to_lowercase());
+            match word.as_deref() {
+                Some(".include") => self.include(line, idx)?,
+                Some(".incbin") => self.incbin(line, idx),
+                Some(".macro") => {
+                    if let Err(problem) = self.expander.define(&line, idx, &mut lines) {
+                        self.error(&line, problem);
+                    }
+                }
+                Some(".endm") => {
+                    self.error(&line, Problem::new(MACRO, ".endm without .macro").on(idx));
+                }
+                Some(name) if self.expander.macros.contains_key(name) => {
+                    self.call(line, idx, name, depth)?
+                }
+                _ => self.size(line),
+            }
+        }
+
+        Some(())
+    }
+
+    // splices in the file an .include line names; a label on the line
+    // stays behind on a line of its own
+    fn include(&mut self, line: Line, idx: usize) -> Option<()> {
+        if idx == 1 {
+            self.size(line.label_only());
+        }
+
+        let opened =
+            self.loader
+                .resolve(&line, idx)
+                .and_then(|path| match self.loader.open(&path) {
+                    Ok(src) => Ok((path, src)),
+                    Err(e) => Err(Problem::new(INCLUDE, e).on(idx + 1)),
+                });
+        match opened {
+            Ok((path, src)) => {
+                self.read(&path.display().to_string(), &src)?;
+                self.loader.stack.pop();
+            }
+            Err(problem) => self.error(&line, problem),
+        }
+        Some(())
+    }
+
+    // reads the data for an .incbin line and sizes it
+    fn incbin(&mut self, mut line: Line, idx: usize) {
+        let path = match self.loader.resolve(&line, idx) {
+            Ok(path) => path,
+            Err(problem) => return self.error(&line, problem),
+        };
+
+        match fs::read(&path) {
+            Ok(bytes) => {
+                line.binary = Some(Arc::new(bytes));
+                self.size(line);
+            }
+            Err(e) => {
+                let message = format!("cannot read {}: {}", path.display(), e);
+                self.error(&line, Problem::new(INCLUDE, message).on(idx + 1));
+            }
+        }
+    }
+
+    // expands a call to macro name; the call's label stays behind on a
+    // line of its own
+    fn call(&mut self, line: Line, idx: usize, name: &str, depth: usize) -> Option<()> {
+        if depth == MAX_MACRO_DEPTH {
+            let message = format!(
+                "macros nested more than {} deep, is `{}` recursive?",
+                MAX_MACRO_DEPTH, name
+            );
+            self.error(&line, Problem::new(MACRO, message));
+            return None;
+        }
+
+        if idx == 1 {
+            self.size(line.label_only());
+        }
+
+        let values = match self.expander.bind(name, &line, idx) {
+            Ok(values) => values,
+            Err(problem) => {
+                self.error(&line, problem);
+                return Some(());
             }
-            current_line.push(word.to_string());
+        };
+        let body =
+            self.expander
+                .instantiate(name, &line, &values, &self.loader.files, &mut self.errors);
+        self.feed(body, depth + 1)
+    }
+
+    // pass 1 for one line
+    fn size(&mut self, line: Line) {
+        match self.assembler.size(&line, &self.loader.files) {
+            Ok(()) => self.kept.push((line, self.assembler.pc)),
+            Err(problem) => self.problem(&line, problem),
+        }
+    }
+
+    // a line that could not be loaded or expanded
+    fn error(&mut self, line: &Line, problem: Problem) {
+        self.errors.push(located(&self.loader.files, line, problem));
+    }
+
+    // a line pass 1 found wrong
+    fn problem(&mut self, line: &Line, problem: Problem) {
+        self.problems
+            .push(located(&self.loader.files, line, problem));
+    }
+}
+
+// the files a program is read from
+struct Loader<'a> {
+    config: &'a Config,
+    files: Vec<String>,  // every source read, indexed by Line::file
+    stack: Vec<PathBuf>, // files being read, outermost first, to catch cycles
+    disk: bool,          // whether there is a file system to read from
+}
+
+impl Loader<'_> {
+    // the file named by an .include or .incbin line: relative to the file
+    // the line is in, or else to the first include path that has it
+    fn resolve(&self, line: &Line, idx: usize) -> Result<PathBuf, Problem> {
+        let directive = line.tokens[idx].to_lowercase();
+        let [token] = &line.tokens[idx + 1..] else {
+            let message = format!("{} expects a file name", directive);
+            return Err(Problem::new(SYNTAX, message));
+        };
+        let name = parse_string(token)
+            .and_then(|name| String::from_utf8(name).map_err(|_| String::new()))
+            .map_err(|_| Problem::new(SYNTAX, format!("invalid file name {}", token)))
+            .map_err(|p| p.on(idx + 1))?;
+
+        if !self.disk {
+            let message = format!("{} needs files, assemble with assemble_file", directive);
+            return Err(Problem::new(INCLUDE, message).on(idx));
         }
 
-        if !current_line.is_empty() {
-            tokenized_lines.push(current_line);
+        let dir = self
+            .files
+            .get(line.file)
+            .and_then(|f| Path::new(f).parent());
+        dir.into_iter()
+            .chain(self.config.include_paths.iter().map(|p| p.as_path()))
+            .map(|dir| dir.join(&name))
+            .find(|path| path.is_file())
+            .ok_or(Problem::new(INCLUDE, format!("cannot find `{}`", name)).on(idx + 1))
+    }
+
+    // reads a source file and marks it as being read, failing if it is
+    // already; the caller pops it once done
+    fn open(&mut self, path: &Path) -> Result<String, String> {
+        let canonical = path
+            .canonicalize()
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+
+        if let Some(start) = self.stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain([&canonical])
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(format!("include cycle: {}", cycle.join(" -> ")));
         }
+
+        let src = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        self.stack.push(canonical);
+        Ok(src)
+    }
+}
+
+// how deeply macros may call each other before expansion gives up
+const MAX_MACRO_DEPTH: usize = 32;
+
+struct Macro {
+    name: String,
+    params: Vec<(String, Option<String>)>, // name and default value
+    body: Vec<Line>,
+}
+
+// macro definitions, for Reader to expand calls with. In a body, \name
+// stands for the argument of parameter name, and labels defined there are
+// local: each expansion gets its own copy. A definition or call in error is
+// recorded and left out.
+struct Expander {
+    macros: HashMap<String, Macro>,
+    expansions: usize, // numbers each expansion's local labels
+}
+
+impl Expander {
+    // reads a definition, up to the matching .endm, from lines; the body
+    // is read even if the .macro line is in error, so it is not taken for
+    // code
+    fn define<I: Iterator<Item = Line>>(
+        &mut self,
+        line: &Line,
+        idx: usize,
+        lines: &mut I,
+    ) -> Result<(), Problem> {
+        // definitions inside the body are kept for when it is expanded
+        let mut body: Vec<Line> = Vec::new();
+        let mut nested = 0;
+        let mut closed = false;
+        for inner in lines.by_ref() {
+            let idx = inner.tokens[0].ends_with(':') as usize;
+            match inner.tokens.get(idx).map(|w| w.to_lowercase()).as_deref() {
+                Some(".macro") => nested += 1,
+                Some(".endm") if nested == 0 => {
+                    closed = true;
+                    break;
+                }
+                Some(".endm") => nested -= 1,
+                _ => {}
+            }
+            body.push(inner);
+        }
+
+        if idx == 1 {
+            return Err(Problem::new(SYNTAX, "a .macro line cannot have a label").on(0));
+        }
+        let Some(name) = line.tokens.get(1).map(|n| n.to_lowercase()) else {
+            return Err(Problem::new(SYNTAX, ".macro expects a name"));
+        };
+        if !is_name(&name) || find_instr_length(name.clone()).is_ok() {
+            let message = format!("`{}` cannot be a macro name", name);
+            return Err(Problem::new(MACRO, message).on(1));
+        }
+        if !closed {
+            return Err(Problem::new(MACRO, format!("macro `{}` has no .endm", name)).on(1));
+        }
+
+        let mut params: Vec<(String, Option<String>)> = Vec::new();
+        for (i, param) in line.tokens.iter().enumerate().skip(2) {
+            let (param, default) = match param.split_once('=') {
+                Some((param, default)) => (param, Some(default.to_string())),
+                None => (param.as_str(), None),
+            };
+            if !is_name(param) || params.iter().any(|(p, _)| p == param) {
+                let message = format!("invalid or repeated parameter `{}`", param);
+                return Err(Problem::new(MACRO, message).on(i));
+            }
+            params.push((param.to_string(), default));
+        }
+
+        let definition = Macro {
+            name: name.clone(),
+            params,
+            body,
+        };
+        self.macros.insert(name, definition);
+        Ok(())
+    }
+
+    // each parameter's value for call, whose arguments are positional or
+    // name=value
+    fn bind(
+        &self,
+        name: &str,
+        call: &Line,
+        idx: usize,
+    ) -> Result<HashMap<String, String>, Problem> {
+        let definition = &self.macros[name];
+
+        let mut args: Vec<Option<String>> = vec![None; definition.params.len()];
+        let mut next = 0;
+        for (j, arg) in call.tokens.iter().enumerate().skip(idx + 1) {
+            let named = arg.split_once('=').and_then(|(param, value)| {
+                let i = definition.params.iter().position(|(p, _)| p == param)?;
+                Some((i, value))
+            });
+            let (i, value) = match named {
+                Some(named) => named,
+                None => {
+                    next += 1;
+                    (next - 1, arg.as_str())
+                }
+            };
+
+            match args.get_mut(i) {
+                None => {
+                    let message = format!(
+                        "macro `{}` takes {} arguments",
+                        name,
+                        definition.params.len()
+                    );
+                    return Err(Problem::new(MACRO, message).on(j));
+                }
+                Some(Some(_)) => {
+                    let message = format!("argument `{}` given twice", definition.params[i].0);
+                    return Err(Problem::new(MACRO, message).on(j));
+                }
+                Some(slot) => *slot = Some(value.to_string()),
+            }
+        }
+
+        let mut values: HashMap<String, String> = HashMap::new();
+        for ((param, default), arg) in definition.params.iter().zip(args) {
+            let value = arg.or(default.clone()).ok_or(Problem::new(
+                MACRO,
+                format!("macro `{}` needs an argument for `{}`", name, param),
+            ))?;
+            values.insert(param.clone(), value);
+        }
+        Ok(values)
+    }
+
+    // the body of macro name with values filled in, each line remembering
+    // the call it came from; a line using an unknown parameter is recorded
+    // in errors, located through files, and left out
+    fn instantiate(
+        &mut self,
+        name: &str,
+        call: &Line,
+        values: &HashMap<String, String>,
+        files: &[String],
+        errors: &mut Vec<AsmError>,
+    ) -> Vec<Line> {
+        let definition = &self.macros[name];
+
+        self.expansions += 1;
+        let locals: HashMap<String, String> = definition
+            .body
+            .iter()
+            .filter_map(|l| l.tokens[0].strip_suffix(':'))
+            .filter(|label| is_name(label))
+            .map(|label| {
+                let local = format!("__{}_{}", label, self.expansions);
+                (label.to_string(), local)
+            })
+            .collect();
+
+        let mut calls = vec![(definition.name.clone(), call.file, call.number)];
+        calls.extend(call.calls.iter().cloned());
+
+        let mut body: Vec<Line> = Vec::new();
+        for line in definition.body.iter() {
+            let line = Line {
+                calls: calls.clone(),
+                ..line.clone()
+            };
+            let text = match substitute(&line.text, values, &locals) {
+                Ok(text) => text,
+                Err(e) => {
+                    errors.push(located(files, &line, Problem::new(MACRO, e)));
+                    continue;
+                }
+            };
+            if let Some(mut expanded) = tokenize_line(line.file, line.number, &text) {
+                expanded.calls = line.calls;
+                expanded.binary = line.binary;
+                body.push(expanded);
+            }
+        }
+        body
+    }
+}
+
+// whether word can name a macro, parameter or constant
+fn is_name(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// text with \param replaced by its argument and local labels renamed,
+// leaving string literals alone
+fn substitute(
+    text: &str,
+    values: &HashMap<String, String>,
+    locals: &HashMap<String, String>,
+) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let word_len = |s: &str| {
+            s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(s.len())
+        };
+
+        let len = match c {
+            '"' => {
+                // up to the closing quote, skipping escaped ones
+                let mut escaped = false;
+                let end = rest[1..].find(|c: char| {
+                    let close = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    close
+                });
+                let len = end.map_or(rest.len(), |e| e + 2);
+                out += &rest[..len];
+                len
+            }
+            '\\' => {
+                let len = word_len(&rest[1..]);
+                let param = &rest[1..1 + len];
+                let value = values
+                    .get(param)
+                    .ok_or(format!("unknown macro parameter `\\{}`", param))?;
+                out += value;
+                len + 1
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let len = word_len(rest);
+                let word = &rest[..len];
+                out += locals.get(word).map_or(word, |local| local.as_str());
+                len
+            }
+            c => {
+                out.push(c);
+                c.len_utf8()
+            }
+        };
+        rest = &rest[len..];
     }
 
-    return tokenized_lines;
+    Ok(out)
 }
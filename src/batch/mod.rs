@@ -0,0 +1,101 @@
+// src/batch/mod.rs
+//
+// Runs many independent programs across a pool of worker threads. Every job
+// gets a fresh VM, its own console input and a step budget, so a runaway
+// program only costs its own budget.
+
+use crate::cost::CostModel;
+use crate::vm::{ExitReason, VM};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+#[derive(Clone)]
+pub struct JobConfig {
+    pub cost_model: CostModel,
+    pub max_steps: u64,
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        Self {
+            cost_model: CostModel::new(),
+            max_steps: 1_000_000,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Job {
+    pub name: String,
+    pub program: Vec<u8>,
+    pub config: JobConfig,
+    pub input: Vec<u8>,
+}
+
+pub struct JobResult {
+    pub name: String,
+    pub exit: ExitReason,
+    pub registers: [u16; 16],
+    pub pc: u16,
+    pub zero_flag: bool,
+    pub carry_flag: bool,
+    pub memory_digest: u64,
+    pub output: Vec<u8>,
+    pub instret: u64,
+    pub cycles: u64,
+}
+
+pub fn run_job(job: &Job) -> JobResult {
+    let mut vm = VM::new();
+    vm.cost_model = job.config.cost_model.clone();
+    vm.devices.console.input = job.input.iter().copied().collect();
+
+    let exit = match vm.load(&job.program) {
+        Ok(()) => vm.run_for(job.config.max_steps),
+        Err(e) => ExitReason::Faulted(e),
+    };
+
+    JobResult {
+        name: job.name.clone(),
+        exit,
+        registers: vm.cpu.registers,
+        pc: vm.cpu.pc,
+        zero_flag: vm.zero_flag,
+        carry_flag: vm.carry_flag,
+        memory_digest: vm.memory.digest(),
+        output: vm.devices.console.output,
+        instret: vm.instret,
+        cycles: vm.cycles,
+    }
+}
+
+// runs every job on up to `workers` threads, results come back in job order
+pub fn run_batch(jobs: &[Job], workers: usize) -> Vec<JobResult> {
+    let workers = workers.clamp(1, jobs.len().max(1));
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<JobResult>>> = Mutex::new(jobs.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= jobs.len() {
+                        break;
+                    }
+
+                    let result = run_job(&jobs[i]);
+                    results.lock().unwrap()[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every job produces a result"))
+        .collect()
+}
@@ -0,0 +1,110 @@
+// src/breakpoints/mod.rs
+//
+// Breakpoints by address, each with an optional condition and a count of how
+// often execution has reached it. run, run_for, run_until and step_for stop
+// at a breakpoint when it has no condition or its condition holds; the
+// condition sees the VM's names plus hits, which counts this arrival. A
+// condition that fails to evaluate stops too, with the error kept on the
+// breakpoint.
+
+use crate::expr::{Bindings, Condition, Env};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub condition: Option<Condition>,
+    pub hits: u64,             // times execution has reached addr
+    pub error: Option<String>, // why the condition last failed to evaluate
+}
+
+#[derive(Clone, Default)]
+pub struct Breakpoints {
+    points: BTreeMap<u16, Breakpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds an unconditional breakpoint, returns false if addr already had one
+    pub fn insert(&mut self, addr: u16) -> bool {
+        if self.points.contains_key(&addr) {
+            return false;
+        }
+
+        self.set(addr, None);
+        true
+    }
+
+    // adds a breakpoint or replaces the one at addr, resetting its hit count
+    pub fn set(&mut self, addr: u16, condition: Option<Condition>) {
+        self.points.insert(
+            addr,
+            Breakpoint {
+                addr,
+                condition,
+                hits: 0,
+                error: None,
+            },
+        );
+    }
+
+    pub fn remove(&mut self, addr: &u16) -> bool {
+        self.points.remove(addr).is_some()
+    }
+
+    pub fn contains(&self, addr: &u16) -> bool {
+        self.points.contains_key(addr)
+    }
+
+    pub fn get(&self, addr: u16) -> Option<&Breakpoint> {
+        self.points.get(&addr)
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    // breakpoint addresses in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = &u16> {
+        self.points.keys()
+    }
+
+    pub fn list(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.points.values()
+    }
+
+    // counts an arrival at addr, true if execution should stop there
+    pub(crate) fn check(&mut self, addr: u16, env: &dyn Env) -> bool {
+        let Some(point) = self.points.get_mut(&addr) else {
+            return false;
+        };
+
+        point.hits += 1;
+        let Some(condition) = &point.condition else {
+            return true;
+        };
+
+        let names = [("hits", point.hits as i64)];
+        match condition.holds(&Bindings { env, names: &names }) {
+            Ok(stop) => {
+                point.error = None;
+                stop
+            }
+            Err(e) => {
+                point.error = Some(e);
+                true
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// src/cost/mod.rs
+//
+// Simulated timing for the VM. Every retired instruction costs the base
+// cycles for its opcode, plus a penalty for each memory word it touches and
+// another if it is a branch that was taken.
+
+#[derive(Clone)]
+pub struct CostModel {
+    pub opcode: [u64; 256], // base cycles, indexed by opcode
+    pub memory_access: u64, // extra cycles per load or store
+    pub taken_branch: u64,  // extra cycles when a jump changes the PC
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CostModel {
+    pub fn new() -> Self {
+        Self {
+            opcode: [1; 256],
+            memory_access: 2,
+            taken_branch: 1,
+        }
+    }
+
+    // saturates, so a table with huge penalties pins the count instead of
+    // wrapping it
+    pub fn cost(&self, opcode: u8, memory_accesses: u64, branch_taken: bool) -> u64 {
+        let mut cycles = self.opcode[opcode as usize]
+            .saturating_add(memory_accesses.saturating_mul(self.memory_access));
+        if branch_taken {
+            cycles = cycles.saturating_add(self.taken_branch);
+        }
+        cycles
+    }
+
+    // reads a cost table, one `name cycles` pair per line, on top of the
+    // defaults. names are mnemonics or the keywords `memory` and `branch`
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut model = Self::new();
+
+        for (i, line) in src.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() != 2 {
+                return Err(format!("line {}: expected `name cycles`", i + 1));
+            }
+
+            let cycles: u64 = words[1]
+                .parse()
+                .map_err(|_| format!("line {}: invalid cycle count", i + 1))?;
+
+            match words[0].to_lowercase().as_str() {
+                "memory" => model.memory_access = cycles,
+                "branch" => model.taken_branch = cycles,
+                name => {
+                    let opcode = opcode_for(name).ok_or(format!(
+                        "line {}: unknown instruction {}",
+                        i + 1,
+                        name
+                    ))?;
+                    model.opcode[opcode as usize] = cycles;
+                }
+            }
+        }
+
+        Ok(model)
+    }
+}
+
+fn opcode_for(mnemonic: &str) -> Option<u8> {
+    match mnemonic {
+        "movimm" => Some(0x01),
+        "mov" => Some(0x02),
+        "load" => Some(0x03),
+        "store" => Some(0x04),
+        "add" => Some(0x05),
+        "sub" => Some(0x06),
+        "cmp" => Some(0x07),
+        "jmp" => Some(0x08),
+        "jmpz" => Some(0x09),
+        "jmpnz" => Some(0x0A),
+        "rdcycle" => Some(0x0B),
+        "rdinstret" => Some(0x0C),
+        "in" => Some(0x0D),
+        "out" => Some(0x0E),
+        "halt" => Some(0xFF),
+        _ => None,
+    }
+}
@@ -0,0 +1,305 @@
+// src/coverage/mod.rs
+//
+// Code coverage. Coverage is an observer that counts how often each address
+// executes and, for every jmpz and jmpnz, how often the branch was taken and
+// not taken. Through the assembler's debug info the counts become per-line
+// SourceCoverage, which can be written as LCOV tracefiles or as an annotated
+// listing:
+//
+//                loop:
+//         10      sub r0 r1
+//         10      jmpnz loop               [taken 9, not taken 1]
+//      #####      store 0x0200 r0
+//
+// A line shows its count, ##### if it has code that never ran, or nothing if
+// it has no code. Coverage from several runs merges either as Coverage, for
+// the same program, or as Lcov, for tracefiles naming any number of sources.
+
+use crate::assembler::DebugInfo;
+use crate::decoder::decode;
+use crate::instructions::Instruction;
+use crate::observer::Observer;
+use crate::vm::VM;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Coverage {
+    pub executed: BTreeMap<u16, u64>,    // address, times retired
+    pub branches: BTreeMap<u16, Branch>, // conditional jumps by address
+    conditional: bool,                   // the last instruction was jmpz or jmpnz
+}
+
+impl Observer for Coverage {
+    fn on_execute_after(&mut self, _vm: &VM, pc: u16, instr: &Instruction) {
+        *self.executed.entry(pc).or_default() += 1;
+        self.conditional = matches!(
+            instr,
+            Instruction::JumpZ { .. } | Instruction::JumpNZ { .. }
+        );
+    }
+
+    fn on_branch(&mut self, _vm: &VM, pc: u16, _target: u16, taken: bool) {
+        if !self.conditional {
+            return;
+        }
+
+        let branch = self.branches.entry(pc).or_default();
+        match taken {
+            true => branch.taken += 1,
+            false => branch.not_taken += 1,
+        }
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // adds another run of the same program
+    pub fn merge(&mut self, other: &Coverage) {
+        for (addr, count) in other.executed.iter() {
+            *self.executed.entry(*addr).or_default() += count;
+        }
+        for (addr, branch) in other.branches.iter() {
+            let ours = self.branches.entry(*addr).or_default();
+            ours.taken += branch.taken;
+            ours.not_taken += branch.not_taken;
+        }
+    }
+
+    // per-line counts of the main source file; program is the assembled
+    // bytecode, used to find the conditional jumps that never ran
+    pub fn source(&self, debug: &DebugInfo, program: &[u8]) -> SourceCoverage {
+        self.file(debug, program, 0)
+    }
+
+    // as source, for the file at index file of debug.files
+    pub fn file(&self, debug: &DebugInfo, program: &[u8], file: usize) -> SourceCoverage {
+        let mut source = SourceCoverage::default();
+
+        for (addr, location) in debug.lines.iter() {
+            if location.file != file {
+                continue;
+            }
+            let line = &location.line;
+            let count = self.executed.get(addr).copied().unwrap_or(0);
+            *source.lines.entry(*line).or_default() += count;
+
+            let conditional = matches!(
+                decode(program, *addr).map(|d| d.instr),
+                Ok(Instruction::JumpZ { .. } | Instruction::JumpNZ { .. })
+            );
+            if conditional {
+                let branch = self.branches.get(addr).copied().unwrap_or_default();
+                let ours = source.branches.entry(*line).or_default();
+                ours.taken += branch.taken;
+                ours.not_taken += branch.not_taken;
+            }
+        }
+
+        source
+    }
+}
+
+// coverage of one source file by line number
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceCoverage {
+    pub lines: BTreeMap<usize, u64>,       // every line with code
+    pub branches: BTreeMap<usize, Branch>, // every line with a conditional jump
+}
+
+impl SourceCoverage {
+    pub fn merge(&mut self, other: &SourceCoverage) {
+        for (line, count) in other.lines.iter() {
+            *self.lines.entry(*line).or_default() += count;
+        }
+        for (line, branch) in other.branches.iter() {
+            let ours = self.branches.entry(*line).or_default();
+            ours.taken += branch.taken;
+            ours.not_taken += branch.not_taken;
+        }
+    }
+
+    // lines with code, and how many of them ran
+    pub fn line_totals(&self) -> (usize, usize) {
+        let hit = self.lines.values().filter(|c| **c > 0).count();
+        (self.lines.len(), hit)
+    }
+
+    // branch directions, two per conditional jump, and how many were taken
+    pub fn branch_totals(&self) -> (usize, usize) {
+        let hit = self
+            .branches
+            .values()
+            .map(|b| (b.taken > 0) as usize + (b.not_taken > 0) as usize)
+            .sum();
+        (self.branches.len() * 2, hit)
+    }
+
+    // src with each line prefixed by its count
+    pub fn annotate(&self, src: &str) -> String {
+        let mut out = String::new();
+
+        for (i, text) in src.lines().enumerate() {
+            let line = i + 1;
+            let count = match self.lines.get(&line) {
+                Some(0) => "#####".to_string(),
+                Some(n) => n.to_string(),
+                None => String::new(),
+            };
+
+            let mut text = format!("{:>9}  {}", count, text);
+            if let Some(branch) = self.branches.get(&line) {
+                text = format!(
+                    "{:<40}[taken {}, not taken {}]",
+                    text, branch.taken, branch.not_taken
+                );
+            }
+            writeln!(out, "{}", text.trim_end()).unwrap();
+        }
+
+        let (lines, lines_hit) = self.line_totals();
+        let (branches, branches_hit) = self.branch_totals();
+        writeln!(
+            out,
+            "\nLines: {}/{} ({}), branches: {}/{} ({})",
+            lines_hit,
+            lines,
+            percent(lines_hit, lines),
+            branches_hit,
+            branches,
+            percent(branches_hit, branches)
+        )
+        .unwrap();
+        out
+    }
+}
+
+fn percent(hit: usize, total: usize) -> String {
+    match total {
+        0 => "-".to_string(),
+        total => format!("{:.1}%", hit as f64 * 100.0 / total as f64),
+    }
+}
+
+// an LCOV tracefile: coverage by source path
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lcov {
+    pub sources: BTreeMap<String, SourceCoverage>,
+}
+
+impl Lcov {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, path: &str, coverage: &SourceCoverage) {
+        self.sources
+            .entry(path.to_string())
+            .or_default()
+            .merge(coverage);
+    }
+
+    pub fn merge(&mut self, other: &Lcov) {
+        for (path, coverage) in other.sources.iter() {
+            self.add(path, coverage);
+        }
+    }
+
+    // reads the SF, DA, BRDA and end_of_record lines of a tracefile; other
+    // records are recomputed on output and ignored here
+    pub fn parse(text: &str) -> Result<Lcov, String> {
+        let mut lcov = Lcov::new();
+        let mut current: Option<(String, SourceCoverage)> = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let bad = || format!("line {}: invalid record `{}`", i + 1, line);
+
+            let (tag, value) = line.split_once(':').unwrap_or((line, ""));
+            match tag {
+                "SF" => current = Some((value.to_string(), SourceCoverage::default())),
+                "DA" => {
+                    let (_, coverage) = current.as_mut().ok_or_else(bad)?;
+                    let mut fields = value.split(',');
+                    let number: usize = field(fields.next()).ok_or_else(bad)?;
+                    let count: u64 = field(fields.next()).ok_or_else(bad)?;
+                    *coverage.lines.entry(number).or_default() += count;
+                }
+                "BRDA" => {
+                    let (_, coverage) = current.as_mut().ok_or_else(bad)?;
+                    let fields: Vec<&str> = value.split(',').collect();
+                    let [number, _, direction, count] = fields[..] else {
+                        return Err(bad());
+                    };
+                    let number: usize = field(Some(number)).ok_or_else(bad)?;
+                    let count: u64 = match count {
+                        "-" => 0,
+                        count => field(Some(count)).ok_or_else(bad)?,
+                    };
+
+                    let branch = coverage.branches.entry(number).or_default();
+                    match direction {
+                        "0" => branch.taken += count,
+                        "1" => branch.not_taken += count,
+                        _ => return Err(bad()),
+                    }
+                }
+                "end_of_record" => {
+                    let (path, coverage) = current.take().ok_or_else(bad)?;
+                    lcov.add(&path, &coverage);
+                }
+                _ => {}
+            }
+        }
+
+        match current {
+            Some((path, _)) => Err(format!("record for {} is not terminated", path)),
+            None => Ok(lcov),
+        }
+    }
+}
+
+fn field<T: std::str::FromStr>(value: Option<&str>) -> Option<T> {
+    value?.trim().parse().ok()
+}
+
+// writes the tracefile; a branch on a line that never ran has count -
+impl std::fmt::Display for Lcov {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (path, coverage) in self.sources.iter() {
+            writeln!(f, "TN:")?;
+            writeln!(f, "SF:{}", path)?;
+
+            for (line, branch) in coverage.branches.iter() {
+                let ran = coverage.lines.get(line).copied().unwrap_or(0) > 0;
+                for (direction, count) in [(0, branch.taken), (1, branch.not_taken)] {
+                    match ran {
+                        true => writeln!(f, "BRDA:{},0,{},{}", line, direction, count)?,
+                        false => writeln!(f, "BRDA:{},0,{},-", line, direction)?,
+                    }
+                }
+            }
+            let (branches, branches_hit) = coverage.branch_totals();
+            writeln!(f, "BRF:{}", branches)?;
+            writeln!(f, "BRH:{}", branches_hit)?;
+
+            for (line, count) in coverage.lines.iter() {
+                writeln!(f, "DA:{},{}", line, count)?;
+            }
+            let (lines, lines_hit) = coverage.line_totals();
+            writeln!(f, "LF:{}", lines)?;
+            writeln!(f, "LH:{}", lines_hit)?;
+            writeln!(f, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
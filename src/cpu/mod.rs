@@ -1,9 +1,16 @@
 // src/cpu/mod.rs
+#[derive(Clone)]
 pub struct CPU {
     pub registers: [u16; 16], // 16 general-purpose 16-bit registers
     pub pc: u16,              // program counter
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CPU {
     pub fn new() -> Self {
         Self {
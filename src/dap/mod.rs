@@ -0,0 +1,694 @@
+// src/dap/mod.rs
+//
+// Debug Adapter Protocol server, so editors can debug RISA16 programs. Messages
+// are JSON bodies behind a Content-Length header, read from one stream and
+// written to another, normally stdin and stdout.
+//
+// The machine has a single thread (id 1) with a single stack frame. Scopes are
+// Registers, Flags and Memory; memory is split into 256-byte regions that
+// expand into rows of 16 bytes. A fault stops with reason "exception" and the
+// trap message, which exceptionInfo also returns. Breakpoint conditions use
+// the crate::expr language.
+//
+// Requests are read on their own thread. While the machine runs it checks
+// for new ones every SLICE steps; pause and disconnect stop it, with reason
+// "pause", and everything that arrived is answered once it has stopped. The
+// reader is left behind when serving ends, as it may be blocked on input
+// that never comes.
+
+use crate::assembler::{Config, DebugInfo, assemble_file};
+use crate::debugger::loop_exit;
+use crate::expr::{Bindings, Condition};
+use crate::json::Json;
+use crate::vm::{ExitReason, State, VM};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+const THREAD_ID: u64 = 1;
+
+// steps between checks for new requests while the machine runs
+const SLICE: u64 = 4096;
+
+// variable references for the scopes, memory regions start at REGIONS
+const REGISTERS: u64 = 1;
+const FLAGS: u64 = 2;
+const MEMORY: u64 = 3;
+const REGIONS: u64 = 16;
+
+const REGION_SIZE: usize = 256;
+const ROW_SIZE: usize = 16;
+
+// what a request answers with: the response body and any events to send after it
+type Outcome = Result<(Json, Vec<(&'static str, Json)>), String>;
+
+pub struct DapServer {
+    pub vm: Option<VM>, // None until launched
+    debug: DebugInfo,
+    program: String,
+    stop_on_entry: bool,
+    queued: VecDeque<Json>, // requests that arrived while the machine ran
+    seq: u64,
+    done: bool,
+}
+
+impl Default for DapServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DapServer {
+    pub fn new() -> Self {
+        Self {
+            vm: None,
+            debug: DebugInfo::default(),
+            program: String::new(),
+            stop_on_entry: false,
+            queued: VecDeque::new(),
+            seq: 0,
+            done: false,
+        }
+    }
+
+    // true once the client disconnected
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // answers requests until disconnect, end of input or an error on either
+    // stream
+    pub fn serve<R: BufRead + Send + 'static, W: Write>(
+        &mut self,
+        input: R,
+        mut output: W,
+    ) -> io::Result<()> {
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || read_requests(input, sender));
+
+        let mut failed: Option<io::Error> = None;
+        while !self.done {
+            let request = match self.queued.pop_front() {
+                Some(request) => request,
+                None => match requests.recv() {
+                    Ok(Ok(request)) => request,
+                    Ok(Err(e)) => return Err(e),
+                    Err(_) => break,
+                },
+            };
+
+            let mut poll = || match requests.try_recv() {
+                Ok(Ok(request)) => Some(request),
+                Ok(Err(e)) => {
+                    failed = Some(e);
+                    None
+                }
+                Err(_) => None,
+            };
+            for message in self.handle_polling(&request, &mut poll) {
+                write_message(&mut output, &message)?;
+            }
+            if let Some(e) = failed.take() {
+                return Err(e);
+            }
+        }
+
+        // dropping requests stops the reader at its next message
+        Ok(())
+    }
+
+    // the response to one request, followed by the events it caused
+    pub fn handle(&mut self, request: &Json) -> Vec<Json> {
+        self.handle_polling(request, &mut || None)
+    }
+
+    // as handle; poll hands over requests that arrive while the machine runs
+    pub fn handle_polling(
+        &mut self,
+        request: &Json,
+        poll: &mut dyn FnMut() -> Option<Json>,
+    ) -> Vec<Json> {
+        let command = request.get("command").and_then(Json::as_str).unwrap_or("");
+        let args = request.get("arguments").unwrap_or(&Json::Null);
+
+        let outcome = match command {
+            "initialize" => Ok((
+                Json::object(vec![
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsConditionalBreakpoints", true.into()),
+                    ("supportsExceptionInfoRequest", true.into()),
+                ]),
+                vec![("initialized", Json::Null)],
+            )),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok((Json::Null, Vec::new())),
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    Ok((Json::Null, vec![stopped("entry", None)]))
+                } else {
+                    self.resume(|vm| vm.run_for(SLICE), "step", Some(poll))
+                }
+            }
+            "threads" => Ok((
+                Json::object(vec![(
+                    "threads",
+                    vec![Json::object(vec![
+                        ("id", THREAD_ID.into()),
+                        ("name", "main".into()),
+                    ])]
+                    .into(),
+                )]),
+                Vec::new(),
+            )),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => self.scopes(),
+            "variables" => self.variables(args),
+            "exceptionInfo" => self.exception_info(),
+            "continue" => {
+                self.resume(|vm| vm.run_for(SLICE), "step", Some(poll))
+                    .map(|(_, events)| {
+                        (
+                            Json::object(vec![("allThreadsContinued", true.into())]),
+                            events,
+                        )
+                    })
+            }
+            "next" => match self.vm.as_ref().and_then(loop_exit) {
+                Some(target) => {
+                    // the jump itself runs even from a breakpoint, as stepIn would
+                    let mut first = true;
+                    let run = move |vm: &mut VM| match std::mem::take(&mut first) {
+                        true => vm.step_for(1),
+                        false => run_slice_to(vm, target),
+                    };
+                    self.resume(run, "step", Some(poll))
+                }
+                None => self.resume(|vm| vm.step_for(1), "step", None),
+            },
+            "stepIn" => self.resume(|vm| vm.step_for(1), "step", None),
+            // a pause that stopped a run was answered by that run's stopped
+            // event; between runs the machine is already paused
+            "pause" => Ok((Json::Null, Vec::new())),
+            "terminate" => Ok((Json::Null, vec![("terminated", Json::Null)])),
+            "disconnect" => {
+                self.done = true;
+                Ok((Json::Null, Vec::new()))
+            }
+            _ => Err(format!("unsupported request `{}`", command)),
+        };
+
+        let mut messages: Vec<Json> = Vec::new();
+
+        let (success, body, message, events) = match outcome {
+            Ok((body, events)) => (true, body, None, events),
+            Err(e) => (false, Json::Null, Some(e), Vec::new()),
+        };
+
+        let mut response = vec![
+            ("seq", self.next_seq().into()),
+            ("type", "response".into()),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            ("success", success.into()),
+            ("command", command.into()),
+        ];
+        if body != Json::Null {
+            response.push(("body", body));
+        }
+        if let Some(message) = message {
+            response.push(("message", message.into()));
+        }
+        messages.push(Json::object(response));
+
+        for (event, body) in events {
+            let mut fields = vec![
+                ("seq", self.next_seq().into()),
+                ("type", "event".into()),
+                ("event", event.into()),
+            ];
+            if body != Json::Null {
+                fields.push(("body", body));
+            }
+            messages.push(Json::object(fields));
+        }
+
+        messages
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        self.seq += 1;
+        self.seq
+    }
+
+    fn vm(&self) -> Result<&VM, String> {
+        self.vm
+            .as_ref()
+            .ok_or("no program has been launched".to_string())
+    }
+
+    // assembles and loads the .asm file named by program
+    fn launch(&mut self, args: &Json) -> Outcome {
+        let program = args
+            .get("program")
+            .and_then(Json::as_str)
+            .ok_or("launch needs a `program` to debug")?;
+
+        let (bytecode, debug) = assemble_file(Path::new(program), &Config::default())
+            .map_err(|e| format!("assembly failed: {}", e))?;
+
+        let mut vm = VM::new();
+        vm.load(&bytecode)?;
+
+        self.vm = Some(vm);
+        self.debug = debug;
+        self.program = program.to_string();
+        self.stop_on_entry = args
+            .get("stopOnEntry")
+            .and_then(Json::as_bool)
+            .unwrap_or(false);
+
+        Ok((Json::Null, Vec::new()))
+    }
+
+    // the index in DebugInfo::files of the source at path, which may name
+    // it another way, e.g. absolute where the program was given relative
+    fn source_index(&self, path: &str) -> Option<usize> {
+        let wanted = Path::new(path).canonicalize().ok();
+        self.debug.files.iter().position(|file| {
+            file == path || (wanted.is_some() && Path::new(file).canonicalize().ok() == wanted)
+        })
+    }
+
+    // replaces the breakpoints of one source, the launched program or a file
+    // it includes; a breakpoint on a line without code moves to the next
+    // line that has some
+    fn set_breakpoints(&mut self, args: &Json) -> Outcome {
+        let requested: Vec<(u64, Option<&str>)> = args
+            .get("breakpoints")
+            .and_then(Json::as_array)
+            .unwrap_or(&[])
+            .iter()
+            .filter_map(|b| {
+                let line = b.get("line").and_then(Json::as_u64)?;
+                Some((line, b.get("condition").and_then(Json::as_str)))
+            })
+            .collect();
+
+        if self.vm.is_none() {
+            return Err("no program has been launched".into());
+        }
+        // without a path, the breakpoints are in the launched program
+        let path = args
+            .get("source")
+            .and_then(|s| s.get("path"))
+            .and_then(Json::as_str);
+        let Some(file) = path.map_or(Some(0), |path| self.source_index(path)) else {
+            let breakpoints: Vec<Json> = requested
+                .iter()
+                .map(|(line, _)| {
+                    Json::object(vec![
+                        ("verified", false.into()),
+                        ("line", (*line).into()),
+                        ("message", "not a source of this program".into()),
+                    ])
+                })
+                .collect();
+            return Ok((
+                Json::object(vec![("breakpoints", breakpoints.into())]),
+                Vec::new(),
+            ));
+        };
+
+        let vm = self.vm.as_mut().unwrap();
+        for (addr, _) in self.debug.lines.iter().filter(|(_, l)| l.file == file) {
+            vm.breakpoints.remove(addr);
+        }
+
+        let mut breakpoints: Vec<Json> = Vec::new();
+        for (line, condition) in requested {
+            let condition = match condition.map(|c| parse_condition(c, vm)).transpose() {
+                Ok(condition) => condition,
+                Err(e) => {
+                    breakpoints.push(Json::object(vec![
+                        ("verified", false.into()),
+                        ("line", line.into()),
+                        ("message", format!("invalid condition: {}", e).into()),
+                    ]));
+                    continue;
+                }
+            };
+
+            let placed = self
+                .debug
+                .lines
+                .iter()
+                .filter(|(_, l)| l.file == file)
+                .map(|(addr, l)| (addr, l.line))
+                .filter(|(_, l)| *l as u64 >= line)
+                .min_by_key(|(_, l)| *l);
+
+            breakpoints.push(match placed {
+                Some((addr, actual)) => {
+                    vm.breakpoints.set(*addr, condition);
+                    Json::object(vec![
+                        ("verified", true.into()),
+                        ("line", actual.into()),
+                        ("instructionReference", format!("{:#06X}", addr).into()),
+                    ])
+                }
+                None => Json::object(vec![
+                    ("verified", false.into()),
+                    ("line", line.into()),
+                    ("message", "no code at or after this line".into()),
+                ]),
+            });
+        }
+
+        Ok((
+            Json::object(vec![("breakpoints", breakpoints.into())]),
+            Vec::new(),
+        ))
+    }
+
+    fn stack_trace(&self) -> Outcome {
+        let vm = self.vm()?;
+        let pc = vm.cpu.pc;
+
+        // named after the nearest label at or before the PC
+        let name = self
+            .debug
+            .symbols
+            .iter()
+            .filter(|(_, addr)| **addr <= pc)
+            .max_by_key(|(name, addr)| (**addr, std::cmp::Reverse(name.as_str())))
+            .map(|(name, _)| name.as_str())
+            .unwrap_or("main");
+
+        // the launched program, or the included file the PC is in
+        let path = match self.debug.location(pc) {
+            Some(location) if location.file != 0 => self.debug.file_name(location),
+            _ => self.program.as_str(),
+        };
+        let source_name = Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let frame = Json::object(vec![
+            ("id", 0u64.into()),
+            ("name", name.into()),
+            (
+                "source",
+                Json::object(vec![("name", source_name.into()), ("path", path.into())]),
+            ),
+            ("line", self.debug.line_for(pc).unwrap_or(0).into()),
+            ("column", 1u64.into()),
+            ("instructionPointerReference", format!("{:#06X}", pc).into()),
+        ]);
+
+        Ok((
+            Json::object(vec![
+                ("stackFrames", vec![frame].into()),
+                ("totalFrames", 1u64.into()),
+            ]),
+            Vec::new(),
+        ))
+    }
+
+    fn scopes(&self) -> Outcome {
+        self.vm()?;
+
+        let scope = |name: &str, reference: u64, expensive: bool| {
+            Json::object(vec![
+                ("name", name.into()),
+                ("variablesReference", reference.into()),
+                ("expensive", expensive.into()),
+            ])
+        };
+
+        Ok((
+            Json::object(vec![(
+                "scopes",
+                vec![
+                    scope("Registers", REGISTERS, false),
+                    scope("Flags", FLAGS, false),
+                    scope("Memory", MEMORY, true),
+                ]
+                .into(),
+            )]),
+            Vec::new(),
+        ))
+    }
+
+    fn variables(&self, args: &Json) -> Outcome {
+        let vm = self.vm()?;
+        let reference = args
+            .get("variablesReference")
+            .and_then(Json::as_u64)
+            .ok_or("variables needs a `variablesReference`")?;
+
+        let variable = |name: String, value: String, reference: u64| {
+            Json::object(vec![
+                ("name", name.into()),
+                ("value", value.into()),
+                ("variablesReference", reference.into()),
+            ])
+        };
+
+        let regions = (vm.memory.data.len() / REGION_SIZE) as u64;
+
+        let variables: Vec<Json> = match reference {
+            REGISTERS => {
+                let mut registers: Vec<Json> = vm
+                    .cpu
+                    .registers
+                    .iter()
+                    .enumerate()
+                    .map(|(i, r)| variable(format!("r{}", i), format!("{:#06X}", r), 0))
+                    .collect();
+                registers.push(variable("pc".into(), format!("{:#06X}", vm.cpu.pc), 0));
+                registers
+            }
+            FLAGS => vec![
+                variable("Z".into(), vm.zero_flag.to_string(), 0),
+                variable("C".into(), vm.carry_flag.to_string(), 0),
+            ],
+            MEMORY => (0..regions)
+                .map(|i| {
+                    let start = i as usize * REGION_SIZE;
+                    variable(
+                        format!("{:#06X}-{:#06X}", start, start + REGION_SIZE - 1),
+                        format!("{} bytes", REGION_SIZE),
+                        REGIONS + i,
+                    )
+                })
+                .collect(),
+            r if (REGIONS..REGIONS + regions).contains(&r) => {
+                let start = (r - REGIONS) as usize * REGION_SIZE;
+                vm.memory.data[start..start + REGION_SIZE]
+                    .chunks(ROW_SIZE)
+                    .enumerate()
+                    .map(|(i, row)| {
+                        let bytes: Vec<String> = row.iter().map(|b| format!("{:02X}", b)).collect();
+                        variable(format!("{:#06X}", start + i * ROW_SIZE), bytes.join(" "), 0)
+                    })
+                    .collect()
+            }
+            _ => return Err(format!("unknown variablesReference {}", reference)),
+        };
+
+        Ok((
+            Json::object(vec![("variables", variables.into())]),
+            Vec::new(),
+        ))
+    }
+
+    fn exception_info(&self) -> Outcome {
+        let fault = self
+            .vm()?
+            .fault
+            .as_deref()
+            .ok_or("the program has not faulted")?;
+
+        Ok((
+            Json::object(vec![
+                ("exceptionId", "trap".into()),
+                ("description", fault.into()),
+                ("breakMode", "always".into()),
+            ]),
+            Vec::new(),
+        ))
+    }
+
+    // lets the machine run and turns the way it stopped into events. With a
+    // poll, run is one slice and is called again while it uses up its
+    // budget, until a pause or disconnect comes in
+    fn resume<F: FnMut(&mut VM) -> ExitReason>(
+        &mut self,
+        mut run: F,
+        reason: &str,
+        mut poll: Option<&mut dyn FnMut() -> Option<Json>>,
+    ) -> Outcome {
+        let vm = self.vm.as_mut().ok_or("no program has been launched")?;
+        if vm.state == State::HALTED {
+            return Err("the program is not running".to_string());
+        }
+
+        let output_start = vm.devices.console.output.len();
+        let mut reason = reason;
+        let exit = loop {
+            let exit = run(vm);
+            let Some(poll) = poll.as_mut() else {
+                break exit;
+            };
+            if exit != ExitReason::BudgetExhausted || vm.fuel == Some(0) {
+                break exit;
+            }
+
+            let mut paused = false;
+            while let Some(request) = poll() {
+                let command = request.get("command").and_then(Json::as_str);
+                paused |= matches!(command, Some("pause" | "disconnect"));
+                self.queued.push_back(request);
+            }
+            if paused {
+                reason = "pause";
+                break exit;
+            }
+        };
+
+        let mut events: Vec<(&'static str, Json)> = Vec::new();
+
+        let output = &vm.devices.console.output[output_start..];
+        if !output.is_empty() {
+            events.push((
+                "output",
+                Json::object(vec![
+                    ("category", "stdout".into()),
+                    ("output", String::from_utf8_lossy(output).to_string().into()),
+                ]),
+            ));
+        }
+
+        match exit {
+            ExitReason::Halted => {
+                events.push(("exited", Json::object(vec![("exitCode", 0u64.into())])));
+                events.push(("terminated", Json::Null));
+            }
+            ExitReason::Faulted(e) => events.push(stopped("exception", Some(e))),
+            ExitReason::BreakpointHit(_) => events.push(stopped("breakpoint", None)),
+            ExitReason::WatchpointHit(hit) => {
+                events.push(stopped("data breakpoint", Some(hit.to_string())))
+            }
+            ExitReason::BudgetExhausted | ExitReason::UserStop => {
+                events.push(stopped(reason, None))
+            }
+        }
+
+        Ok((Json::Null, events))
+    }
+}
+
+// at most SLICE steps towards target, the loop exit next is heading for
+fn run_slice_to(vm: &mut VM, target: u16) -> ExitReason {
+    let mut steps: u64 = 0;
+    let exit = vm.run_until(|vm| {
+        steps += 1;
+        vm.cpu.pc == target || steps > SLICE
+    });
+
+    match exit {
+        ExitReason::UserStop if vm.cpu.pc != target => ExitReason::BudgetExhausted,
+        exit => exit,
+    }
+}
+
+// a breakpoint condition, with every name in it known to the machine
+fn parse_condition(src: &str, vm: &VM) -> Result<Condition, String> {
+    let condition = Condition::parse(src)?;
+    condition.expr.check_names(&Bindings {
+        env: vm,
+        names: &[("hits", 0)],
+    })?;
+    Ok(condition)
+}
+
+fn stopped(reason: &str, text: Option<String>) -> (&'static str, Json) {
+    let mut body = vec![
+        ("reason", reason.into()),
+        ("threadId", THREAD_ID.into()),
+        ("allThreadsStopped", true.into()),
+    ];
+    if let Some(text) = text {
+        body.push(("description", text.as_str().into()));
+        body.push(("text", text.into()));
+    }
+
+    ("stopped", Json::object(body))
+}
+
+// forwards requests until end of input, a disconnect or a closed channel; a
+// read error is forwarded too, and ends it
+fn read_requests<R: BufRead>(mut input: R, requests: mpsc::Sender<io::Result<Json>>) {
+    loop {
+        let request = match read_message(&mut input) {
+            Ok(Some(request)) => request,
+            Ok(None) => return,
+            Err(e) => {
+                let _ = requests.send(Err(e));
+                return;
+            }
+        };
+
+        let disconnect = request.get("command").and_then(Json::as_str) == Some("disconnect");
+        if requests.send(Ok(request)).is_err() || disconnect {
+            return;
+        }
+    }
+}
+
+// reads one Content-Length framed message, None at end of input
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Json>> {
+    let mut length: Option<usize> = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+
+    let length = length.ok_or(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "message has no Content-Length",
+    ))?;
+
+    let mut body = vec![0u8; length];
+    input.read_exact(&mut body)?;
+
+    let body = String::from_utf8(body)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "message is not UTF-8"))?;
+    Json::parse(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
@@ -0,0 +1,721 @@
+// src/debugger/mod.rs
+//
+// Interactive gdb-style debugger. The program is assembled with debug info so
+// breakpoints can name labels and every stop shows the source line it is on.
+//
+//   break [addr|label] [if <expr>]
+//                          b   set a breakpoint, or list them
+//   condition <addr|label> [expr]
+//                              change or drop a breakpoint's condition
+//   delete [addr|label]    d   remove a breakpoint, or all of them
+//   watch <addr|label|rN> [len] [log] [if <expr>]
+//                              stop when memory is written or a register
+//                              changes, or only log it; with no argument list
+//                              the watchpoints
+//   rwatch, awatch             the same for reads, and for any access
+//   unwatch [id]               remove a watchpoint, or all of them
+//   step [n]               s   execute n instructions
+//   next                   n   like step, but a backward conditional jump runs
+//                              its loop to completion
+//   continue               c   run until a breakpoint, halt or fault
+//   regs                       show the registers and PC
+//   flags                      show the zero and carry flags
+//   x/<n><x|w> <addr|label>    examine n bytes (x) or words (w) of memory
+//   set <rN|pc> = <value>      change a register or the PC
+//   disasm [addr] [n]          disassemble n instructions, from the PC by default
+//   list                   l   show the source around the PC
+//   where                      show the current source line
+//   quit                   q
+//
+// An empty line repeats the previous command. Conditions use the expression
+// language from crate::expr; hits counts arrivals at a breakpoint, and
+// watchpoint conditions can also use old and new.
+
+use crate::assembler::{
+    Config, DebugInfo, Location, assemble_file, assemble_with_debug, parse_register, parse_u16,
+};
+use crate::decoder::decode;
+use crate::expr::{Bindings, Condition};
+use crate::instructions::Instruction;
+use crate::vm::{ExitReason, State, VM};
+use crate::watchpoints::{Access, Action, Target};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+pub const PROMPT: &str = "(risa16) ";
+
+const HELP: &str = "break [addr|label] [if <expr>]
+                       set a breakpoint, or list them
+condition <addr> [expr]
+                       change or drop a breakpoint's condition
+delete [addr|label]    remove a breakpoint, or all of them
+watch <addr|rN> [len] [log] [if <expr>]
+                       stop on writes or register changes, or just log them
+rwatch <addr> [len] [log]
+                       stop on reads
+awatch <addr> [len] [log]
+                       stop on any access
+unwatch [id]           remove a watchpoint, or all of them
+step [n]               execute n instructions
+next                   step, running loops to completion
+continue               run until a breakpoint, halt or fault
+regs                   show the registers
+flags                  show the flags
+x/<n><x|w> <addr>      examine n bytes (x) or words (w) of memory
+set <rN|pc> = <value>  change a register or the PC
+disasm [addr] [n]      disassemble n instructions
+list                   show the source around the PC
+where                  show the current source line
+quit                   leave the debugger";
+
+pub struct Debugger {
+    pub vm: VM,
+    pub debug: DebugInfo,
+    sources: Vec<Vec<String>>, // lines of each file, indexed by Location::file
+    last_command: String,
+    done: bool,
+}
+
+impl Debugger {
+    pub fn new(src: &str) -> Result<Self, String> {
+        let (bytecode, debug) = assemble_with_debug(src)?;
+        Self::with_sources(&bytecode, debug, vec![lines_of(src)])
+    }
+
+    // the program at path, with its .include and .incbin files
+    pub fn open(path: &Path, config: &Config) -> Result<Self, String> {
+        let (bytecode, debug) = assemble_file(path, config)?;
+        Self::load(&bytecode, debug)
+    }
+
+    // an assembled program whose source files are read back from disk
+    pub fn load(bytecode: &[u8], debug: DebugInfo) -> Result<Self, String> {
+        let sources = debug
+            .files
+            .iter()
+            .map(|f| fs::read_to_string(f).map_or(Vec::new(), |src| lines_of(&src)))
+            .collect();
+        Self::with_sources(bytecode, debug, sources)
+    }
+
+    fn with_sources(
+        bytecode: &[u8],
+        debug: DebugInfo,
+        sources: Vec<Vec<String>>,
+    ) -> Result<Self, String> {
+        let mut vm = VM::new();
+        vm.load(bytecode)?;
+
+        Ok(Self {
+            vm,
+            debug,
+            sources,
+            last_command: String::new(),
+            done: false,
+        })
+    }
+
+    // true once quit has been entered
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // reads commands until quit or end of input
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+
+        let mut lines = input.lines();
+        while !self.done {
+            write!(output, "{}", PROMPT)?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+
+            match self.command(&line) {
+                Ok(reply) if reply.is_empty() => {}
+                Ok(reply) => writeln!(output, "{}", reply)?,
+                Err(e) => writeln!(output, "error: {}", e)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    // runs one command line and returns what it printed
+    pub fn command(&mut self, line: &str) -> Result<String, String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => {
+                self.last_command = line.to_string();
+                line.to_string()
+            }
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((name, args)) = words.split_first() else {
+            return Ok(String::new());
+        };
+
+        match *name {
+            "break" | "b" => self.set_breakpoint(args),
+            "condition" => self.condition(args),
+            "delete" | "d" => self.delete_breakpoint(args),
+            "watch" => self.watch(Access::Write, args),
+            "rwatch" => self.watch(Access::Read, args),
+            "awatch" => self.watch(Access::Any, args),
+            "unwatch" => self.unwatch(args),
+            "step" | "s" => {
+                let n = match args {
+                    [] => 1,
+                    [n] => n.parse().map_err(|_| format!("invalid count `{}`", n))?,
+                    _ => return Err("usage: step [n]".to_string()),
+                };
+                self.resume(|vm| vm.step_for(n))
+            }
+            "next" | "n" => self.next(),
+            "continue" | "c" => self.resume(|vm| vm.run()),
+            "regs" => Ok(self.registers()),
+            "flags" => Ok(format!(
+                "Z={} C={}",
+                self.vm.zero_flag as u8, self.vm.carry_flag as u8
+            )),
+            "set" => self.set(&line["set".len()..]),
+            "disasm" => {
+                let start = match args.first() {
+                    Some(addr) => self.resolve(addr)?,
+                    None => self.vm.cpu.pc,
+                };
+                let count = match args.get(1) {
+                    Some(n) => n.parse().map_err(|_| format!("invalid count `{}`", n))?,
+                    None => 8,
+                };
+                Ok(self.disassemble(start, count))
+            }
+            "list" | "l" => Ok(self.list()),
+            "where" => Ok(self.location()),
+            "help" | "h" => Ok(HELP.to_string()),
+            "quit" | "q" => {
+                self.done = true;
+                Ok(String::new())
+            }
+            _ if name.starts_with("x") => self.examine(name, args),
+            _ => Err(format!("unknown command `{}`, try `help`", name)),
+        }
+    }
+
+    // an address is a number or a label
+    fn resolve(&self, token: &str) -> Result<u16, String> {
+        if let Ok(addr) = parse_u16(token) {
+            return Ok(addr);
+        }
+
+        self.debug
+            .symbols
+            .get(token)
+            .copied()
+            .ok_or(format!("no label or address `{}`", token))
+    }
+
+    // address, label and source line of the PC
+    fn location(&self) -> String {
+        let pc = self.vm.cpu.pc;
+        let text = match self.debug.location(pc) {
+            Some(location) => format!(
+                "{}: {}",
+                self.place(location),
+                self.source_line(location.file, location.line).trim()
+            ),
+            None => self.disassemble_one(pc).0,
+        };
+
+        format!("{}  {}", self.describe(pc), text)
+    }
+
+    // the address, followed by its label when it has one
+    fn describe(&self, addr: u16) -> String {
+        match self.debug.symbol_at(addr) {
+            Some(label) => format!("{:#06X} <{}>", addr, label),
+            None => format!("{:#06X}", addr),
+        }
+    }
+
+    fn set_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let (args, condition) = self.split_condition(args, &[])?;
+
+        match args {
+            [] if condition.is_none() => {
+                if self.vm.breakpoints.is_empty() {
+                    return Ok("No breakpoints.".to_string());
+                }
+
+                let lines: Vec<String> = self
+                    .vm
+                    .breakpoints
+                    .iter()
+                    .map(|addr| self.describe_breakpoint(*addr))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            [target] => {
+                let addr = self.resolve(target)?;
+                self.vm.breakpoints.set(addr, condition);
+                Ok(self.describe_breakpoint(addr))
+            }
+            _ => Err("usage: break [addr|label] [if <expr>]".to_string()),
+        }
+    }
+
+    // condition loop r0 == 5, or condition loop to drop it
+    fn condition(&mut self, args: &[&str]) -> Result<String, String> {
+        let Some((target, expr)) = args.split_first() else {
+            return Err("usage: condition <addr|label> [expr]".to_string());
+        };
+
+        let addr = self.resolve(target)?;
+        if !self.vm.breakpoints.contains(&addr) {
+            return Err(format!("no breakpoint at {:#06X}", addr));
+        }
+
+        let condition = match expr {
+            [] => None,
+            _ => Some(self.parse_condition(&expr.join(" "), &[])?),
+        };
+        self.vm.breakpoints.set(addr, condition);
+        Ok(self.describe_breakpoint(addr))
+    }
+
+    // splits off a trailing `if <expr>`; extra names the condition may use
+    // besides the VM's
+    fn split_condition<'a>(
+        &self,
+        args: &'a [&'a str],
+        extra: &[&str],
+    ) -> Result<(&'a [&'a str], Option<Condition>), String> {
+        match args.iter().position(|a| *a == "if") {
+            Some(i) => {
+                let condition = self.parse_condition(&args[i + 1..].join(" "), extra)?;
+                Ok((&args[..i], Some(condition)))
+            }
+            None => Ok((args, None)),
+        }
+    }
+
+    // parses a condition and checks that every name in it is known
+    fn parse_condition(&self, src: &str, extra: &[&str]) -> Result<Condition, String> {
+        let condition = Condition::parse(src)?;
+
+        let names: Vec<(&str, i64)> = ["hits"]
+            .iter()
+            .chain(extra)
+            .map(|name| (*name, 0))
+            .collect();
+        condition.expr.check_names(&Bindings {
+            env: &self.vm,
+            names: &names,
+        })?;
+
+        Ok(condition)
+    }
+
+    fn describe_breakpoint(&self, addr: u16) -> String {
+        let mut text = format!("Breakpoint at {}", self.describe(addr));
+
+        if let Some(point) = self.vm.breakpoints.get(addr) {
+            if let Some(condition) = &point.condition {
+                text += &format!(" if {}", condition.source);
+            }
+            text += &hit_count(point.hits);
+        }
+        text
+    }
+
+    fn delete_breakpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            [] => {
+                self.vm.breakpoints.clear();
+                Ok("Deleted all breakpoints.".to_string())
+            }
+            [target] => {
+                let addr = self.resolve(target)?;
+                if !self.vm.breakpoints.remove(&addr) {
+                    return Err(format!("no breakpoint at {:#06X}", addr));
+                }
+                Ok(format!("Deleted breakpoint at {}", self.describe(addr)))
+            }
+            _ => Err("usage: delete [addr|label]".to_string()),
+        }
+    }
+
+    // watch 0x0200, watch buffer 4 log, watch r3
+    fn watch(&mut self, access: Access, args: &[&str]) -> Result<String, String> {
+        let (args, condition) = self.split_condition(args, &["old", "new"])?;
+
+        let Some((target, rest)) = args.split_first() else {
+            if condition.is_some() {
+                return Err("usage: watch <addr|label|rN> [len] [log] [if <expr>]".to_string());
+            }
+            return Ok(self.watchpoints());
+        };
+
+        let (action, rest) = match rest.split_last() {
+            Some((&"log", rest)) => (Action::Log, rest),
+            _ => (Action::Stop, rest),
+        };
+
+        let id = if target.starts_with('r') && !self.debug.symbols.contains_key(*target) {
+            if access != Access::Write || !rest.is_empty() {
+                return Err("registers can only be watched for changes".to_string());
+            }
+            let reg = parse_register(target)?;
+            self.vm.watchpoints.watch_register(reg, action)?
+        } else {
+            let start = self.resolve(target)?;
+            let len = match rest {
+                [] => 2,
+                [len] => parse_u16(len).map_err(|_| format!("invalid length `{}`", len))?,
+                _ => return Err("usage: watch <addr|label|rN> [len] [log] [if <expr>]".to_string()),
+            };
+            self.vm
+                .watchpoints
+                .watch_memory(start, len, access, action)?
+        };
+        self.vm.watchpoints.set_condition(id, condition);
+
+        Ok(self.describe_watchpoint(id))
+    }
+
+    fn unwatch(&mut self, args: &[&str]) -> Result<String, String> {
+        match args {
+            [] => {
+                self.vm.watchpoints.clear();
+                Ok("Deleted all watchpoints.".to_string())
+            }
+            [id] => {
+                let id: usize = id.parse().map_err(|_| format!("invalid id `{}`", id))?;
+                if !self.vm.watchpoints.remove(id) {
+                    return Err(format!("no watchpoint {}", id));
+                }
+                Ok(format!("Deleted watchpoint {}", id))
+            }
+            _ => Err("usage: unwatch [id]".to_string()),
+        }
+    }
+
+    fn watchpoints(&self) -> String {
+        let list = self.vm.watchpoints.list();
+        if list.is_empty() {
+            return "No watchpoints.".to_string();
+        }
+
+        let lines: Vec<String> = list
+            .iter()
+            .map(|w| self.describe_watchpoint(w.id))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn describe_watchpoint(&self, id: usize) -> String {
+        let Some(watch) = self.vm.watchpoints.list().iter().find(|w| w.id == id) else {
+            return format!("no watchpoint {}", id);
+        };
+
+        let target = match watch.target {
+            Target::Memory { start, len, access } => {
+                let kind = match access {
+                    Access::Read => "reads of",
+                    Access::Write => "writes to",
+                    Access::Any => "accesses to",
+                };
+                format!("{} {} ({} bytes)", kind, self.describe(start), len)
+            }
+            Target::Register(reg) => format!("changes to r{}", reg),
+        };
+        let action = match watch.action {
+            Action::Stop => "",
+            Action::Log => ", log only",
+        };
+        let condition = match &watch.condition {
+            Some(condition) => format!(" if {}", condition.source),
+            None => String::new(),
+        };
+
+        format!(
+            "Watchpoint {}: {}{}{}{}",
+            id,
+            target,
+            action,
+            condition,
+            hit_count(watch.hits)
+        )
+    }
+
+    // steps one instruction, or a whole loop when stopped on its backward jump
+    fn next(&mut self) -> Result<String, String> {
+        match loop_exit(&self.vm) {
+            // the jump itself runs even from a breakpoint, as step would
+            Some(target) => self.resume(|vm| match vm.step_for(1) {
+                ExitReason::BudgetExhausted => vm.run_until(|vm| vm.cpu.pc == target),
+                exit => exit,
+            }),
+            None => self.resume(|vm| vm.step_for(1)),
+        }
+    }
+
+    // lets the machine run and reports where it stopped
+    fn resume<F: FnOnce(&mut VM) -> ExitReason>(&mut self, run: F) -> Result<String, String> {
+        if self.vm.state == State::HALTED {
+            return Err("the program is not running".to_string());
+        }
+
+        let output_start = self.vm.devices.console.output.len();
+        // hits before this run were reported when they happened
+        self.vm.watchpoints.take_log();
+        let exit = run(&mut self.vm);
+
+        let mut lines: Vec<String> = Vec::new();
+
+        let output = &self.vm.devices.console.output[output_start..];
+        if !output.is_empty() {
+            lines.push(String::from_utf8_lossy(output).trim_end().to_string());
+        }
+
+        // logged and stopping hits alike
+        for hit in self.vm.watchpoints.take_log() {
+            lines.push(hit.to_string());
+        }
+
+        match exit {
+            ExitReason::Halted => lines.push("Program halted.".to_string()),
+            ExitReason::Faulted(e) => lines.push(format!("Program faulted: {}", e)),
+            ExitReason::BreakpointHit(addr) => {
+                lines.push(format!("Breakpoint at {}", self.describe(addr)));
+                if let Some(e) = self.vm.breakpoints.get(addr).and_then(|b| b.error.as_ref()) {
+                    lines.push(format!("Error in condition: {}", e));
+                }
+            }
+            ExitReason::WatchpointHit(hit) => {
+                let watch = self.vm.watchpoints.list().iter().find(|w| w.id == hit.id);
+                if let Some(e) = watch.and_then(|w| w.error.as_ref()) {
+                    lines.push(format!("Error in condition: {}", e));
+                }
+            }
+            ExitReason::BudgetExhausted | ExitReason::UserStop => {}
+        }
+
+        lines.push(self.location());
+        Ok(lines.join("\n"))
+    }
+
+    fn registers(&self) -> String {
+        let mut lines: Vec<String> = Vec::new();
+
+        for row in 0..4 {
+            let cells: Vec<String> = (row * 4..row * 4 + 4)
+                .map(|i| {
+                    format!(
+                        "{:<3} = {:#06X}",
+                        format!("r{}", i),
+                        self.vm.cpu.registers[i]
+                    )
+                })
+                .collect();
+            lines.push(cells.join("  "));
+        }
+        lines.push(format!("pc  = {:#06X}", self.vm.cpu.pc));
+
+        lines.join("\n")
+    }
+
+    // set r3 = 5, set pc = loop
+    fn set(&mut self, assignment: &str) -> Result<String, String> {
+        let (target, value) = assignment
+            .split_once('=')
+            .ok_or("usage: set <rN|pc> = <value>")?;
+        let (target, value) = (target.trim(), value.trim());
+
+        let value = self.resolve(value)?;
+
+        if target == "pc" {
+            self.vm.cpu.pc = value;
+        } else {
+            let reg = parse_register(target)?;
+            self.vm.cpu.registers[reg as usize] = value;
+        }
+
+        Ok(format!("{} = {:#06X}", target, value))
+    }
+
+    // x/16x 0x0200 shows 16 bytes, x/4w 0x0200 shows 4 big-endian words
+    fn examine(&self, name: &str, args: &[&str]) -> Result<String, String> {
+        let spec = name["x".len()..]
+            .strip_prefix('/')
+            .unwrap_or(&name["x".len()..]);
+        let digits = spec.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+
+        let count: usize = match digits {
+            "" => 16,
+            digits => digits
+                .parse()
+                .map_err(|_| format!("invalid count `{}`", digits))?,
+        };
+        let width: usize = match &spec[digits.len()..] {
+            "" | "x" => 1,
+            "w" => 2,
+            other => return Err(format!("unknown format `{}`, use x or w", other)),
+        };
+
+        let addr = match args {
+            [addr] => self.resolve(addr)? as usize,
+            _ => return Err("usage: x/<n><x|w> <addr|label>".to_string()),
+        };
+
+        let end = count.checked_mul(width).and_then(|n| addr.checked_add(n));
+        let end = match end {
+            Some(end) if end <= self.vm.memory.data.len() => end,
+            _ => return Err("address out of bounds".to_string()),
+        };
+
+        let per_row = 16 / width;
+        let mut lines: Vec<String> = Vec::new();
+
+        for row in (addr..end).step_by(per_row * width) {
+            let cells: Vec<String> = (row..end.min(row + per_row * width))
+                .step_by(width)
+                .map(|a| match width {
+                    1 => format!("{:02X}", self.vm.memory.data[a]),
+                    _ => format!(
+                        "{:02X}{:02X}",
+                        self.vm.memory.data[a],
+                        self.vm.memory.data[a + 1]
+                    ),
+                })
+                .collect();
+            lines.push(format!("{:#06X}:  {}", row, cells.join(" ")));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    // text of the instruction at addr and how many bytes it takes
+    fn disassemble_one(&self, addr: u16) -> (String, u16) {
+        match decode(&self.vm.memory.data, addr) {
+            Ok(decoded) => {
+                let mut text = decoded.instr.to_string();
+
+                if let Instruction::Jump { addr: target }
+                | Instruction::JumpZ { addr: target }
+                | Instruction::JumpNZ { addr: target } = decoded.instr
+                    && let Some(label) = self.debug.symbol_at(target)
+                {
+                    text = format!("{} <{}>", text, label);
+                }
+
+                (text, decoded.length)
+            }
+            Err(_) => match self.vm.memory.data.get(addr as usize) {
+                Some(byte) => (format!("(bad) {:#04X}", byte), 1),
+                None => ("(out of bounds)".to_string(), 1),
+            },
+        }
+    }
+
+    fn disassemble(&self, start: u16, count: usize) -> String {
+        let mut lines: Vec<String> = Vec::new();
+        let mut addr = start;
+
+        for _ in 0..count {
+            if addr as usize >= self.vm.memory.data.len() {
+                break;
+            }
+
+            let (text, length) = self.disassemble_one(addr);
+            let marker = if addr == self.vm.cpu.pc { "=>" } else { "  " };
+            let breakpoint = if self.vm.breakpoints.contains(&addr) {
+                "*"
+            } else {
+                " "
+            };
+
+            lines.push(format!(
+                "{}{} {}:  {}",
+                marker,
+                breakpoint,
+                self.describe(addr),
+                text
+            ));
+            addr += length;
+        }
+
+        lines.join("\n")
+    }
+
+    // the source lines around the PC
+    fn list(&self) -> String {
+        let location = match self.debug.location(self.vm.cpu.pc) {
+            Some(location) => location,
+            None => return format!("no source line for {:#06X}", self.vm.cpu.pc),
+        };
+        let current = location.line;
+        let source = self.sources.get(location.file).map_or(&[][..], |s| s);
+
+        let first = current.saturating_sub(5).max(1);
+        let last = (current + 5).min(source.len());
+
+        let mut lines: Vec<String> = Vec::new();
+        if location.file != 0 {
+            lines.push(self.debug.file_name(location).to_string());
+        }
+        lines.extend((first..=last).map(|n| {
+            let marker = if n == current { "=>" } else { "  " };
+            format!("{} {:>4}  {}", marker, n, source[n - 1])
+        }));
+        lines.join("\n")
+    }
+
+    // `line 7` in the main file, `lib.asm:7` in an included one
+    fn place(&self, location: &Location) -> String {
+        match location.file {
+            0 => format!("line {}", location.line),
+            _ => format!("{}:{}", self.debug.file_name(location), location.line),
+        }
+    }
+
+    // one line of a source file, empty when the file could not be read back
+    fn source_line(&self, file: usize, line: usize) -> &str {
+        self.sources
+            .get(file)
+            .and_then(|s| s.get(line - 1))
+            .map_or("", |l| l.as_str())
+    }
+}
+
+fn lines_of(src: &str) -> Vec<String> {
+    src.lines().map(|l| l.to_string()).collect()
+}
+
+// ", hit 3 times" for listings, nothing before the first hit
+fn hit_count(hits: u64) -> String {
+    match hits {
+        0 => String::new(),
+        1 => ", hit 1 time".to_string(),
+        n => format!(", hit {} times", n),
+    }
+}
+
+// where `next` should stop: when the PC is on the backward conditional jump
+// closing a loop, the instruction after it, otherwise None for a single step
+pub fn loop_exit(vm: &VM) -> Option<u16> {
+    let pc = vm.cpu.pc;
+    let decoded = decode(&vm.memory.data, pc).ok()?;
+
+    match decoded.instr {
+        Instruction::JumpZ { addr } | Instruction::JumpNZ { addr } if addr <= pc => {
+            Some(pc + decoded.length)
+        }
+        _ => None,
+    }
+}
@@ -141,6 +141,48 @@ pub fn decode(bytes: &[u8], pc: u16) -> Result<DecodedInstruction, String> {
             })
         }
 
+        0x0B => {
+            // RdCycle: reg (1B)
+            let reg = *bytes.get(pc + 1).ok_or("Missing register Byte")?;
+
+            Ok(DecodedInstruction {
+                instr: Instruction::RdCycle { reg },
+                length: 2,
+            })
+        }
+
+        0x0C => {
+            // RdInstret: reg (1B)
+            let reg = *bytes.get(pc + 1).ok_or("Missing register Byte")?;
+
+            Ok(DecodedInstruction {
+                instr: Instruction::RdInstret { reg },
+                length: 2,
+            })
+        }
+
+        0x0D => {
+            // In: reg (1B), port (1B)
+            let reg = *bytes.get(pc + 1).ok_or("Missing register Byte")?;
+            let port = *bytes.get(pc + 2).ok_or("Missing port Byte")?;
+
+            Ok(DecodedInstruction {
+                instr: Instruction::In { reg, port },
+                length: 3,
+            })
+        }
+
+        0x0E => {
+            // Out: port (1B), reg (1B)
+            let port = *bytes.get(pc + 1).ok_or("Missing port Byte")?;
+            let reg = *bytes.get(pc + 2).ok_or("Missing register Byte")?;
+
+            Ok(DecodedInstruction {
+                instr: Instruction::Out { port, reg },
+                length: 3,
+            })
+        }
+
         0xFF => {
             // HALT
             Ok(DecodedInstruction {
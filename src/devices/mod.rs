@@ -0,0 +1,127 @@
+// src/devices/mod.rs
+//
+// Port-mapped devices reached through the IN and OUT instructions.
+//
+// Port 0x00  console data    IN reads the next input byte, 0xFFFF once input
+//                            is exhausted. OUT writes the low byte.
+// Port 0x01  console status  IN reads how many input bytes are left.
+// Port 0x02  timer           IN reads milliseconds since the machine started.
+// Port 0x03  random          IN reads a pseudo-random value.
+//
+// Every read goes through the replay mode so runs can be recorded and
+// reproduced exactly.
+
+use crate::replay::{Event, Mode};
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::{Instant, SystemTime};
+
+pub const CONSOLE_DATA: u8 = 0x00;
+pub const CONSOLE_STATUS: u8 = 0x01;
+pub const TIMER: u8 = 0x02;
+pub const RANDOM: u8 = 0x03;
+
+#[derive(Clone, Default)]
+pub struct Console {
+    pub input: VecDeque<u8>, // bytes waiting to be read by the guest
+    pub output: Vec<u8>,     // bytes written by the guest
+}
+
+#[derive(Clone)]
+pub struct Devices {
+    pub console: Console,
+    pub epoch: Instant, // when the timer started counting
+    pub rng_state: u64, // xorshift state, never zero
+    pub replay: Mode,
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Devices {
+    pub fn new() -> Self {
+        // seed from the per-process hasher keys and the clock
+        let seed = RandomState::new().hash_one(SystemTime::now());
+        Self::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            console: Console::default(),
+            epoch: Instant::now(),
+            rng_state: seed | 1,
+            replay: Mode::Live,
+        }
+    }
+
+    // reads a port on behalf of the instruction retiring as number instret
+    pub fn read(&mut self, port: u8, instret: u64) -> Result<u16, String> {
+        if let Mode::Replaying { events, next } = &mut self.replay {
+            return match events.get(*next) {
+                Some(event) if event.port == port && event.instret == instret => {
+                    *next += 1;
+                    Ok(event.value)
+                }
+                Some(event) => Err(format!(
+                    "replay diverged: expected a read of port {:#04X} at instruction {}, got port {:#04X} at instruction {}",
+                    event.port, event.instret, port, instret
+                )),
+                None => Err(format!(
+                    "replay diverged: read of port {:#04X} at instruction {} is past the end of the recording",
+                    port, instret
+                )),
+            };
+        }
+
+        let value = self.read_live(port)?;
+
+        if let Mode::Recording(events) = &mut self.replay {
+            events.push(Event {
+                instret,
+                port,
+                value,
+            });
+        }
+
+        Ok(value)
+    }
+
+    fn read_live(&mut self, port: u8) -> Result<u16, String> {
+        match port {
+            CONSOLE_DATA => Ok(self
+                .console
+                .input
+                .pop_front()
+                .map(|b| b as u16)
+                .unwrap_or(0xFFFF)),
+            CONSOLE_STATUS => Ok(self.console.input.len().min(0xFFFF) as u16),
+            TIMER => Ok(self.epoch.elapsed().as_millis() as u16),
+            RANDOM => Ok(self.next_random()),
+            _ => Err(format!("no device can be read at port {:#04X}", port)),
+        }
+    }
+
+    pub fn write(&mut self, port: u8, value: u16) -> Result<(), String> {
+        match port {
+            CONSOLE_DATA => {
+                self.console.output.push(value as u8);
+                Ok(())
+            }
+            _ => Err(format!("no device can be written at port {:#04X}", port)),
+        }
+    }
+
+    // xorshift64*, the top 16 bits are the best mixed
+    fn next_random(&mut self) -> u16 {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+        (x.wrapping_mul(0x2545F4914F6CDD1D) >> 48) as u16
+    }
+}
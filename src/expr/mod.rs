@@ -0,0 +1,466 @@
+// src/expr/mod.rs
+//
+// A small expression language over machine state, used for breakpoint and
+// watchpoint conditions:
+//
+//   r3 == 0x10 && Z
+//   mem16[0x200] > 5
+//   hits > 100
+//
+// Names are looked up through the Env trait, so any tool can evaluate
+// expressions against its own state. The VM provides r0-r15, pc, Z, C,
+// instret and cycles; mem8[addr] and mem16[addr] read memory big-endian.
+// hi(x) and lo(x) are the high and low bytes of a 16-bit value. Operators
+// and their precedence follow C, values are 64-bit signed and comparisons
+// give 1 or 0. The assembler uses the same language for its operands.
+
+use crate::vm::VM;
+
+pub trait Env {
+    // the value of a name such as r3 or Z, None if it is not defined
+    fn lookup(&self, name: &str) -> Option<i64>;
+
+    // one byte of memory, None if addr is out of bounds
+    fn read_memory(&self, addr: u16) -> Option<u8>;
+}
+
+impl Env for VM {
+    fn lookup(&self, name: &str) -> Option<i64> {
+        match name {
+            "pc" => Some(self.cpu.pc as i64),
+            "Z" => Some(self.zero_flag as i64),
+            "C" => Some(self.carry_flag as i64),
+            "instret" => Some(self.instret as i64),
+            "cycles" => Some(self.cycles as i64),
+            _ => {
+                let reg: usize = name.strip_prefix('r')?.parse().ok()?;
+                self.cpu.registers.get(reg).map(|r| *r as i64)
+            }
+        }
+    }
+
+    fn read_memory(&self, addr: u16) -> Option<u8> {
+        self.memory.data.get(addr as usize).copied()
+    }
+}
+
+// an Env with some extra names, such as hits, layered over another
+pub struct Bindings<'a> {
+    pub env: &'a dyn Env,
+    pub names: &'a [(&'a str, i64)],
+}
+
+impl Env for Bindings<'_> {
+    fn lookup(&self, name: &str) -> Option<i64> {
+        match self.names.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => Some(*value),
+            None => self.env.lookup(name),
+        }
+    }
+
+    fn read_memory(&self, addr: u16) -> Option<u8> {
+        self.env.read_memory(addr)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+    BitNot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Name(String),
+    Mem8(Box<Expr>),
+    Mem16(Box<Expr>),
+    Hi(Box<Expr>),
+    Lo(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(src: &str) -> Result<Expr, String> {
+        let tokens = tokenize(src)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+
+        let expr = parser.expr(0)?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected `{}`", token)),
+        }
+    }
+
+    // arithmetic wraps, as it would in a register
+    pub fn eval(&self, env: &dyn Env) -> Result<i64, String> {
+        self.evaluate(env, false)
+    }
+
+    // as eval, but overflow is an error; for values such as assembler
+    // operands, where a wrapped result would be silently wrong
+    pub fn eval_checked(&self, env: &dyn Env) -> Result<i64, String> {
+        self.evaluate(env, true)
+    }
+
+    fn evaluate(&self, env: &dyn Env, checked: bool) -> Result<i64, String> {
+        let overflow = || "arithmetic overflow".to_string();
+        match self {
+            Expr::Number(n) => Ok(*n),
+            Expr::Name(name) => env.lookup(name).ok_or(format!("unknown name `{}`", name)),
+            Expr::Mem8(addr) => {
+                let addr = address(addr.evaluate(env, checked)?)?;
+                let byte = env
+                    .read_memory(addr)
+                    .ok_or(format!("address {:#06X} out of bounds", addr))?;
+                Ok(byte as i64)
+            }
+            Expr::Mem16(addr) => {
+                let addr = address(addr.evaluate(env, checked)?)?;
+                let (hi, lo) = match (env.read_memory(addr), env.read_memory(addr.wrapping_add(1)))
+                {
+                    (Some(hi), Some(lo)) => (hi, lo),
+                    _ => return Err(format!("address {:#06X} out of bounds", addr)),
+                };
+                Ok(((hi as i64) << 8) | lo as i64)
+            }
+            Expr::Hi(value) => Ok((value.evaluate(env, checked)? >> 8) & 0xFF),
+            Expr::Lo(value) => Ok(value.evaluate(env, checked)? & 0xFF),
+            Expr::Unary(op, operand) => {
+                let value = operand.evaluate(env, checked)?;
+                Ok(match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Neg if checked => value.checked_neg().ok_or_else(overflow)?,
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::BitNot => !value,
+                })
+            }
+            // && and || only evaluate the right side when they need it
+            Expr::Binary(BinaryOp::And, lhs, rhs) => {
+                Ok((lhs.evaluate(env, checked)? != 0 && rhs.evaluate(env, checked)? != 0) as i64)
+            }
+            Expr::Binary(BinaryOp::Or, lhs, rhs) => {
+                Ok((lhs.evaluate(env, checked)? != 0 || rhs.evaluate(env, checked)? != 0) as i64)
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let (a, b) = (lhs.evaluate(env, checked)?, rhs.evaluate(env, checked)?);
+                Ok(match op {
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::Shl | BinaryOp::Shr if checked && !(0..64).contains(&b) => {
+                        return Err(format!("shift by {} is out of range", b));
+                    }
+                    BinaryOp::Shl if checked => {
+                        let shifted = a << b;
+                        match shifted >> b == a {
+                            true => shifted,
+                            false => return Err(overflow()),
+                        }
+                    }
+                    BinaryOp::Add if checked => a.checked_add(b).ok_or_else(overflow)?,
+                    BinaryOp::Sub if checked => a.checked_sub(b).ok_or_else(overflow)?,
+                    BinaryOp::Mul if checked => a.checked_mul(b).ok_or_else(overflow)?,
+                    BinaryOp::Shl => u32::try_from(b)
+                        .ok()
+                        .and_then(|b| a.checked_shl(b))
+                        .unwrap_or(0),
+                    BinaryOp::Shr => u32::try_from(b)
+                        .ok()
+                        .and_then(|b| a.checked_shr(b))
+                        .unwrap_or(if a < 0 { -1 } else { 0 }),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                        return Err("division by zero".into());
+                    }
+                    BinaryOp::Div if checked => a.checked_div(b).ok_or_else(overflow)?,
+                    BinaryOp::Rem if checked => a.checked_rem(b).ok_or_else(overflow)?,
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::Rem => a.wrapping_rem(b),
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                })
+            }
+        }
+    }
+
+    // fails if a name is not defined by env, without evaluating anything
+    pub fn check_names(&self, env: &dyn Env) -> Result<(), String> {
+        match self {
+            Expr::Number(_) => Ok(()),
+            Expr::Name(name) => match env.lookup(name) {
+                Some(_) => Ok(()),
+                None => Err(format!("unknown name `{}`", name)),
+            },
+            Expr::Mem8(e) | Expr::Mem16(e) | Expr::Hi(e) | Expr::Lo(e) | Expr::Unary(_, e) => {
+                e.check_names(env)
+            }
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.check_names(env)?;
+                rhs.check_names(env)
+            }
+        }
+    }
+
+    // whether evaluating needs memory, which only a running machine has
+    pub fn reads_memory(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Name(_) => false,
+            Expr::Mem8(_) | Expr::Mem16(_) => true,
+            Expr::Hi(e) | Expr::Lo(e) | Expr::Unary(_, e) => e.reads_memory(),
+            Expr::Binary(_, lhs, rhs) => lhs.reads_memory() || rhs.reads_memory(),
+        }
+    }
+}
+
+fn address(value: i64) -> Result<u16, String> {
+    u16::try_from(value).map_err(|_| format!("{} is not an address", value))
+}
+
+// a parsed expression together with the text it came from, for display
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    pub source: String,
+    pub expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(src: &str) -> Result<Condition, String> {
+        Ok(Condition {
+            source: src.trim().to_string(),
+            expr: Expr::parse(src)?,
+        })
+    }
+
+    // true when the expression is non-zero
+    pub fn holds(&self, env: &dyn Env) -> Result<bool, String> {
+        Ok(self.expr.eval(env)? != 0)
+    }
+}
+
+// operators, longest first so >= is not read as > =
+const OPERATORS: [&str; 24] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "+", "-", "*", "/", "%", "&", "|",
+    "^", "!", "~", "(", ")", "[", "]",
+];
+
+fn tokenize(src: &str) -> Result<Vec<String>, String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut rest = src.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_ascii_alphanumeric() || c == '_' {
+            rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len())
+        } else {
+            OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .map(|op| op.len())
+                .ok_or(format!("unexpected `{}`", c))?
+        };
+
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// binary operators by precedence, loosest first
+const LEVELS: [&[(&str, BinaryOp)]; 10] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+    ],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+// how deeply subexpressions may nest, so the recursive parser and
+// evaluator stay well inside the stack; a debug build at this depth fits in
+// a quarter of a test thread's 2 MiB
+const MAX_DEPTH: usize = 128;
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+    depth: usize, // subexpressions open around the current token
+}
+
+impl Parser {
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        match self.depth > MAX_DEPTH {
+            true => Err("expression nested too deeply".to_string()),
+            false => Ok(()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        match self.next()? {
+            t if t == token => Ok(()),
+            t => Err(format!("expected `{}`, found `{}`", token, t)),
+        }
+    }
+
+    // left-associative binary operators at precedence level and tighter,
+    // by precedence climbing: a chain of operators is a loop, and only a
+    // tighter operator on the right recurses
+    fn expr(&mut self, level: usize) -> Result<Expr, String> {
+        let mut lhs = self.unary()?;
+        let depth = self.depth;
+
+        // each operator nests what came before it one level deeper
+        while let Some((op, op_level)) = self.binary_op(level) {
+            self.pos += 1;
+            self.enter()?;
+            let rhs = self.expr(op_level + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        self.depth = depth;
+        Ok(lhs)
+    }
+
+    // the operator at the next token and its level, if it binds at least
+    // as tightly as level
+    fn binary_op(&self, level: usize) -> Option<(BinaryOp, usize)> {
+        let token = self.peek()?;
+        (level..LEVELS.len()).find_map(|l| {
+            LEVELS[l]
+                .iter()
+                .find(|(s, _)| *s == token)
+                .map(|(_, op)| (*op, l))
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        let op = match self.peek() {
+            Some("!") => UnaryOp::Not,
+            Some("-") => UnaryOp::Neg,
+            Some("~") => UnaryOp::BitNot,
+            _ => return self.primary(),
+        };
+
+        self.pos += 1;
+        self.enter()?;
+        let operand = self.unary()?;
+        self.depth -= 1;
+        Ok(Expr::Unary(op, Box::new(operand)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.next()?;
+        self.enter()?;
+        let expr = self.operand(token);
+        self.depth -= 1;
+        expr
+    }
+
+    // a parenthesised expression, memory read, call, number or name
+    fn operand(&mut self, token: String) -> Result<Expr, String> {
+        match token.as_str() {
+            "(" => {
+                let expr = self.expr(0)?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            "mem8" | "mem16" => {
+                self.expect("[")?;
+                let addr = Box::new(self.expr(0)?);
+                self.expect("]")?;
+                Ok(match token.as_str() {
+                    "mem8" => Expr::Mem8(addr),
+                    _ => Expr::Mem16(addr),
+                })
+            }
+            // only calls; hi and lo on their own are plain names
+            "hi" | "lo" if self.peek() == Some("(") => {
+                self.pos += 1;
+                let value = Box::new(self.expr(0)?);
+                self.expect(")")?;
+                Ok(match token.as_str() {
+                    "hi" => Expr::Hi(value),
+                    _ => Expr::Lo(value),
+                })
+            }
+            t if t.starts_with(|c: char| c.is_ascii_digit()) => parse_number(t).map(Expr::Number),
+            t if t.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') => {
+                Ok(Expr::Name(token))
+            }
+            t => Err(format!("unexpected `{}`", t)),
+        }
+    }
+}
+
+fn parse_number(token: &str) -> Result<i64, String> {
+    let parsed = match token.strip_prefix("0x").or(token.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => token.parse(),
+    };
+
+    parsed.map_err(|_| format!("invalid number `{}`", token))
+}
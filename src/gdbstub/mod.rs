@@ -0,0 +1,505 @@
+// src/gdbstub/mod.rs
+//
+// GDB remote serial protocol stub, so existing debugger frontends can drive
+// the machine over a localhost TCP socket.
+//
+// Registers are numbered r0-r15 (0-15), pc (16) and flags (17, bit 0 zero,
+// bit 1 carry). Every register is 16 bits and travels big-endian like the
+// machine itself. The layout is published as target.xml through
+// qXfer:features:read.
+//
+// Stop replies:
+//   S05  stopped after a step or on a breakpoint
+//   T05watch:addr;   stopped by a watchpoint on a write to addr, rwatch
+//                    for a read
+//   S02  interrupted by the client
+//   S0B  the program faulted, the machine stays inspectable
+//   W00  the program halted
+
+use crate::vm::{State, VM};
+use crate::watchpoints::{Access, Action, Event, Target, WatchHit};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+pub const TARGET_XML: &str = include_str!("target.xml");
+
+const REGISTER_COUNT: usize = 18;
+const PC: usize = 16;
+const FLAGS: usize = 17;
+
+// steps between checks for an interrupt from the client while continuing
+const POLL_INTERVAL: u64 = 4096;
+
+pub struct GdbStub {
+    pub vm: VM,
+    ack: bool, // false once the client asked for QStartNoAckMode
+    done: bool,
+}
+
+impl GdbStub {
+    pub fn new(vm: VM) -> Self {
+        Self {
+            vm,
+            ack: true,
+            done: false,
+        }
+    }
+
+    // true once the client detached or killed the program
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // waits for one client on listener and serves it until it goes away
+    pub fn listen(&mut self, listener: &TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+
+    pub fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+
+        while !self.done {
+            let packet = match read_packet(&mut stream, self.ack)? {
+                Some(packet) => packet,
+                None => break,
+            };
+
+            let reply = {
+                let mut interrupted = || poll_interrupt(&mut stream);
+                self.handle(&packet, &mut interrupted)
+            };
+
+            if let Some(reply) = reply {
+                self.send(&mut stream, &reply)?;
+            }
+
+            // the OK above is still acked, nothing after it is
+            if packet == "QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+
+        Ok(())
+    }
+
+    // frames a reply and, unless acks are off, resends it until the client takes it
+    fn send(&self, stream: &mut TcpStream, reply: &str) -> io::Result<()> {
+        let framed = frame(reply);
+
+        loop {
+            stream.write_all(framed.as_bytes())?;
+            if !self.ack {
+                return Ok(());
+            }
+
+            match read_byte(stream)? {
+                Some(b'-') => continue,
+                Some(_) => return Ok(()),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // answers one packet, None when the client expects no reply. interrupted
+    // is polled while the machine runs freely
+    pub fn handle(
+        &mut self,
+        packet: &str,
+        interrupted: &mut dyn FnMut() -> bool,
+    ) -> Option<String> {
+        let (command, args) = match packet.char_indices().nth(1) {
+            Some((i, _)) => packet.split_at(i),
+            None => (packet, ""),
+        };
+
+        let reply = match command {
+            "?" => self.stop_reply(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" => self.step(args),
+            "c" => self.cont(args, interrupted),
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" => "OK".to_string(),
+            "q" | "Q" => self.query(packet),
+            "D" => {
+                self.done = true;
+                "OK".to_string()
+            }
+            "k" => {
+                self.done = true;
+                return None;
+            }
+            _ => String::new(),
+        };
+
+        Some(reply)
+    }
+
+    fn stop_reply(&self) -> String {
+        match (self.vm.state, &self.vm.fault) {
+            (State::RUNNING, _) => "S05".to_string(),
+            (State::HALTED, Some(_)) => "S0B".to_string(),
+            (State::HALTED, None) => "W00".to_string(),
+        }
+    }
+
+    fn register(&self, n: usize) -> u16 {
+        match n {
+            PC => self.vm.cpu.pc,
+            FLAGS => self.vm.zero_flag as u16 | (self.vm.carry_flag as u16) << 1,
+            n => self.vm.cpu.registers[n],
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: u16) {
+        match n {
+            PC => self.vm.cpu.pc = value,
+            FLAGS => {
+                self.vm.zero_flag = value & 1 != 0;
+                self.vm.carry_flag = value & 2 != 0;
+            }
+            n => self.vm.cpu.registers[n] = value,
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        (0..REGISTER_COUNT)
+            .map(|n| format!("{:04x}", self.register(n)))
+            .collect()
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let Some(bytes) = decode_hex(args).filter(|b| b.len() == REGISTER_COUNT * 2) else {
+            return "E01".to_string();
+        };
+
+        for n in 0..REGISTER_COUNT {
+            self.set_register(n, u16::from_be_bytes([bytes[n * 2], bytes[n * 2 + 1]]));
+        }
+        "OK".to_string()
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        match usize::from_str_radix(args, 16) {
+            Ok(n) if n < REGISTER_COUNT => format!("{:04x}", self.register(n)),
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let Some((n, value)) = args.split_once('=') else {
+            return "E01".to_string();
+        };
+
+        match (usize::from_str_radix(n, 16), decode_hex(value)) {
+            (Ok(n), Some(bytes)) if n < REGISTER_COUNT && bytes.len() == 2 => {
+                self.set_register(n, u16::from_be_bytes([bytes[0], bytes[1]]));
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // addr,length with both in hex, checked against the end of memory
+    fn memory_range(&self, args: &str) -> Option<(usize, usize)> {
+        let (addr, len) = args.split_once(',')?;
+        let addr = usize::from_str_radix(addr, 16).ok()?;
+        let len = usize::from_str_radix(len, 16).ok()?;
+
+        if addr.checked_add(len)? > self.vm.memory.data.len() {
+            return None;
+        }
+        Some((addr, len))
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        match self.memory_range(args) {
+            Some((addr, len)) => encode_hex(&self.vm.memory.data[addr..addr + len]),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let Some((range, data)) = args.split_once(':') else {
+            return "E01".to_string();
+        };
+
+        match (self.memory_range(range), decode_hex(data)) {
+            (Some((addr, len)), Some(bytes)) if bytes.len() == len => {
+                self.vm.memory.data[addr..addr + len].copy_from_slice(&bytes);
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    // Z0,addr,kind sets a software breakpoint, z0 removes it
+    // Z0 software breakpoints, Z2 write, Z3 read and Z4 access watchpoints
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+
+        let access = match fields.next() {
+            Some("0") => None,
+            Some("2") => Some(Access::Write),
+            Some("3") => Some(Access::Read),
+            Some("4") => Some(Access::Any),
+            // hardware breakpoints are not supported
+            _ => return String::new(),
+        };
+
+        let addr = match fields.next().map(|a| u16::from_str_radix(a, 16)) {
+            Some(Ok(addr)) => addr,
+            _ => return "E01".to_string(),
+        };
+
+        if let Some(access) = access {
+            let len = match fields.next().map(|l| u16::from_str_radix(l, 16)) {
+                Some(Ok(len)) => len,
+                _ => return "E01".to_string(),
+            };
+            return self.watchpoint(insert, addr, len, access);
+        }
+
+        if insert {
+            self.vm.breakpoints.insert(addr);
+        } else {
+            self.vm.breakpoints.remove(&addr);
+        }
+        "OK".to_string()
+    }
+
+    fn watchpoint(&mut self, insert: bool, start: u16, len: u16, access: Access) -> String {
+        let target = Target::Memory { start, len, access };
+        let watches = &mut self.vm.watchpoints;
+
+        if insert {
+            return match watches.watch_memory(start, len, access, Action::Stop) {
+                Ok(_) => "OK".to_string(),
+                Err(_) => "E01".to_string(),
+            };
+        }
+
+        let id = watches
+            .list()
+            .iter()
+            .find(|w| w.target == target)
+            .map(|w| w.id);
+        match id {
+            Some(id) => {
+                watches.remove(id);
+                "OK".to_string()
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn step(&mut self, args: &str) -> String {
+        if resume_at(&mut self.vm, args).is_err() {
+            return "E01".to_string();
+        }
+
+        self.vm.step();
+        match self.vm.watchpoints.take_stop() {
+            Some(hit) => watch_reply(&hit),
+            None => self.stop_reply(),
+        }
+    }
+
+    // runs until a breakpoint, halt, fault or interrupt, one step at a time
+    fn cont(&mut self, args: &str, interrupted: &mut dyn FnMut() -> bool) -> String {
+        if resume_at(&mut self.vm, args).is_err() {
+            return "E01".to_string();
+        }
+
+        let mut steps: u64 = 0;
+        while self.vm.state == State::RUNNING && self.vm.fuel != Some(0) {
+            self.vm.step();
+            steps += 1;
+
+            if let Some(hit) = self.vm.watchpoints.take_stop() {
+                return watch_reply(&hit);
+            }
+            if self.vm.state == State::RUNNING && self.vm.breakpoint_hit() {
+                break;
+            }
+            if steps.is_multiple_of(POLL_INTERVAL) && interrupted() {
+                return "S02".to_string();
+            }
+        }
+
+        self.stop_reply()
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return read_annex(TARGET_XML, args);
+        }
+
+        match packet {
+            "QStartNoAckMode" => "OK".to_string(),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+// s and c may carry the address to resume from
+fn resume_at(vm: &mut VM, args: &str) -> Result<(), ()> {
+    if !args.is_empty() {
+        vm.cpu.pc = u16::from_str_radix(args, 16).map_err(|_| ())?;
+    }
+    Ok(())
+}
+
+// framed as $data#checksum with }, $, # and * escaped
+pub fn frame(data: &str) -> String {
+    let mut escaped: Vec<u8> = Vec::new();
+    for b in data.bytes() {
+        match b {
+            b'}' | b'$' | b'#' | b'*' => escaped.extend_from_slice(&[b'}', b ^ 0x20]),
+            b => escaped.push(b),
+        }
+    }
+
+    let escaped = String::from_utf8_lossy(&escaped).to_string();
+    format!("${}#{:02x}", escaped, checksum(escaped.as_bytes()))
+}
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+// None once the client hung up
+fn read_byte(stream: &mut TcpStream) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match stream.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// reads the next well-formed packet, acking it unless acks are off.
+// stray acks and interrupts between packets are dropped
+fn read_packet(stream: &mut TcpStream, ack: bool) -> io::Result<Option<String>> {
+    loop {
+        match read_byte(stream)? {
+            Some(b'$') => {}
+            Some(_) => continue,
+            None => return Ok(None),
+        }
+
+        // the checksum covers the data as sent, before unescaping
+        let mut raw: Vec<u8> = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                Some(b'#') => break,
+                Some(b) => raw.push(b),
+                None => return Ok(None),
+            }
+        }
+
+        let mut sum = [0u8; 2];
+        stream.read_exact(&mut sum)?;
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|s| u8::from_str_radix(s, 16).ok())
+            == Some(checksum(&raw));
+
+        let mut data: Vec<u8> = Vec::new();
+        let mut bytes = raw.into_iter();
+        while let Some(b) = bytes.next() {
+            match b {
+                b'}' => data.extend(bytes.next().map(|b| b ^ 0x20)),
+                b => data.push(b),
+            }
+        }
+
+        if ack {
+            stream.write_all(if valid { b"+" } else { b"-" })?;
+        }
+        if valid {
+            return Ok(Some(String::from_utf8_lossy(&data).to_string()));
+        }
+    }
+}
+
+// the stop reply for a watchpoint; gdb has no kind for register watches
+fn watch_reply(hit: &WatchHit) -> String {
+    match hit.event {
+        Event::Write { addr } => format!("T05watch:{:x};", addr),
+        Event::Read { addr } => format!("T05rwatch:{:x};", addr),
+        Event::Register(_) => "S05".to_string(),
+    }
+}
+
+// true if the client sent ^C while the machine was running
+fn poll_interrupt(stream: &mut TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let mut byte = [0u8; 1];
+    let interrupted = match stream.read(&mut byte) {
+        Ok(1) => byte[0] == 0x03,
+        Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+        _ => false,
+    };
+
+    let _ = stream.set_nonblocking(false);
+    interrupted
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// offset,length window into an qXfer annex, m when more follows, l for the last part
+fn read_annex(annex: &str, args: &str) -> String {
+    let Some((offset, len)) = args.split_once(',') else {
+        return "E01".to_string();
+    };
+    let (Ok(offset), Ok(len)) = (
+        usize::from_str_radix(offset, 16),
+        usize::from_str_radix(len, 16),
+    ) else {
+        return "E01".to_string();
+    };
+
+    let Some(end) = offset.checked_add(len) else {
+        return "E01".to_string();
+    };
+
+    let bytes = annex.as_bytes();
+    let start = offset.min(bytes.len());
+    let end = end.min(bytes.len());
+    let chunk = String::from_utf8_lossy(&bytes[start..end]);
+
+    if end < bytes.len() {
+        format!("m{}", chunk)
+    } else {
+        format!("l{}", chunk)
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use std::fmt;
+
+#[derive(Clone, Copy, Debug)]
 pub enum Instruction {
     MovImm { reg: u8, imm: u16 },      // 0x01
     Mov { src_reg: u8, dest_reg: u8 }, // 0x02
@@ -10,5 +12,32 @@ pub enum Instruction {
     Jump { addr: u16 },                // 0x08
     JumpZ { addr: u16 },               // 0x09
     JumpNZ { addr: u16 },              // 0x0A
+    RdCycle { reg: u8 },               // 0x0B
+    RdInstret { reg: u8 },             // 0x0C
+    In { reg: u8, port: u8 },          // 0x0D
+    Out { port: u8, reg: u8 },         // 0x0E
     Halt,                              // 0xFF
 }
+
+// disassembly, in the syntax the assembler accepts
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::MovImm { reg, imm } => write!(f, "movimm r{} {:#06X}", reg, imm),
+            Instruction::Mov { src_reg, dest_reg } => write!(f, "mov r{} r{}", dest_reg, src_reg),
+            Instruction::Load { reg, addr } => write!(f, "load r{} {:#06X}", reg, addr),
+            Instruction::Store { addr, reg } => write!(f, "store {:#06X} r{}", addr, reg),
+            Instruction::Add { dest_reg, src_reg } => write!(f, "add r{} r{}", dest_reg, src_reg),
+            Instruction::Sub { dest_reg, src_reg } => write!(f, "sub r{} r{}", dest_reg, src_reg),
+            Instruction::Compare { reg_1, reg_2 } => write!(f, "cmp r{} r{}", reg_1, reg_2),
+            Instruction::Jump { addr } => write!(f, "jmp {:#06X}", addr),
+            Instruction::JumpZ { addr } => write!(f, "jmpz {:#06X}", addr),
+            Instruction::JumpNZ { addr } => write!(f, "jmpnz {:#06X}", addr),
+            Instruction::RdCycle { reg } => write!(f, "rdcycle r{}", reg),
+            Instruction::RdInstret { reg } => write!(f, "rdinstret r{}", reg),
+            Instruction::In { reg, port } => write!(f, "in r{} {:#04X}", reg, port),
+            Instruction::Out { port, reg } => write!(f, "out {:#04X} r{}", port, reg),
+            Instruction::Halt => write!(f, "halt"),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+// src/journal/mod.rs
+//
+// Undo journal for reverse execution. While a journal is attached to a VM,
+// every step records the state it is about to overwrite: the registers it
+// changed, flags, PC, run state, counters, device bookkeeping and the old
+// value of every memory byte it wrote. Undoing an entry puts all of that back.
+// The timer follows the host clock and is not rewound.
+//
+// Entries are kept newest last and the oldest are dropped once the journal
+// grows past its memory budget.
+
+use crate::vm::{State, VM};
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+#[derive(Clone)]
+pub struct JournalEntry {
+    pub pc: u16,
+    pub registers: Vec<(u8, u16)>, // register, value before the step
+    pub zero_flag: bool,
+    pub carry_flag: bool,
+    pub state: State,
+    pub fault: Option<String>,
+    pub fuel: Option<u64>,
+    pub instret: u64,
+    pub cycles: u64,
+    pub memory: Vec<(u16, u8)>, // address, byte before the step
+    pub input_read: Option<u8>, // console byte consumed by the step
+    pub output_len: usize,      // console output length before the step
+    pub rng_state: u64,
+    pub replay_position: usize, // how far into the record or replay log
+}
+
+impl JournalEntry {
+    // rough heap and inline footprint, used against the budget
+    fn size(&self) -> usize {
+        size_of::<JournalEntry>()
+            + self.registers.len() * size_of::<(u8, u16)>()
+            + self.memory.len() * size_of::<(u16, u8)>()
+            + self.fault.as_ref().map_or(0, |e| e.len())
+    }
+
+    // true if the step wrote the byte at addr
+    pub fn wrote(&self, addr: u16) -> bool {
+        self.memory.iter().any(|(a, _)| *a == addr)
+    }
+}
+
+#[derive(Clone)]
+pub struct Journal {
+    pub budget: usize, // bytes the entries may use before the oldest are dropped
+    entries: VecDeque<JournalEntry>,
+    used: usize,
+    writes: Vec<(u16, u8)>, // memory written by the step in progress
+}
+
+impl Journal {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            entries: VecDeque::new(),
+            used: 0,
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+        self.writes.clear();
+    }
+
+    // the most recent step, the one step_back would undo
+    pub fn last(&self) -> Option<&JournalEntry> {
+        self.entries.back()
+    }
+
+    // called for every memory byte the current step overwrites
+    pub fn record_write(&mut self, addr: u16, old: u8) {
+        self.writes.push((addr, old));
+    }
+
+    fn push(&mut self, entry: JournalEntry) {
+        self.used += entry.size();
+        self.entries.push_back(entry);
+
+        while self.used > self.budget {
+            match self.entries.pop_front() {
+                Some(old) => self.used -= old.size(),
+                None => break,
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Option<JournalEntry> {
+        let entry = self.entries.pop_back()?;
+        self.used -= entry.size();
+        Some(entry)
+    }
+}
+
+// captures the parts of the machine a step may change, before it runs
+pub(crate) fn begin(vm: &VM) -> (JournalEntry, [u16; 16], usize) {
+    let entry = JournalEntry {
+        pc: vm.cpu.pc,
+        registers: Vec::new(),
+        zero_flag: vm.zero_flag,
+        carry_flag: vm.carry_flag,
+        state: vm.state,
+        fault: vm.fault.clone(),
+        fuel: vm.fuel,
+        instret: vm.instret,
+        cycles: vm.cycles,
+        memory: Vec::new(),
+        input_read: vm.devices.console.input.front().copied(),
+        output_len: vm.devices.console.output.len(),
+        rng_state: vm.devices.rng_state,
+        replay_position: vm.devices.replay.position(),
+    };
+
+    (entry, vm.cpu.registers, vm.devices.console.input.len())
+}
+
+// completes the entry once the step has run and stores it
+pub(crate) fn finish(vm: &mut VM, started: (JournalEntry, [u16; 16], usize)) {
+    let (mut entry, registers, input_len) = started;
+
+    for (i, old) in registers.iter().enumerate() {
+        if vm.cpu.registers[i] != *old {
+            entry.registers.push((i as u8, *old));
+        }
+    }
+
+    // only keep the console byte if the step actually read it
+    if vm.devices.console.input.len() == input_len {
+        entry.input_read = None;
+    }
+
+    if let Some(journal) = vm.journal.as_mut() {
+        entry.memory = std::mem::take(&mut journal.writes);
+        journal.push(entry);
+    }
+}
+
+// undoes the most recent step, returns false if there is nothing to undo
+pub(crate) fn undo(vm: &mut VM) -> bool {
+    let entry = match vm.journal.as_mut().and_then(|j| j.pop()) {
+        Some(entry) => entry,
+        None => return false,
+    };
+
+    vm.cpu.pc = entry.pc;
+    for (reg, old) in entry.registers.iter() {
+        vm.cpu.registers[*reg as usize] = *old;
+    }
+    vm.zero_flag = entry.zero_flag;
+    vm.carry_flag = entry.carry_flag;
+    vm.state = entry.state;
+    vm.fault = entry.fault;
+    vm.fuel = entry.fuel;
+    vm.instret = entry.instret;
+    vm.cycles = entry.cycles;
+
+    // later writes are undone first so the oldest value wins
+    for (addr, old) in entry.memory.iter().rev() {
+        vm.memory.data[*addr as usize] = *old;
+    }
+
+    if let Some(byte) = entry.input_read {
+        vm.devices.console.input.push_front(byte);
+    }
+    vm.devices.console.output.truncate(entry.output_len);
+    vm.devices.rng_state = entry.rng_state;
+    vm.devices.replay.rewind(entry.replay_position);
+
+    true
+}
@@ -1,6 +1,25 @@
 pub mod assembler;
+pub mod batch;
+pub mod breakpoints;
+pub mod cost;
+pub mod coverage;
 pub mod cpu;
+pub mod dap;
+pub mod debugger;
 pub mod decoder;
+pub mod devices;
+pub mod expr;
+pub mod gdbstub;
 pub mod instructions;
+pub mod journal;
+pub mod json;
+pub mod memdump;
 pub mod memory;
+pub mod observer;
+pub mod profiler;
+pub mod recompiler;
+pub mod replay;
+pub mod snapshot;
+pub mod trace;
 pub mod vm;
+pub mod watchpoints;
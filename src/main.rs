@@ -1,29 +1,706 @@
-use risa16::assembler::assemble;
-use risa16::vm::{State, VM};
+use risa16::assembler::{Config, DebugInfo, ErrorFormat, assemble_file};
+use risa16::batch::{Job, JobConfig, run_batch};
+use risa16::cost::CostModel;
+use risa16::coverage::{Coverage, Lcov};
+use risa16::dap::DapServer;
+use risa16::debugger::Debugger;
+use risa16::gdbstub::GdbStub;
+use risa16::memdump;
+use risa16::profiler::Profiler;
+use risa16::recompiler::recompile;
+use risa16::replay::{self, Mode};
+use risa16::trace::{self, Format, Tracer};
+use risa16::vm::{ExitReason, VM};
 use std::env;
 use std::fs;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+const USAGE: &str = "Usage: risa16 [run] <file> [options]
+       risa16 resume <snapshot> [options]
+       risa16 debug <file> [--input file] [-I DIR] [-D NAME[=N]]
+       risa16 gdb <file> [--port N] [--input file]
+       risa16 dap
+       risa16 batch <dir> [--jobs N] [--max-steps N] [--cost-model table.txt]
+       risa16 recompile <file> [out.rs]
+       risa16 diff <a.snap> <b.snap> [--symbols file.asm]
+
+Options:
+  --max-steps N            stop after N instructions
+  --cost-model table.txt   per-instruction cycle costs
+  --input file             console input
+  --fill-byte N            byte for gaps left by .org and .align
+  -I DIR                   search DIR for .include and .incbin files;
+                           may be given more than once
+  -D NAME[=N]              define constant NAME as N, or 1, for .if and
+                           .ifdef; may be given more than once
+  --error-format F         text, with the source of each assembly error,
+                           or jsonl for one JSON object per error
+  --save-on-exit file      write a snapshot when execution stops
+  --record file            log every device read to a replay file
+  --replay file            feed device reads back from a replay file
+  --trace file             write a record of every step, JSON Lines for
+                           .jsonl files and text otherwise
+  --trace-format F         text or jsonl, overriding the file extension
+  --trace-only RANGE       trace only start..end, an address or a label;
+                           may be given more than once
+  --profile file           write where the time went, by label, loop and
+                           address
+  --profile-folded file    write folded stacks for flame graph tools
+  --coverage file          add line and branch coverage to an LCOV file
+  --coverage-listing file  write the source annotated with coverage
+  --dump-mem RANGE         print start..end, an address or a label of
+                           memory as a hexdump at exit
+  --dump-raw file          write the --dump-mem bytes to a file instead";
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|s| s.as_str()) {
+        Some("recompile") if args.len() == 2 || args.len() == 3 => {
+            recompile_file(&args[1], args.get(2))
+        }
+        Some("run") => run_file(&args[1..]),
+        Some("resume") => resume_file(&args[1..]),
+        Some("debug") => debug_file(&args[1..]),
+        Some("gdb") => serve_gdb(&args[1..]),
+        Some("dap") if args.len() == 1 => serve_dap(),
+        Some("batch") => run_dir(&args[1..]),
+        Some("diff") => diff_snapshots(&args[1..]),
+        Some(_) => run_file(&args),
+        None => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(1);
+}
+
+// options shared by run and resume
+struct RunOptions {
+    max_steps: Option<u64>,
+    cost_model: Option<String>,
+    input: Option<String>,
+    save_on_exit: Option<String>,
+    record: Option<String>,
+    replay: Option<String>,
+    trace: Option<String>,
+    trace_format: Option<Format>,
+    trace_only: Vec<String>,
+    profile: Option<String>,
+    profile_folded: Option<String>,
+    coverage: Option<String>,
+    coverage_listing: Option<String>,
+    dump_mem: Option<String>,
+    dump_raw: Option<String>,
+    assembler: Config,
+    error_format: ErrorFormat,
+}
+
+// splits args into the one positional argument and the run options
+fn parse_run_options(args: &[String]) -> (String, RunOptions) {
+    let mut path: Option<String> = None;
+    let mut options = RunOptions {
+        max_steps: None,
+        cost_model: None,
+        input: None,
+        save_on_exit: None,
+        record: None,
+        replay: None,
+        trace: None,
+        trace_format: None,
+        trace_only: Vec::new(),
+        profile: None,
+        profile_folded: None,
+        coverage: None,
+        coverage_listing: None,
+        dump_mem: None,
+        dump_raw: None,
+        assembler: Config::default(),
+        error_format: ErrorFormat::Text,
+    };
 
-    if args.len() != 2 {
-        eprintln!("Usage: risa16 <file>");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-steps" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                options.max_steps = Some(n.parse().unwrap_or_else(|_| usage()));
+            }
+            "--cost-model" => {
+                options.cost_model = Some(args.next().unwrap_or_else(|| usage()).clone())
+            }
+            "--fill-byte" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                let byte = match n.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => n.parse(),
+                };
+                options.assembler.fill = byte.unwrap_or_else(|_| usage());
+            }
+            "-I" => {
+                let dir = args.next().unwrap_or_else(|| usage());
+                options.assembler.include_paths.push(dir.into());
+            }
+            "-D" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                let define = parse_define(spec).unwrap_or_else(|| usage());
+                options.assembler.defines.push(define);
+            }
+            "--input" => options.input = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--save-on-exit" => {
+                options.save_on_exit = Some(args.next().unwrap_or_else(|| usage()).clone())
+            }
+            "--record" => options.record = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--replay" => options.replay = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--trace" => options.trace = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--trace-format" => {
+                options.trace_format = match args.next().map(|s| s.as_str()) {
+                    Some("text") => Some(Format::Text),
+                    Some("jsonl") => Some(Format::JsonLines),
+                    _ => usage(),
+                }
+            }
+            "--error-format" => {
+                options.error_format = match args.next().map(|s| s.as_str()) {
+                    Some("text") => ErrorFormat::Text,
+                    Some("jsonl") => ErrorFormat::Json,
+                    _ => usage(),
+                }
+            }
+            "--profile" => options.profile = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--profile-folded" => {
+                options.profile_folded = Some(args.next().unwrap_or_else(|| usage()).clone())
+            }
+            "--coverage" => options.coverage = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--coverage-listing" => {
+                options.coverage_listing = Some(args.next().unwrap_or_else(|| usage()).clone())
+            }
+            "--dump-mem" => options.dump_mem = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--dump-raw" => options.dump_raw = Some(args.next().unwrap_or_else(|| usage()).clone()),
+            "--trace-only" => options
+                .trace_only
+                .push(args.next().unwrap_or_else(|| usage()).clone()),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg.clone()),
+            _ => usage(),
+        }
+    }
+
+    (path.unwrap_or_else(|| usage()), options)
+}
+
+// NAME=N, with N decimal or 0x hex and possibly negative, or NAME for 1
+fn parse_define(spec: &str) -> Option<(String, i64)> {
+    let Some((name, value)) = spec.split_once('=') else {
+        return Some((spec.to_string(), 1));
+    };
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, value),
+    };
+    let n = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some((name.to_string(), if negative { -n } else { n }))
+}
+
+// the program at path, or its errors printed to stderr and the process
+// ended
+fn assemble_or_exit(path: &str, config: &Config, format: ErrorFormat) -> (Vec<u8>, DebugInfo) {
+    assemble_file(Path::new(path), config).unwrap_or_else(|errors| {
+        eprint!("{}", errors.report(format));
+        std::process::exit(1);
+    })
+}
+
+fn run_file(args: &[String]) {
+    let (path, options) = parse_run_options(args);
+    let (bytecode, debug) = assemble_or_exit(&path, &options.assembler, options.error_format);
+    let src = fs::read_to_string(&path).expect("Failed to read source file");
+    let source = Source {
+        path,
+        text: src,
+        bytecode,
+        debug,
+    };
+
+    let mut vm = VM::new();
+    if let Err(e) = vm.load(&source.bytecode) {
+        eprintln!("Load failed: {}", e);
         std::process::exit(1);
     }
 
-    let src = fs::read_to_string(&args[1]).expect("Failed to read source file");
+    if let Some(input) = &options.input {
+        let input = fs::read(input).expect("Failed to read input file");
+        vm.devices.console.input = input.into_iter().collect();
+    }
 
-    let bytecode = assemble(&src).expect("Assembly failed");
+    execute(vm, &options, Some(&source));
+}
+
+fn resume_file(args: &[String]) {
+    let (path, options) = parse_run_options(args);
+    let snapshot = fs::read(&path).expect("Failed to read snapshot");
 
     let mut vm = VM::new();
-    vm.memory.data[..bytecode.len()].copy_from_slice(&bytecode);
+    if let Err(e) = vm.restore(&snapshot) {
+        eprintln!("Cannot resume from {}: {}", path, e);
+        std::process::exit(1);
+    }
 
-    while vm.state == State::RUNNING {
-        vm.step();
+    // extra input is queued after whatever the snapshot had left
+    if let Some(input) = &options.input {
+        let input = fs::read(input).expect("Failed to read input file");
+        vm.devices.console.input.extend(input);
+    }
+
+    // a snapshot has no source, so trace filters must be addresses
+    execute(vm, &options, None);
+}
+
+// the program a run was assembled from, absent when resuming a snapshot
+struct Source {
+    path: String,
+    text: String,
+    bytecode: Vec<u8>,
+    debug: DebugInfo,
+}
+
+// runs a loaded machine and reports how it went
+fn execute(mut vm: VM, options: &RunOptions, source: Option<&Source>) {
+    let no_debug = DebugInfo::default();
+    let debug = source.map_or(&no_debug, |s| &s.debug);
+
+    if (options.coverage.is_some() || options.coverage_listing.is_some()) && source.is_none() {
+        eprintln!("Coverage needs the program source, it cannot be used with resume");
+        std::process::exit(1);
+    }
+
+    if options.dump_raw.is_some() && options.dump_mem.is_none() {
+        usage();
+    }
+
+    if let Some(table) = &options.cost_model {
+        vm.cost_model = read_cost_model(table);
+    }
+
+    if options.record.is_some() && options.replay.is_some() {
+        usage();
+    }
+    if options.record.is_some() {
+        vm.devices.replay = Mode::Recording(Vec::new());
+    }
+    if let Some(path) = &options.replay {
+        let log = fs::read_to_string(path).expect("Failed to read replay file");
+        let events = replay::parse(&log).unwrap_or_else(|e| {
+            eprintln!("Invalid replay file: {}", e);
+            std::process::exit(1);
+        });
+        vm.devices.replay = Mode::Replaying { events, next: 0 };
+    }
+
+    let profiler = Arc::new(Mutex::new(Profiler::new()));
+    if options.profile.is_some() || options.profile_folded.is_some() {
+        vm.observers.attach(Box::new(profiler.clone()));
+    }
+
+    let coverage = Arc::new(Mutex::new(Coverage::new()));
+    if options.coverage.is_some() || options.coverage_listing.is_some() {
+        vm.observers.attach(Box::new(coverage.clone()));
+    }
+
+    let output_start = vm.devices.console.output.len();
+
+    let mut exit = match &options.trace {
+        Some(path) => run_traced(&mut vm, options, path, debug),
+        None => match options.max_steps {
+            Some(n) => vm.run_for(n),
+            None => vm.run(),
+        },
+    };
+
+    if let (Some(path), Mode::Recording(events)) = (&options.record, &vm.devices.replay) {
+        fs::write(path, replay::to_text(events)).expect("Failed to write replay file");
+    }
+
+    // a replayed run that halts early has diverged just as much as a bad read
+    let remaining = vm.devices.replay.remaining();
+    if exit == ExitReason::Halted && remaining > 0 {
+        exit = ExitReason::Faulted(format!(
+            "replay diverged: program halted with {} recorded reads left",
+            remaining
+        ));
+    }
+
+    let output = &vm.devices.console.output[output_start..];
+    std::io::stdout()
+        .write_all(output)
+        .expect("Failed to write console output");
+    if !output.is_empty() && !output.ends_with(b"\n") {
+        println!();
     }
 
     println!("Final registers:");
     for (i, r) in vm.cpu.registers.iter().enumerate() {
         println!("R{} = {:#06X}", i, r);
     }
+
+    println!("Instructions: {}", vm.instret);
+    println!("Cycles: {}", vm.cycles);
+    if vm.instret > 0 {
+        println!("CPI: {:.2}", vm.cycles as f64 / vm.instret as f64);
+    }
+
+    if let Some(spec) = &options.dump_mem {
+        dump_memory(&vm, spec, options.dump_raw.as_deref(), debug);
+    }
+
+    let profiler = profiler.lock().unwrap();
+    if let Some(path) = &options.profile {
+        let report = profiler.report(debug).to_text(20);
+        fs::write(path, report).expect("Failed to write profile");
+    }
+    if let Some(path) = &options.profile_folded {
+        fs::write(path, profiler.folded(debug)).expect("Failed to write profile");
+    }
+
+    if let Some(source) = source {
+        write_coverage(&coverage.lock().unwrap(), source, options);
+    }
+
+    if let Some(snap) = &options.save_on_exit {
+        fs::write(snap, vm.snapshot()).expect("Failed to write snapshot");
+    }
+
+    if exit != ExitReason::Halted {
+        // a trap leaves the PC on the instruction that raised it
+        match (&exit, debug.describe(vm.cpu.pc)) {
+            (ExitReason::Faulted(e), Some(location)) => eprintln!("{}: {}", location, e),
+            _ => eprintln!("Error at PC {:#06X}: {}", vm.cpu.pc, exit),
+        }
+        std::process::exit(1);
+    }
+}
+
+// merges this run into the LCOV file, if it exists, and writes the listing
+fn write_coverage(coverage: &Coverage, source: &Source, options: &RunOptions) {
+    let mut lcov = Lcov::new();
+    if let Some(path) = &options.coverage
+        && Path::new(path).exists()
+    {
+        let text = fs::read_to_string(path).expect("Failed to read coverage file");
+        lcov = Lcov::parse(&text).unwrap_or_else(|e| {
+            eprintln!("Invalid coverage file {}: {}", path, e);
+            std::process::exit(1);
+        });
+    }
+
+    // the main file under the name it was run as, included files under
+    // the names they were found by
+    for (file, name) in source.debug.files.iter().enumerate() {
+        let name = if file == 0 { &source.path } else { name };
+        lcov.add(name, &coverage.file(&source.debug, &source.bytecode, file));
+    }
+
+    if let Some(path) = &options.coverage {
+        fs::write(path, lcov.to_string()).expect("Failed to write coverage file");
+    }
+    if let Some(path) = &options.coverage_listing {
+        let listing = lcov.sources[&source.path].annotate(&source.text);
+        fs::write(path, listing).expect("Failed to write coverage listing");
+    }
+}
+
+fn dump_memory(vm: &VM, spec: &str, raw: Option<&str>, debug: &DebugInfo) {
+    let range = trace::parse_range(spec, debug).unwrap_or_else(|e| {
+        eprintln!("Invalid memory range: {}", e);
+        std::process::exit(1);
+    });
+    let start = *range.start();
+    let data = memdump::region(&vm.memory, range).unwrap_or_else(|e| {
+        eprintln!("Invalid memory range: {}", e);
+        std::process::exit(1);
+    });
+
+    match raw {
+        Some(path) => fs::write(path, data).expect("Failed to write memory dump"),
+        None => {
+            println!("Memory:");
+            print!("{}", memdump::hexdump(data, start));
+        }
+    }
+}
+
+fn run_traced(vm: &mut VM, options: &RunOptions, path: &str, debug: &DebugInfo) -> ExitReason {
+    let format = options.trace_format.unwrap_or(if path.ends_with(".jsonl") {
+        Format::JsonLines
+    } else {
+        Format::Text
+    });
+
+    let file = fs::File::create(path).expect("Failed to create trace file");
+    let mut tracer = Tracer::new(std::io::BufWriter::new(file), format);
+
+    for spec in options.trace_only.iter() {
+        let range = trace::parse_range(spec, debug).unwrap_or_else(|e| {
+            eprintln!("Invalid trace filter: {}", e);
+            std::process::exit(1);
+        });
+        tracer.only(range);
+    }
+
+    tracer
+        .run(vm, options.max_steps)
+        .expect("Failed to write trace file")
+}
+
+fn debug_file(args: &[String]) {
+    let mut path: Option<&String> = None;
+    let mut input: Option<&String> = None;
+    let mut config = Config::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => input = Some(args.next().unwrap_or_else(|| usage())),
+            "-I" => {
+                let dir = args.next().unwrap_or_else(|| usage());
+                config.include_paths.push(dir.into());
+            }
+            "-D" => {
+                let spec = args.next().unwrap_or_else(|| usage());
+                config
+                    .defines
+                    .push(parse_define(spec).unwrap_or_else(|| usage()));
+            }
+            _ if path.is_none() && !arg.starts_with("-") => path = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| usage());
+    let (bytecode, debug) = assemble_or_exit(path, &config, ErrorFormat::Text);
+
+    let mut debugger = Debugger::load(&bytecode, debug).unwrap_or_else(|e| {
+        eprintln!("Load failed: {}", e);
+        std::process::exit(1);
+    });
+
+    if let Some(input) = input {
+        let input = fs::read(input).expect("Failed to read input file");
+        debugger.vm.devices.console.input = input.into_iter().collect();
+    }
+
+    let stdin = std::io::stdin();
+    debugger
+        .repl(stdin.lock(), std::io::stdout())
+        .expect("Failed to talk to the terminal");
+}
+
+// waits for a gdb remote protocol client on localhost
+fn serve_gdb(args: &[String]) {
+    let mut path: Option<&String> = None;
+    let mut port: u16 = 1234;
+    let mut input: Option<&String> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                port = n.parse().unwrap_or_else(|_| usage());
+            }
+            "--input" => input = Some(args.next().unwrap_or_else(|| usage())),
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| usage());
+    let (bytecode, _) = assemble_or_exit(path, &Config::default(), ErrorFormat::Text);
+
+    let mut vm = VM::new();
+    if let Err(e) = vm.load(&bytecode) {
+        eprintln!("Load failed: {}", e);
+        std::process::exit(1);
+    }
+
+    if let Some(input) = input {
+        let input = fs::read(input).expect("Failed to read input file");
+        vm.devices.console.input = input.into_iter().collect();
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|e| {
+        eprintln!("Cannot listen on port {}: {}", port, e);
+        std::process::exit(1);
+    });
+    println!("Listening for gdb on 127.0.0.1:{}", port);
+
+    let mut stub = GdbStub::new(vm);
+    if let Err(e) = stub.listen(&listener) {
+        eprintln!("Connection failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// speaks the debug adapter protocol on stdin and stdout
+fn serve_dap() {
+    // requests are read on another thread, which a locked stdin cannot move to
+    let stdin = std::io::BufReader::new(std::io::stdin());
+    if let Err(e) = DapServer::new().serve(stdin, std::io::stdout()) {
+        eprintln!("Debug adapter failed: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// runs every .asm file in a directory as an independent job, feeding each
+// one the matching .in file as console input when there is one
+fn run_dir(args: &[String]) {
+    let mut dir: Option<&String> = None;
+    let mut workers: usize = thread_count();
+    let mut config = JobConfig::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--jobs" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                workers = n.parse().unwrap_or_else(|_| usage());
+            }
+            "--max-steps" => {
+                let n = args.next().unwrap_or_else(|| usage());
+                config.max_steps = n.parse().unwrap_or_else(|_| usage());
+            }
+            "--cost-model" => {
+                config.cost_model = read_cost_model(args.next().unwrap_or_else(|| usage()))
+            }
+            _ if dir.is_none() && !arg.starts_with("--") => dir = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let dir = dir.unwrap_or_else(|| usage());
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .expect("Failed to read directory")
+        .map(|entry| entry.expect("Failed to read directory entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "asm"))
+        .collect();
+    paths.sort();
+
+    let mut jobs: Vec<Job> = Vec::new();
+    let mut failed = false;
+
+    for path in paths.iter() {
+        let name = path.display().to_string();
+
+        let program = match assemble_file(path, &Config::default()) {
+            Ok((program, _)) => program,
+            Err(e) => {
+                println!("{}: assembly failed: {}", name, e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let input = fs::read(Path::new(path).with_extension("in")).unwrap_or_default();
+
+        jobs.push(Job {
+            name,
+            program,
+            config: config.clone(),
+            input,
+        });
+    }
+
+    for result in run_batch(&jobs, workers) {
+        println!(
+            "{}: {} after {} instructions, {} cycles, memory {:016x}",
+            result.name, result.exit, result.instret, result.cycles, result.memory_digest
+        );
+        let registers: Vec<String> = result
+            .registers
+            .iter()
+            .map(|r| format!("{:04X}", r))
+            .collect();
+        println!("  registers: {}", registers.join(" "));
+        if !result.output.is_empty() {
+            println!("  output: {:?}", String::from_utf8_lossy(&result.output));
+        }
+        if result.exit != ExitReason::Halted {
+            failed = true;
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+// prints the memory that differs between two snapshots, named after the
+// labels of an optional source file
+fn diff_snapshots(args: &[String]) {
+    let mut paths: Vec<&String> = Vec::new();
+    let mut symbols: Option<&String> = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--symbols" => symbols = Some(args.next().unwrap_or_else(|| usage())),
+            _ if paths.len() < 2 && !arg.starts_with("--") => paths.push(arg),
+            _ => usage(),
+        }
+    }
+    let [old, new] = paths[..] else { usage() };
+
+    let debug = match symbols {
+        Some(path) => assemble_or_exit(path, &Config::default(), ErrorFormat::Text).1,
+        None => DebugInfo::default(),
+    };
+
+    let old = fs::read(old).expect("Failed to read snapshot");
+    let new = fs::read(new).expect("Failed to read snapshot");
+    let changes = memdump::diff_snapshots(&old, &new).unwrap_or_else(|e| {
+        eprintln!("Cannot compare snapshots: {}", e);
+        std::process::exit(1);
+    });
+
+    match changes.is_empty() {
+        true => println!("Memory is identical"),
+        false => print!("{}", memdump::diff_to_text(&changes, &debug)),
+    }
+}
+
+fn thread_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+fn read_cost_model(path: &str) -> CostModel {
+    let table = fs::read_to_string(path).expect("Failed to read cost model");
+    CostModel::parse(&table).unwrap_or_else(|e| {
+        eprintln!("Invalid cost model: {}", e);
+        std::process::exit(1);
+    })
+}
+
+fn recompile_file(path: &str, out: Option<&String>) {
+    let (bytecode, _) = assemble_or_exit(path, &Config::default(), ErrorFormat::Text);
+
+    let rust = match recompile(&bytecode) {
+        Ok(rust) => rust,
+        Err(e) => {
+            eprintln!("Recompile failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match out {
+        Some(out) => fs::write(out, rust).expect("Failed to write output file"),
+        None => print!("{}", rust),
+    }
 }
@@ -0,0 +1,157 @@
+// src/memdump/mod.rs
+//
+// Memory dumps and diffs. hexdump formats a block of memory sixteen bytes to
+// a row, with the printable characters alongside:
+//
+//   0x0200  48 65 6C 6C 6F 00 00 00  00 00 00 00 00 00 00 00  |Hello...........|
+//   *
+//   0x0FF0  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 01  |................|
+//
+// A row equal to the one before it is printed once, then a `*` for the run.
+//
+// diff compares two memory states and returns each run of changed bytes
+// with its old and new contents. Given the program's debug info, a run is
+// named after the nearest label at or before it, as `label+offset`.
+
+use crate::assembler::DebugInfo;
+use crate::memory::Memory;
+use crate::vm::VM;
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+
+const ROW: usize = 16;
+
+// the bytes of range that lie in memory; ranges from labels run to the top
+// of the address space, so the end is clipped rather than rejected
+pub fn region(memory: &Memory, range: RangeInclusive<u16>) -> Result<&[u8], String> {
+    let (start, end) = (*range.start() as usize, *range.end() as usize);
+    if start >= memory.data.len() {
+        return Err(format!("address {:#06X} is out of bounds", start));
+    }
+    Ok(&memory.data[start..=end.min(memory.data.len() - 1)])
+}
+
+// data as hexdump rows, the first byte at address start
+pub fn hexdump(data: &[u8], start: u16) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&[u8]> = None;
+    let mut repeating = false;
+
+    for (i, row) in data.chunks(ROW).enumerate() {
+        let last = (i + 1) * ROW >= data.len();
+        if previous == Some(row) && row.len() == ROW && !last {
+            if !repeating {
+                writeln!(out, "*").unwrap();
+                repeating = true;
+            }
+            continue;
+        }
+        previous = Some(row);
+        repeating = false;
+
+        let addr = start as usize + i * ROW;
+        let mut hex = String::new();
+        for (j, byte) in row.iter().enumerate() {
+            if j == ROW / 2 {
+                hex.push(' ');
+            }
+            write!(hex, "{:02X} ", byte).unwrap();
+        }
+        let text: String = row
+            .iter()
+            .map(|b| match b {
+                0x20..=0x7E => *b as char,
+                _ => '.',
+            })
+            .collect();
+
+        writeln!(out, "{:#06X}  {:<49} |{}|", addr, hex, text).unwrap();
+    }
+
+    out
+}
+
+// a run of consecutive bytes that differ
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub start: u16,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+pub fn diff(old: &Memory, new: &Memory) -> Vec<Change> {
+    let mut changes: Vec<Change> = Vec::new();
+
+    for (addr, (a, b)) in old.data.iter().zip(new.data.iter()).enumerate() {
+        if a == b {
+            continue;
+        }
+        match changes.last_mut() {
+            Some(c) if c.start as usize + c.old.len() == addr => {
+                c.old.push(*a);
+                c.new.push(*b);
+            }
+            _ => changes.push(Change {
+                start: addr as u16,
+                old: vec![*a],
+                new: vec![*b],
+            }),
+        }
+    }
+
+    changes
+}
+
+// restores both snapshots and diffs their memory
+pub fn diff_snapshots(old: &[u8], new: &[u8]) -> Result<Vec<Change>, String> {
+    let (mut a, mut b) = (VM::new(), VM::new());
+    a.restore(old)?;
+    b.restore(new)?;
+    Ok(diff(&a.memory, &b.memory))
+}
+
+// the nearest label at or before addr, with the offset from it
+pub fn symbol_for(debug: &DebugInfo, addr: u16) -> Option<String> {
+    let (name, base) = debug
+        .symbols
+        .iter()
+        .filter(|(_, a)| **a <= addr)
+        .max_by(|(n1, a1), (n2, a2)| a1.cmp(a2).then(n2.cmp(n1)))?;
+
+    match addr - base {
+        0 => Some(name.clone()),
+        offset => Some(format!("{}+{:#X}", name, offset)),
+    }
+}
+
+// one line per change:
+//   0x0200 counter+0x2  2 bytes  00 05 -> 03 07
+pub fn diff_to_text(changes: &[Change], debug: &DebugInfo) -> String {
+    let hex = |bytes: &[u8]| -> String {
+        let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        bytes.join(" ")
+    };
+
+    let mut out = String::new();
+    for change in changes.iter() {
+        let mut line = format!("{:#06X}", change.start);
+        if let Some(symbol) = symbol_for(debug, change.start) {
+            write!(line, " {}", symbol).unwrap();
+        }
+
+        let count = match change.old.len() {
+            1 => "1 byte".to_string(),
+            n => format!("{} bytes", n),
+        };
+        writeln!(
+            out,
+            "{}  {}  {} -> {}",
+            line,
+            count,
+            hex(&change.old),
+            hex(&change.new)
+        )
+        .unwrap();
+    }
+    out
+}
@@ -1,7 +1,14 @@
+#[derive(Clone)]
 pub struct Memory {
     pub data: Vec<u8>, // your RAM stored as bytes
 }
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Memory {
     pub fn new() -> Self {
         // returns type Memory
@@ -9,4 +16,14 @@ impl Memory {
             data: vec![0; 4096],
         }
     }
+
+    // FNV-1a hash of the contents, cheap to compare between runs
+    pub fn digest(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for byte in self.data.iter() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
 }
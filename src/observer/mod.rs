@@ -0,0 +1,121 @@
+// src/observer/mod.rs
+//
+// Instrumentation hooks for tools built on the VM, such as tracers,
+// profilers and coverage. Observers attached to a VM are called as it runs:
+//
+//   on_fetch           before the instruction at pc is decoded
+//   on_execute_before  after decoding, before it runs
+//   on_execute_after   once it has retired, with the PC it ran at
+//   on_mem_read        a guest load, after the read
+//   on_mem_write       a guest store, after the write
+//   on_branch          a jump that retired, taken or not
+//   on_trap            a fault, with its message
+//
+// Every callback has a default that does nothing, so an observer only
+// implements what it needs. With no observers attached the VM only checks
+// that the list is empty. Observers belong to one machine: a cloned VM
+// starts without any.
+//
+// Observers must be Send so a VM can still move to another thread. To read
+// results back while the observer stays attached, attach an Arc<Mutex<T>>
+// and keep a clone of it.
+
+use crate::instructions::Instruction;
+use crate::vm::VM;
+use std::sync::{Arc, Mutex};
+
+pub trait Observer: Send {
+    fn on_fetch(&mut self, _vm: &VM, _pc: u16) {}
+
+    fn on_execute_before(&mut self, _vm: &VM, _pc: u16, _instr: &Instruction) {}
+
+    fn on_execute_after(&mut self, _vm: &VM, _pc: u16, _instr: &Instruction) {}
+
+    fn on_mem_read(&mut self, _vm: &VM, _addr: u16, _value: u16) {}
+
+    fn on_mem_write(&mut self, _vm: &VM, _addr: u16, _old: u16, _new: u16) {}
+
+    fn on_branch(&mut self, _vm: &VM, _pc: u16, _target: u16, _taken: bool) {}
+
+    fn on_trap(&mut self, _vm: &VM, _pc: u16, _message: &str) {}
+}
+
+impl<T: Observer> Observer for Arc<Mutex<T>> {
+    fn on_fetch(&mut self, vm: &VM, pc: u16) {
+        self.lock().unwrap().on_fetch(vm, pc);
+    }
+
+    fn on_execute_before(&mut self, vm: &VM, pc: u16, instr: &Instruction) {
+        self.lock().unwrap().on_execute_before(vm, pc, instr);
+    }
+
+    fn on_execute_after(&mut self, vm: &VM, pc: u16, instr: &Instruction) {
+        self.lock().unwrap().on_execute_after(vm, pc, instr);
+    }
+
+    fn on_mem_read(&mut self, vm: &VM, addr: u16, value: u16) {
+        self.lock().unwrap().on_mem_read(vm, addr, value);
+    }
+
+    fn on_mem_write(&mut self, vm: &VM, addr: u16, old: u16, new: u16) {
+        self.lock().unwrap().on_mem_write(vm, addr, old, new);
+    }
+
+    fn on_branch(&mut self, vm: &VM, pc: u16, target: u16, taken: bool) {
+        self.lock().unwrap().on_branch(vm, pc, target, taken);
+    }
+
+    fn on_trap(&mut self, vm: &VM, pc: u16, message: &str) {
+        self.lock().unwrap().on_trap(vm, pc, message);
+    }
+}
+
+#[derive(Default)]
+pub struct Observers {
+    list: Vec<(usize, Box<dyn Observer>)>,
+    next_id: usize,
+}
+
+// observers hold host state that cannot be shared, so a clone has none
+impl Clone for Observers {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    // observers are called in the order they were attached; returns an id
+    // for detach
+    pub fn attach(&mut self, observer: Box<dyn Observer>) -> usize {
+        self.next_id += 1;
+        self.list.push((self.next_id, observer));
+        self.next_id
+    }
+
+    pub fn detach(&mut self, id: usize) -> Option<Box<dyn Observer>> {
+        let i = self.list.iter().position(|(i, _)| *i == id)?;
+        Some(self.list.remove(i).1)
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    pub(crate) fn each<F: FnMut(&mut dyn Observer)>(&mut self, mut f: F) {
+        for (_, observer) in self.list.iter_mut() {
+            f(observer.as_mut());
+        }
+    }
+}
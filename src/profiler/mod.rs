@@ -0,0 +1,240 @@
+// src/profiler/mod.rs
+//
+// Instruction-level profiler. Attached to a VM as an observer, it counts how
+// often each address executes and the cycles it costs, and how often each
+// backward jump is taken. A report groups the counts by the label that
+// encloses each address (the nearest label at or before it), lists the
+// hottest loops, one per back-edge, and the hottest addresses.
+//
+// Folded stacks (`frame;frame count`, as flame graph tools read them) are
+// weighted by cycles. The machine has no calls yet, so every stack is a
+// single frame: the enclosing label.
+
+use crate::assembler::DebugInfo;
+use crate::instructions::Instruction;
+use crate::observer::Observer;
+use crate::vm::VM;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// name used for code before the first label
+const NO_LABEL: &str = "(start)";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub executions: u64,
+    pub cycles: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Profiler {
+    pub addresses: BTreeMap<u16, Counts>,
+    pub back_edges: BTreeMap<(u16, u16), u64>, // (jump, target), times taken
+    cycles_before: u64,
+}
+
+impl Observer for Profiler {
+    fn on_execute_before(&mut self, vm: &VM, _pc: u16, _instr: &Instruction) {
+        self.cycles_before = vm.cycles;
+    }
+
+    fn on_execute_after(&mut self, vm: &VM, pc: u16, _instr: &Instruction) {
+        let counts = self.addresses.entry(pc).or_default();
+        counts.executions += 1;
+        counts.cycles += vm.cycles - self.cycles_before;
+    }
+
+    fn on_branch(&mut self, _vm: &VM, pc: u16, target: u16, taken: bool) {
+        if taken && target <= pc {
+            *self.back_edges.entry((pc, target)).or_default() += 1;
+        }
+    }
+}
+
+// one line of the by-label or by-address table
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub counts: Counts,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loop {
+    pub start: u16, // the back-edge's target
+    pub end: u16,   // the jump itself
+    pub label: String,
+    pub iterations: u64,
+    pub cycles: u64, // spent between start and end, inclusive
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub total: Counts,
+    pub labels: Vec<Entry>,    // hottest first
+    pub loops: Vec<Loop>,      // hottest first
+    pub addresses: Vec<Entry>, // hottest first
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.addresses.clear();
+        self.back_edges.clear();
+    }
+
+    // the nearest label at or before addr
+    fn enclosing(debug: &DebugInfo, addr: u16) -> &str {
+        debug
+            .symbols
+            .iter()
+            .filter(|(_, a)| **a <= addr)
+            .max_by(|(n1, a1), (n2, a2)| a1.cmp(a2).then(n2.cmp(n1)))
+            .map_or(NO_LABEL, |(name, _)| name.as_str())
+    }
+
+    pub fn report(&self, debug: &DebugInfo) -> Report {
+        let mut total = Counts::default();
+        let mut labels: BTreeMap<&str, Counts> = BTreeMap::new();
+        let mut addresses: Vec<Entry> = Vec::new();
+
+        for (addr, counts) in self.addresses.iter() {
+            total.executions += counts.executions;
+            total.cycles += counts.cycles;
+
+            let label = labels.entry(Self::enclosing(debug, *addr)).or_default();
+            label.executions += counts.executions;
+            label.cycles += counts.cycles;
+
+            let name = match debug.line_for(*addr) {
+                Some(line) => format!("{:#06X} line {}", addr, line),
+                None => format!("{:#06X}", addr),
+            };
+            addresses.push(Entry {
+                name,
+                counts: *counts,
+            });
+        }
+
+        let mut labels: Vec<Entry> = labels
+            .into_iter()
+            .map(|(name, counts)| Entry {
+                name: name.to_string(),
+                counts,
+            })
+            .collect();
+
+        let mut loops: Vec<Loop> = self
+            .back_edges
+            .iter()
+            .map(|((end, start), iterations)| Loop {
+                start: *start,
+                end: *end,
+                label: Self::enclosing(debug, *start).to_string(),
+                iterations: *iterations,
+                cycles: self
+                    .addresses
+                    .range(*start..=*end)
+                    .map(|(_, c)| c.cycles)
+                    .sum(),
+            })
+            .collect();
+
+        // hottest first, ties in address or name order
+        labels.sort_by_key(|e| Reverse(e.counts.cycles));
+        addresses.sort_by_key(|e| Reverse(e.counts.cycles));
+        loops.sort_by_key(|l| Reverse(l.cycles));
+
+        Report {
+            total,
+            labels,
+            loops,
+            addresses,
+        }
+    }
+
+    // one `label cycles` line per label, for flame graph tools
+    pub fn folded(&self, debug: &DebugInfo) -> String {
+        let mut out = String::new();
+        for entry in self.report(debug).labels.iter() {
+            if entry.counts.cycles > 0 {
+                writeln!(out, "{} {}", entry.name, entry.counts.cycles).unwrap();
+            }
+        }
+        out
+    }
+}
+
+impl Report {
+    // the report as text, with at most top addresses listed
+    pub fn to_text(&self, top: usize) -> String {
+        let mut out = String::new();
+        let percent = |cycles: u64| match self.total.cycles {
+            0 => 0.0,
+            total => cycles as f64 * 100.0 / total as f64,
+        };
+
+        writeln!(
+            out,
+            "Total: {} instructions, {} cycles",
+            self.total.executions, self.total.cycles
+        )
+        .unwrap();
+
+        writeln!(out, "\nBy label:").unwrap();
+        writeln!(out, "{:>10} {:>7} {:>10}  label", "cycles", "%", "count").unwrap();
+        for entry in self.labels.iter() {
+            writeln!(
+                out,
+                "{:>10} {:>6.1}% {:>10}  {}",
+                entry.counts.cycles,
+                percent(entry.counts.cycles),
+                entry.counts.executions,
+                entry.name
+            )
+            .unwrap();
+        }
+
+        if !self.loops.is_empty() {
+            writeln!(out, "\nHottest loops:").unwrap();
+            writeln!(
+                out,
+                "{:>10} {:>7} {:>10}  loop",
+                "cycles", "%", "iterations"
+            )
+            .unwrap();
+            for l in self.loops.iter() {
+                writeln!(
+                    out,
+                    "{:>10} {:>6.1}% {:>10}  {} {:#06X}..{:#06X}",
+                    l.cycles,
+                    percent(l.cycles),
+                    l.iterations,
+                    l.label,
+                    l.start,
+                    l.end
+                )
+                .unwrap();
+            }
+        }
+
+        writeln!(out, "\nHottest addresses:").unwrap();
+        writeln!(out, "{:>10} {:>7} {:>10}  address", "cycles", "%", "count").unwrap();
+        for entry in self.addresses.iter().take(top) {
+            writeln!(
+                out,
+                "{:>10} {:>6.1}% {:>10}  {}",
+                entry.counts.cycles,
+                percent(entry.counts.cycles),
+                entry.counts.executions,
+                entry.name
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
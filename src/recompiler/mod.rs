@@ -0,0 +1,387 @@
+// src/recompiler/mod.rs
+//
+// Ahead-of-time translation of an assembled RISA16 image into Rust source.
+//
+// The image is loaded at address 0 exactly like the VM does, control flow is
+// recovered by following every reachable jump target and fall-through from
+// the entry point, and each basic block becomes one arm of a `match` on the
+// program counter. The generated `run` function works on the same `CPU`,
+// `Memory` and flag state as `VM::step` and leaves it in the same condition:
+// on `halt` or a fault the PC points at the instruction that stopped.
+//
+// Code is translated from the image as assembled, so programs that store
+// into their own instructions are not supported.
+
+use crate::decoder::decode;
+use crate::instructions::Instruction;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+// size of VM memory, anything past the image decodes as zeros
+const MEMORY_SIZE: usize = 0x1000;
+
+pub fn recompile(bytes: &[u8]) -> Result<String, String> {
+    if bytes.len() > MEMORY_SIZE {
+        return Err(format!(
+            "image is {} bytes but memory is only {} bytes",
+            bytes.len(),
+            MEMORY_SIZE
+        ));
+    }
+
+    let mut image: Vec<u8> = vec![0; MEMORY_SIZE];
+    image[..bytes.len()].copy_from_slice(bytes);
+
+    let leaders = find_leaders(&image);
+
+    let mut out = String::new();
+    writeln!(out, "// Generated by risa16::recompiler. Do not edit.").unwrap();
+    writeln!(out, "#![allow(unused, clippy::all)]").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "use risa16::cpu::CPU;").unwrap();
+    writeln!(out, "use risa16::memory::Memory;").unwrap();
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "pub fn run(cpu: &mut CPU, memory: &mut Memory, zero_flag: &mut bool, carry_flag: &mut bool) -> Result<(), String> {{"
+    )
+    .unwrap();
+    writeln!(out, "    loop {{").unwrap();
+    writeln!(out, "        match cpu.pc {{").unwrap();
+
+    for leader in leaders.iter() {
+        writeln!(out, "            {:#06X} => {{", leader).unwrap();
+        emit_block(&mut out, &image, *leader, &leaders)?;
+        writeln!(out, "            }}").unwrap();
+    }
+
+    writeln!(
+        out,
+        "            pc => return Err(format!(\"no recompiled block at {{:#06X}}\", pc)),"
+    )
+    .unwrap();
+    writeln!(out, "        }}").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    Ok(out)
+}
+
+// where control goes after an instruction
+enum Flow {
+    Next,        // falls through to the next instruction
+    Branch(u16), // conditional jump, taken or falls through
+    Goto(u16),   // unconditional jump
+    Stop,        // halt or fault
+}
+
+fn flow(instr: &Instruction, pc: u16) -> Flow {
+    match *instr {
+        Instruction::Halt => Flow::Stop,
+
+        Instruction::Jump { addr } | Instruction::JumpZ { addr } | Instruction::JumpNZ { addr }
+            if addr as usize >= MEMORY_SIZE =>
+        {
+            Flow::Stop
+        }
+
+        // the VM only advances the PC when execute left it untouched, so a
+        // jump to its own address behaves like a fall-through
+        Instruction::Jump { addr } | Instruction::JumpZ { addr } | Instruction::JumpNZ { addr }
+            if addr == pc =>
+        {
+            Flow::Next
+        }
+
+        Instruction::Jump { addr } => Flow::Goto(addr),
+        Instruction::JumpZ { addr } | Instruction::JumpNZ { addr } => Flow::Branch(addr),
+
+        _ if fault(instr).is_some() => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+// the error execute would raise for these operands, if any
+fn fault(instr: &Instruction) -> Option<&'static str> {
+    match *instr {
+        Instruction::MovImm { reg, .. } if reg >= 0x10 => Some("Register out of bounds"),
+
+        Instruction::Mov { src_reg, dest_reg }
+        | Instruction::Add { dest_reg, src_reg }
+        | Instruction::Sub { dest_reg, src_reg }
+            if src_reg >= 0x10 || dest_reg >= 0x10 =>
+        {
+            Some("Register out of bounds")
+        }
+
+        Instruction::RdCycle { reg }
+        | Instruction::RdInstret { reg }
+        | Instruction::In { reg, .. }
+        | Instruction::Out { reg, .. }
+            if reg >= 0x10 =>
+        {
+            Some("Register out of bounds")
+        }
+
+        Instruction::Compare { reg_1, reg_2 } if reg_1 >= 0x10 || reg_2 >= 0x10 => {
+            Some("Register out of bounds")
+        }
+
+        Instruction::Jump { addr } | Instruction::JumpZ { addr } | Instruction::JumpNZ { addr }
+            if addr as usize >= MEMORY_SIZE =>
+        {
+            Some("address out of bounds")
+        }
+
+        // memory is checked before the register, same as execute
+        Instruction::Load { addr, .. } | Instruction::Store { addr, .. }
+            if addr as usize + 1 >= MEMORY_SIZE =>
+        {
+            Some("address out of bounds")
+        }
+
+        Instruction::Load { reg, .. } | Instruction::Store { reg, .. } if reg >= 0x10 => {
+            Some("Register out of bounds")
+        }
+
+        _ => None,
+    }
+}
+
+fn find_leaders(image: &[u8]) -> BTreeSet<u16> {
+    let mut leaders: BTreeSet<u16> = BTreeSet::new();
+    let mut visited: HashSet<u16> = HashSet::new();
+    let mut worklist: Vec<u16> = vec![0];
+
+    leaders.insert(0);
+
+    while let Some(start) = worklist.pop() {
+        let mut pc = start;
+
+        // walk straight-line code until control leaves it
+        while visited.insert(pc) {
+            let decoded = match decode(image, pc) {
+                Ok(decoded) => decoded,
+                Err(_) => break,
+            };
+
+            let next = pc as usize + decoded.length as usize;
+
+            match flow(&decoded.instr, pc) {
+                Flow::Next => {}
+                Flow::Branch(addr) => {
+                    for target in [addr, next as u16] {
+                        if leaders.insert(target) {
+                            worklist.push(target);
+                        }
+                    }
+                    break;
+                }
+                Flow::Goto(addr) => {
+                    if leaders.insert(addr) {
+                        worklist.push(addr);
+                    }
+                    break;
+                }
+                Flow::Stop => break,
+            }
+
+            // running off the end of memory faults at the next fetch
+            if next >= MEMORY_SIZE {
+                leaders.insert(next as u16);
+                break;
+            }
+            pc = next as u16;
+        }
+    }
+
+    leaders
+}
+
+fn emit_block(
+    out: &mut String,
+    image: &[u8],
+    start: u16,
+    leaders: &BTreeSet<u16>,
+) -> Result<(), String> {
+    let mut pc = start;
+
+    loop {
+        let decoded = match decode(image, pc) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                emit_stop(out, pc, &format!("Err({:?}.to_string())", e));
+                return Ok(());
+            }
+        };
+
+        let instr = decoded.instr;
+        let next = pc as usize + decoded.length as usize;
+
+        if let Some(e) = fault(&instr) {
+            emit_stop(out, pc, &format!("Err({:?}.to_string())", e));
+            return Ok(());
+        }
+
+        emit_instr(out, &instr)?;
+
+        match flow(&instr, pc) {
+            Flow::Stop => {
+                // only halt reaches here, faults were handled above
+                emit_stop(out, pc, "Ok(())");
+                return Ok(());
+            }
+            Flow::Goto(addr) => {
+                writeln!(out, "                cpu.pc = {:#06X};", addr).unwrap();
+                return Ok(());
+            }
+            Flow::Branch(addr) => {
+                let cond = match instr {
+                    Instruction::JumpZ { .. } => "*zero_flag",
+                    _ => "!*zero_flag",
+                };
+                writeln!(out, "                if {} {{", cond).unwrap();
+                writeln!(out, "                    cpu.pc = {:#06X};", addr).unwrap();
+                writeln!(out, "                }} else {{").unwrap();
+                writeln!(out, "                    cpu.pc = {:#06X};", next).unwrap();
+                writeln!(out, "                }}").unwrap();
+                return Ok(());
+            }
+            Flow::Next => {}
+        }
+
+        // running into another block or off the end of memory
+        if next >= MEMORY_SIZE || leaders.contains(&(next as u16)) {
+            writeln!(out, "                cpu.pc = {:#06X};", next).unwrap();
+            return Ok(());
+        }
+        pc = next as u16;
+    }
+}
+
+fn emit_stop(out: &mut String, pc: u16, result: &str) {
+    writeln!(out, "                cpu.pc = {:#06X};", pc).unwrap();
+    writeln!(out, "                return {};", result).unwrap();
+}
+
+fn emit_instr(out: &mut String, instr: &Instruction) -> Result<(), String> {
+    let indent = "                ";
+
+    match *instr {
+        Instruction::MovImm { reg, imm } => {
+            writeln!(out, "{}cpu.registers[{}] = {:#06X};", indent, reg, imm).unwrap();
+        }
+
+        Instruction::Mov { src_reg, dest_reg } => {
+            // zero flag comes from the destination before the copy, as in execute
+            writeln!(
+                out,
+                "{}*zero_flag = cpu.registers[{}] == 0;",
+                indent, dest_reg
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}cpu.registers[{}] = cpu.registers[{}];",
+                indent, dest_reg, src_reg
+            )
+            .unwrap();
+        }
+
+        Instruction::Load { reg, addr } => {
+            writeln!(
+                out,
+                "{}cpu.registers[{}] = (memory.data[{:#06X}] as u16) << 8 | memory.data[{:#06X}] as u16;",
+                indent,
+                reg,
+                addr,
+                addr + 1
+            )
+            .unwrap();
+        }
+
+        Instruction::Store { addr, reg } => {
+            writeln!(
+                out,
+                "{}memory.data[{:#06X}] = (cpu.registers[{}] >> 8) as u8;",
+                indent, addr, reg
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}memory.data[{:#06X}] = cpu.registers[{}] as u8;",
+                indent,
+                addr + 1,
+                reg
+            )
+            .unwrap();
+        }
+
+        Instruction::Add { dest_reg, src_reg } => {
+            writeln!(
+                out,
+                "{}let sum = cpu.registers[{}] as u32 + cpu.registers[{}] as u32;",
+                indent, dest_reg, src_reg
+            )
+            .unwrap();
+            writeln!(out, "{}*carry_flag = sum >= 0x10000;", indent).unwrap();
+            writeln!(out, "{}cpu.registers[{}] = sum as u16;", indent, dest_reg).unwrap();
+            writeln!(out, "{}*zero_flag = sum as u16 == 0;", indent).unwrap();
+        }
+
+        Instruction::Sub { dest_reg, src_reg } => {
+            writeln!(
+                out,
+                "{}*carry_flag = cpu.registers[{}] < cpu.registers[{}];",
+                indent, dest_reg, src_reg
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}cpu.registers[{}] = cpu.registers[{}].wrapping_sub(cpu.registers[{}]);",
+                indent, dest_reg, dest_reg, src_reg
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}*zero_flag = cpu.registers[{}] == 0;",
+                indent, dest_reg
+            )
+            .unwrap();
+        }
+
+        Instruction::Compare { reg_1, reg_2 } => {
+            writeln!(
+                out,
+                "{}*zero_flag = cpu.registers[{}] == cpu.registers[{}];",
+                indent, reg_1, reg_2
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{}*carry_flag = cpu.registers[{}] < cpu.registers[{}];",
+                indent, reg_1, reg_2
+            )
+            .unwrap();
+        }
+
+        // the generated code keeps no cycle or instruction counters
+        Instruction::RdCycle { .. } => return Err("rdcycle cannot be recompiled".to_string()),
+        Instruction::RdInstret { .. } => {
+            return Err("rdinstret cannot be recompiled".to_string());
+        }
+
+        // nor any devices
+        Instruction::In { .. } | Instruction::Out { .. } => {
+            return Err("port I/O cannot be recompiled".to_string());
+        }
+
+        // control flow is emitted by the block itself
+        Instruction::Jump { .. }
+        | Instruction::JumpZ { .. }
+        | Instruction::JumpNZ { .. }
+        | Instruction::Halt => {}
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+// src/replay/mod.rs
+//
+// Record and replay of nondeterministic input. While recording, every value
+// the guest reads from a device port is logged together with the number of
+// instructions retired before the read. Replaying feeds the logged values
+// back instead of asking the devices, and any read that does not line up
+// with the log is reported as a divergence.
+//
+// Replay files are plain text, one read per line:
+//
+//   # risa16 replay v1
+//   <instret> <port> <value>
+
+use std::fmt::Write;
+
+pub const HEADER: &str = "# risa16 replay v1";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub instret: u64,
+    pub port: u8,
+    pub value: u16,
+}
+
+#[derive(Clone, Default)]
+pub enum Mode {
+    #[default]
+    Live,
+    Recording(Vec<Event>),
+    Replaying {
+        events: Vec<Event>,
+        next: usize,
+    },
+}
+
+impl Mode {
+    // how far into the log we are, used to rewind it on step_back
+    pub fn position(&self) -> usize {
+        match self {
+            Mode::Live => 0,
+            Mode::Recording(events) => events.len(),
+            Mode::Replaying { next, .. } => *next,
+        }
+    }
+
+    pub fn rewind(&mut self, position: usize) {
+        match self {
+            Mode::Live => {}
+            Mode::Recording(events) => events.truncate(position),
+            Mode::Replaying { next, .. } => *next = position,
+        }
+    }
+
+    // recorded reads the guest has not made yet
+    pub fn remaining(&self) -> usize {
+        match self {
+            Mode::Replaying { events, next } => events.len() - next,
+            _ => 0,
+        }
+    }
+}
+
+pub fn to_text(events: &[Event]) -> String {
+    let mut out = String::new();
+    writeln!(out, "{}", HEADER).unwrap();
+    for event in events.iter() {
+        writeln!(
+            out,
+            "{} {:#04X} {:#06X}",
+            event.instret, event.port, event.value
+        )
+        .unwrap();
+    }
+    out
+}
+
+pub fn parse(src: &str) -> Result<Vec<Event>, String> {
+    let mut lines = src.lines();
+
+    if lines.next().map(|l| l.trim()) != Some(HEADER) {
+        return Err("not a risa16 replay file".to_string());
+    }
+
+    let mut events: Vec<Event> = Vec::new();
+
+    for (i, line) in lines.enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() != 3 {
+            return Err(format!("line {}: expected `instret port value`", i + 2));
+        }
+
+        let instret: u64 = words[0]
+            .parse()
+            .map_err(|_| format!("line {}: invalid instruction count", i + 2))?;
+        let port = parse_hex(words[1])
+            .filter(|p| *p <= 0xFF)
+            .ok_or(format!("line {}: invalid port", i + 2))?;
+        let value = parse_hex(words[2]).ok_or(format!("line {}: invalid value", i + 2))?;
+
+        events.push(Event {
+            instret,
+            port: port as u8,
+            value,
+        });
+    }
+
+    Ok(events)
+}
+
+fn parse_hex(token: &str) -> Option<u16> {
+    let digits = token.strip_prefix("0x").or(token.strip_prefix("0X"))?;
+    u16::from_str_radix(digits, 16).ok()
+}
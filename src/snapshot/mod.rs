@@ -0,0 +1,205 @@
+// src/snapshot/mod.rs
+//
+// Versioned binary snapshots of a whole machine. A snapshot holds everything
+// needed to resume execution exactly where it stopped: registers, PC, flags,
+// run state, counters, memory and device state. Debugger settings such as
+// breakpoints, watchpoints, observers, the cost model, the undo journal and
+// record/replay mode belong to the host and are not saved.
+//
+// Layout, all integers big-endian like the machine itself:
+//
+//   magic        8 bytes  "RISA16SN"
+//   version      u16
+//   registers    16 x u16
+//   pc           u16
+//   flags        u8       bit 0 zero, bit 1 carry
+//   state        u8       0 running, 1 halted
+//   fault        u8 present, then u32 length + UTF-8 bytes
+//   fuel         u8 present, then u64
+//   instret      u64
+//   cycles       u64
+//   memory       u32 length + bytes
+//   console in   u32 length + bytes
+//   console out  u32 length + bytes
+//   rng state    u64
+//
+// The timer follows the host clock and restarts from zero on restore.
+
+use crate::vm::{State, VM};
+
+pub const MAGIC: &[u8; 8] = b"RISA16SN";
+pub const VERSION: u16 = 2;
+
+pub fn save(vm: &VM) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::new();
+
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_be_bytes());
+
+    for r in vm.cpu.registers.iter() {
+        out.extend_from_slice(&r.to_be_bytes());
+    }
+    out.extend_from_slice(&vm.cpu.pc.to_be_bytes());
+
+    out.push(vm.zero_flag as u8 | (vm.carry_flag as u8) << 1);
+    out.push(match vm.state {
+        State::RUNNING => 0,
+        State::HALTED => 1,
+    });
+
+    match &vm.fault {
+        Some(e) => {
+            out.push(1);
+            put_bytes(&mut out, e.as_bytes());
+        }
+        None => out.push(0),
+    }
+
+    match vm.fuel {
+        Some(fuel) => {
+            out.push(1);
+            out.extend_from_slice(&fuel.to_be_bytes());
+        }
+        None => out.push(0),
+    }
+
+    out.extend_from_slice(&vm.instret.to_be_bytes());
+    out.extend_from_slice(&vm.cycles.to_be_bytes());
+
+    put_bytes(&mut out, &vm.memory.data);
+
+    let input: Vec<u8> = vm.devices.console.input.iter().copied().collect();
+    put_bytes(&mut out, &input);
+    put_bytes(&mut out, &vm.devices.console.output);
+    out.extend_from_slice(&vm.devices.rng_state.to_be_bytes());
+
+    out
+}
+
+// restores machine state into vm, which is left untouched on error
+pub fn load(vm: &mut VM, bytes: &[u8]) -> Result<(), String> {
+    if !bytes.starts_with(MAGIC) {
+        return Err("not a RISA16 snapshot".to_string());
+    }
+
+    let mut reader = Reader {
+        bytes,
+        pos: MAGIC.len(),
+    };
+
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(format!(
+            "snapshot version {} is not supported (expected version {})",
+            version, VERSION
+        ));
+    }
+
+    let mut restored = vm.clone();
+
+    for r in restored.cpu.registers.iter_mut() {
+        *r = reader.u16()?;
+    }
+    restored.cpu.pc = reader.u16()?;
+
+    let flags = reader.u8()?;
+    restored.zero_flag = flags & 1 != 0;
+    restored.carry_flag = flags & 2 != 0;
+
+    restored.state = match reader.u8()? {
+        0 => State::RUNNING,
+        1 => State::HALTED,
+        other => return Err(format!("invalid run state {} in snapshot", other)),
+    };
+
+    restored.fault = match reader.u8()? {
+        0 => None,
+        _ => Some(
+            String::from_utf8(reader.bytes()?.to_vec())
+                .map_err(|_| "fault message in snapshot is not UTF-8")?,
+        ),
+    };
+
+    restored.fuel = match reader.u8()? {
+        0 => None,
+        _ => Some(reader.u64()?),
+    };
+
+    restored.instret = reader.u64()?;
+    restored.cycles = reader.u64()?;
+
+    let memory = reader.bytes()?;
+    if memory.len() != restored.memory.data.len() {
+        return Err(format!(
+            "snapshot memory is {} bytes but this machine has {}",
+            memory.len(),
+            restored.memory.data.len()
+        ));
+    }
+    restored.memory.data = memory.to_vec();
+    restored.devices.console.input = reader.bytes()?.iter().copied().collect();
+    restored.devices.console.output = reader.bytes()?.to_vec();
+    restored.devices.rng_state = reader.u64()? | 1;
+
+    if reader.pos != bytes.len() {
+        return Err("trailing bytes after snapshot".to_string());
+    }
+
+    // history from before the restore no longer applies
+    if let Some(journal) = restored.journal.as_mut() {
+        journal.clear();
+    }
+
+    // observers are host state a clone leaves behind, so they move across
+    restored.observers = std::mem::take(&mut vm.observers);
+    *vm = restored;
+    Ok(())
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+// bounds-checked cursor over the snapshot bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + n;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or("snapshot is truncated")?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(buf))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
@@ -0,0 +1,308 @@
+// src/trace/mod.rs
+//
+// Execution traces. A Tracer steps the VM one instruction at a time and
+// writes a record for each step: the instruction count, PC, raw bytes,
+// disassembly, the registers and flags it changed and the memory it wrote.
+// Records go to any Write, either as text for people or as JSON Lines for
+// tools:
+//
+//        3 0x000C  060001      sub r0 r1             r0=0x0009
+//   {"n":3,"pc":12,"bytes":"060001","asm":"sub r0 r1","regs":{"r0":9},"flags":{},"writes":[]}
+//
+// Writes are one entry per changed byte. A step that faults also carries the
+// trap message. Filters limit the records to address ranges; the machine
+// still runs every instruction.
+
+use crate::assembler::DebugInfo;
+use crate::decoder::decode;
+use crate::json::Json;
+use crate::observer::Observer;
+use crate::vm::{ExitReason, State, VM};
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    JsonLines,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub n: u64, // instructions retired before this one
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub asm: String,
+    pub registers: Vec<(u8, u16)>,  // register, new value
+    pub flags: Vec<(char, bool)>,   // 'Z' or 'C', new value
+    pub writes: Vec<(u16, u8, u8)>, // address, old byte, new byte
+    pub fault: Option<String>,
+}
+
+impl Record {
+    pub fn to_text(&self) -> String {
+        let bytes: String = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+
+        let mut changes: Vec<String> = Vec::new();
+        for (reg, value) in self.registers.iter() {
+            changes.push(format!("r{}={:#06X}", reg, value));
+        }
+        for (flag, value) in self.flags.iter() {
+            changes.push(format!("{}={}", flag, *value as u8));
+        }
+        for (addr, _, new) in self.writes.iter() {
+            changes.push(format!("[{:#06X}]={:#04X}", addr, new));
+        }
+        if let Some(fault) = &self.fault {
+            changes.push(format!("fault: {}", fault));
+        }
+
+        format!(
+            "{:>6} {:#06X}  {:<12}{:<22}{}",
+            self.n,
+            self.pc,
+            bytes,
+            self.asm,
+            changes.join(" ")
+        )
+        .trim_end()
+        .to_string()
+    }
+
+    pub fn to_json(&self) -> Json {
+        let bytes: String = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let registers: Vec<(String, Json)> = self
+            .registers
+            .iter()
+            .map(|(reg, value)| (format!("r{}", reg), (*value as u64).into()))
+            .collect();
+        let flags: Vec<(String, Json)> = self
+            .flags
+            .iter()
+            .map(|(flag, value)| (flag.to_string(), (*value).into()))
+            .collect();
+        let writes: Vec<Json> = self
+            .writes
+            .iter()
+            .map(|(addr, old, new)| {
+                Json::object(vec![
+                    ("addr", (*addr as u64).into()),
+                    ("old", (*old as u64).into()),
+                    ("new", (*new as u64).into()),
+                ])
+            })
+            .collect();
+
+        let mut fields: Vec<(String, Json)> = vec![
+            ("n".to_string(), self.n.into()),
+            ("pc".to_string(), (self.pc as u64).into()),
+            ("bytes".to_string(), bytes.into()),
+            ("asm".to_string(), self.asm.clone().into()),
+            ("regs".to_string(), Json::Object(registers)),
+            ("flags".to_string(), Json::Object(flags)),
+            ("writes".to_string(), writes.into()),
+        ];
+        if let Some(fault) = &self.fault {
+            fields.push(("fault".to_string(), fault.clone().into()));
+        }
+        Json::Object(fields)
+    }
+}
+
+pub struct Tracer<W: Write> {
+    out: W,
+    format: Format,
+    only: Vec<RangeInclusive<u16>>, // empty traces everything
+    stores: Arc<Mutex<Stores>>,     // attached while a step runs
+    pub records: u64,               // records written so far
+}
+
+// the guest stores of one step: address, old word, new word
+#[derive(Default)]
+struct Stores(Vec<(u16, u16, u16)>);
+
+impl Observer for Stores {
+    fn on_mem_write(&mut self, _vm: &VM, addr: u16, old: u16, new: u16) {
+        self.0.push((addr, old, new));
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            only: Vec::new(),
+            stores: Arc::new(Mutex::new(Stores::default())),
+            records: 0,
+        }
+    }
+
+    // limits the trace to instructions in range, may be called more than once
+    pub fn only(&mut self, range: RangeInclusive<u16>) {
+        self.only.push(range);
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    // executes one instruction, returns its record if it ran
+    pub fn capture(&mut self, vm: &mut VM) -> Option<Record> {
+        if vm.state == State::HALTED || vm.fuel == Some(0) {
+            return None;
+        }
+
+        let n = vm.instret;
+        let pc = vm.cpu.pc;
+        let registers = vm.cpu.registers;
+        let (zero, carry) = (vm.zero_flag, vm.carry_flag);
+
+        let (bytes, asm) = match decode(&vm.memory.data, pc) {
+            Ok(decoded) => {
+                let end = pc as usize + decoded.length as usize;
+                (
+                    vm.memory.data[pc as usize..end].to_vec(),
+                    decoded.instr.to_string(),
+                )
+            }
+            Err(_) => (
+                vm.memory
+                    .data
+                    .get(pc as usize)
+                    .copied()
+                    .into_iter()
+                    .collect(),
+                "???".to_string(),
+            ),
+        };
+
+        let id = vm.observers.attach(Box::new(self.stores.clone()));
+        vm.step();
+        vm.observers.detach(id);
+
+        let changed_registers = (0..16u8)
+            .filter(|r| registers[*r as usize] != vm.cpu.registers[*r as usize])
+            .map(|r| (r, vm.cpu.registers[r as usize]))
+            .collect();
+
+        let mut flags: Vec<(char, bool)> = Vec::new();
+        if zero != vm.zero_flag {
+            flags.push(('Z', vm.zero_flag));
+        }
+        if carry != vm.carry_flag {
+            flags.push(('C', vm.carry_flag));
+        }
+
+        // stores split into bytes, each with its value before the step and
+        // after it, in address order
+        let mut bytes_written: BTreeMap<u16, (u8, u8)> = BTreeMap::new();
+        for (addr, old, new) in self.stores.lock().unwrap().0.drain(..) {
+            for (addr, old, new) in [
+                (addr, (old >> 8) as u8, (new >> 8) as u8),
+                (addr.wrapping_add(1), old as u8, new as u8),
+            ] {
+                bytes_written.entry(addr).or_insert((old, new)).1 = new;
+            }
+        }
+        let writes = bytes_written
+            .into_iter()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(addr, (old, new))| (addr, old, new))
+            .collect();
+
+        Some(Record {
+            n,
+            pc,
+            bytes,
+            asm,
+            registers: changed_registers,
+            flags,
+            writes,
+            fault: vm.fault.clone(),
+        })
+    }
+
+    // executes one instruction and writes its record if the filters allow
+    pub fn step(&mut self, vm: &mut VM) -> io::Result<Option<Record>> {
+        let Some(record) = self.capture(vm) else {
+            return Ok(None);
+        };
+
+        if self.only.is_empty() || self.only.iter().any(|r| r.contains(&record.pc)) {
+            match self.format {
+                Format::Text => writeln!(self.out, "{}", record.to_text())?,
+                Format::JsonLines => writeln!(self.out, "{}", record.to_json())?,
+            }
+            self.records += 1;
+        }
+        Ok(Some(record))
+    }
+
+    // like VM::run_for, or VM::run without a limit, tracing every step;
+    // breakpoints are not checked
+    pub fn run(&mut self, vm: &mut VM, max_steps: Option<u64>) -> io::Result<ExitReason> {
+        let mut steps: u64 = 0;
+
+        loop {
+            if vm.state == State::HALTED {
+                self.out.flush()?;
+                return Ok(match &vm.fault {
+                    Some(e) => ExitReason::Faulted(e.clone()),
+                    None => ExitReason::Halted,
+                });
+            }
+
+            if max_steps == Some(steps) || vm.fuel == Some(0) {
+                self.out.flush()?;
+                return Ok(ExitReason::BudgetExhausted);
+            }
+
+            self.step(vm)?;
+            steps += 1;
+
+            if let Some(hit) = vm.watchpoints.take_stop() {
+                self.out.flush()?;
+                return Ok(ExitReason::WatchpointHit(hit));
+            }
+        }
+    }
+}
+
+// the addresses a filter names: `start..end` with end exclusive, a single
+// address, or a label, which covers everything up to the next label
+pub fn parse_range(spec: &str, debug: &DebugInfo) -> Result<RangeInclusive<u16>, String> {
+    let resolve = |token: &str| -> Result<u16, String> {
+        if let Ok(addr) = crate::assembler::parse_u16(token) {
+            return Ok(addr);
+        }
+        debug
+            .symbols
+            .get(token)
+            .copied()
+            .ok_or(format!("no label or address `{}`", token))
+    };
+
+    if let Some((start, end)) = spec.split_once("..") {
+        let (start, end) = (resolve(start)?, resolve(end)?);
+        if end <= start {
+            return Err(format!("empty range `{}`", spec));
+        }
+        return Ok(start..=end - 1);
+    }
+
+    let start = resolve(spec)?;
+    if !debug.symbols.contains_key(spec) {
+        return Ok(start..=start);
+    }
+
+    let end = debug
+        .symbols
+        .values()
+        .filter(|addr| **addr > start)
+        .min()
+        .map_or(u16::MAX, |next| next - 1);
+    Ok(start..=end)
+}
@@ -1,21 +1,71 @@
+use crate::breakpoints::Breakpoints;
+use crate::cost::CostModel;
 use crate::cpu::CPU;
 use crate::decoder::{DecodedInstruction, decode};
+use crate::devices::Devices;
 use crate::instructions::Instruction;
+use crate::journal::{self, Journal};
+use crate::observer::{Observer, Observers};
+use crate::watchpoints::{WatchHit, Watchpoints};
+use std::fmt;
 
 use crate::memory::Memory;
+use crate::snapshot;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum State {
     HALTED,
     RUNNING,
 }
 
+// why run, run_for or run_until handed control back
+#[derive(PartialEq, Debug, Clone)]
+pub enum ExitReason {
+    Halted,
+    Faulted(String),
+    BudgetExhausted,
+    BreakpointHit(u16),
+    WatchpointHit(WatchHit),
+    UserStop,
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitReason::Halted => write!(f, "halted"),
+            ExitReason::Faulted(e) => write!(f, "faulted: {}", e),
+            ExitReason::BudgetExhausted => write!(f, "step budget exhausted"),
+            ExitReason::BreakpointHit(addr) => write!(f, "breakpoint at {:#06X}", addr),
+            ExitReason::WatchpointHit(hit) => write!(f, "{}", hit),
+            ExitReason::UserStop => write!(f, "stopped"),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct VM {
     pub cpu: CPU,
     pub memory: Memory,
     pub zero_flag: bool,
     pub carry_flag: bool,
     pub state: State,
+    pub fault: Option<String>, // why execution halted, if it was an error
+    pub fuel: Option<u64>,     // instructions left to execute, None for no limit
+    pub breakpoints: Breakpoints, // addresses run stops at before executing
+    pub watchpoints: Watchpoints,
+    pub observers: Observers, // instrumentation hooks, not carried over by clone
+    pub cost_model: CostModel,
+    pub cycles: u64,  // simulated cycles spent so far
+    pub instret: u64, // instructions retired so far
+    pub devices: Devices,
+    pub journal: Option<Journal>, // undo history for step_back, None when not recording
+    stopped_at: Option<u16>,      // PC whose breakpoint the last run checked, until the PC moves
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
@@ -26,32 +76,262 @@ impl VM {
             zero_flag: false,
             carry_flag: false,
             state: State::RUNNING,
+            fault: None,
+            fuel: None,
+            breakpoints: Breakpoints::new(),
+            watchpoints: Watchpoints::new(),
+            observers: Observers::new(),
+            cost_model: CostModel::new(),
+            cycles: 0,
+            instret: 0,
+            devices: Devices::new(),
+            journal: None,
+            stopped_at: None,
+        }
+    }
+
+    // copies a program image into memory at address 0
+    pub fn load(&mut self, program: &[u8]) -> Result<(), String> {
+        if program.len() > self.memory.data.len() {
+            return Err(format!(
+                "program is {} bytes but memory is only {} bytes",
+                program.len(),
+                self.memory.data.len()
+            ));
+        }
+
+        self.memory.data[..program.len()].copy_from_slice(program);
+        Ok(())
+    }
+
+    // serialises the machine state, see the snapshot module for the format
+    pub fn snapshot(&self) -> Vec<u8> {
+        snapshot::save(self)
+    }
+
+    // replaces the machine state with a snapshot, leaving it untouched on error
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), String> {
+        snapshot::load(self, bytes)?;
+        self.stopped_at = None;
+        Ok(())
+    }
+
+    // runs until the program halts, faults or hits a breakpoint
+    pub fn run(&mut self) -> ExitReason {
+        self.run_inner(None, |_| false)
+    }
+
+    // like run but gives up after max_steps instructions
+    pub fn run_for(&mut self, max_steps: u64) -> ExitReason {
+        self.run_inner(Some(max_steps), |_| false)
+    }
+
+    // like run but also stops as soon as stop returns true, checked before each step
+    pub fn run_until<F: FnMut(&VM) -> bool>(&mut self, stop: F) -> ExitReason {
+        self.run_inner(None, stop)
+    }
+
+    // like run_for but executes the instruction at the PC even when a
+    // breakpoint is set on it, as a debugger's step does
+    pub fn step_for(&mut self, max_steps: u64) -> ExitReason {
+        let exit = self.run_from(Some(max_steps), |_| false, true);
+        self.stopped(&exit);
+        exit
+    }
+
+    // a breakpoint on the first PC counts, unless the last run stopped
+    // there and so has already reported it
+    fn run_inner<F: FnMut(&VM) -> bool>(&mut self, max_steps: Option<u64>, stop: F) -> ExitReason {
+        let resumed = self.stopped_at == Some(self.cpu.pc);
+        let exit = self.run_from(max_steps, stop, resumed);
+        self.stopped(&exit);
+        exit
+    }
+
+    // remembers the breakpoint a run reported or counted on its way out,
+    // so the next run steps over it rather than stopping or counting again
+    fn stopped(&mut self, exit: &ExitReason) {
+        match exit {
+            ExitReason::BreakpointHit(addr) => self.stopped_at = Some(*addr),
+            ExitReason::BudgetExhausted => self.stopped_at = Some(self.cpu.pc),
+            _ => {}
+        }
+    }
+
+    fn run_from<F: FnMut(&VM) -> bool>(
+        &mut self,
+        max_steps: Option<u64>,
+        mut stop: F,
+        resumed: bool,
+    ) -> ExitReason {
+        let mut steps: u64 = 0;
+
+        loop {
+            if self.state == State::HALTED {
+                return match &self.fault {
+                    Some(e) => ExitReason::Faulted(e.clone()),
+                    None => ExitReason::Halted,
+                };
+            }
+
+            if stop(self) {
+                return ExitReason::UserStop;
+            }
+
+            if !(resumed && steps == 0) && self.breakpoint_hit() {
+                return ExitReason::BreakpointHit(self.cpu.pc);
+            }
+
+            if max_steps == Some(steps) || self.fuel == Some(0) {
+                return ExitReason::BudgetExhausted;
+            }
+
+            self.step();
+            steps += 1;
+
+            if let Some(hit) = self.watchpoints.take_stop() {
+                return ExitReason::WatchpointHit(hit);
+            }
+        }
+    }
+
+    // counts an arrival at the breakpoint on the PC, if any, and evaluates
+    // its condition; true if execution should stop here
+    pub fn breakpoint_hit(&mut self) -> bool {
+        if !self.breakpoints.contains(&self.cpu.pc) {
+            return false;
+        }
+
+        // the condition reads the VM, so the breakpoints step aside meanwhile
+        let mut breakpoints = std::mem::take(&mut self.breakpoints);
+        let stop = breakpoints.check(self.cpu.pc, self);
+        self.breakpoints = breakpoints;
+        stop
+    }
+
+    // starts recording an undo journal that may use about budget bytes
+    pub fn enable_journal(&mut self, budget: usize) {
+        self.journal = Some(Journal::new(budget));
+    }
+
+    // undoes the last recorded step, returns false when there is no history left
+    pub fn step_back(&mut self) -> bool {
+        self.stopped_at = None;
+        journal::undo(self)
+    }
+
+    // steps back until the PC is addr, returns false if history ran out first
+    pub fn run_back_to(&mut self, addr: u16) -> bool {
+        while self.step_back() {
+            if self.cpu.pc == addr {
+                return true;
+            }
+        }
+        false
+    }
+
+    // steps back to just before the most recent write to addr, returns false
+    // if history ran out first
+    pub fn run_back_to_write(&mut self, addr: u16) -> bool {
+        loop {
+            let wrote = match self.journal.as_ref().and_then(|j| j.last()) {
+                Some(entry) => entry.wrote(addr),
+                None => return false,
+            };
+
+            self.step_back();
+            if wrote {
+                return true;
+            }
         }
     }
 
     pub fn step(&mut self) {
-        if self.state == State::HALTED {
+        // out of fuel, nothing more runs until it is topped up
+        if self.state == State::HALTED || self.fuel == Some(0) {
             return;
         }
 
+        // a stop from an earlier step has been reported or ignored by now
+        self.watchpoints.take_stop();
+        self.stopped_at = None;
+        let pc = self.cpu.pc;
+        let registers = self.cpu.registers;
+
+        match self.journal {
+            Some(_) => {
+                let started = journal::begin(self);
+                self.step_inner();
+                journal::finish(self, started);
+            }
+            None => self.step_inner(),
+        }
+
+        if self.watchpoints.watches_registers() {
+            let mut watchpoints = std::mem::take(&mut self.watchpoints);
+            watchpoints.registers_changed(self, pc, &registers, &self.cpu.registers);
+            self.watchpoints = watchpoints;
+        }
+    }
+
+    fn step_inner(&mut self) {
+        if let Some(fuel) = self.fuel {
+            self.fuel = Some(fuel - 1);
+        }
+
+        let pc = self.cpu.pc;
+        self.notify(|o, vm| o.on_fetch(vm, pc));
+
         let decoded: Result<DecodedInstruction, String> = decode(&self.memory.data, self.cpu.pc);
         match decoded {
             // because it might be an error
             Ok(instr) => {
                 let old_pc: u16 = self.cpu.pc;
+                let opcode: u8 = self.memory.data[old_pc as usize];
+
+                // work out the timing before execute consumes the instruction
+                let memory_accesses: u64 = match instr.instr {
+                    Instruction::Load { .. } | Instruction::Store { .. } => 1,
+                    _ => 0,
+                };
+                let branch_taken: bool = match instr.instr {
+                    Instruction::Jump { .. } => true,
+                    Instruction::JumpZ { .. } => self.zero_flag,
+                    Instruction::JumpNZ { .. } => !self.zero_flag,
+                    _ => false,
+                };
+
+                self.notify(|o, vm| o.on_execute_before(vm, old_pc, &instr.instr));
                 self.execute(instr.instr);
+
+                // faulting instructions never retire
+                if self.fault.is_some() {
+                    return;
+                }
+                self.instret += 1;
+                self.cycles = self.cycles.saturating_add(self.cost_model.cost(
+                    opcode,
+                    memory_accesses,
+                    branch_taken,
+                ));
+
+                self.notify(|o, vm| o.on_execute_after(vm, old_pc, &instr.instr));
+                if let Instruction::Jump { addr }
+                | Instruction::JumpZ { addr }
+                | Instruction::JumpNZ { addr } = instr.instr
+                {
+                    self.notify(|o, vm| o.on_branch(vm, old_pc, addr, branch_taken));
+                }
+
                 if self.state == State::HALTED {
                     return;
                 }
                 if old_pc == self.cpu.pc {
                     // if pc mutated in execute ie. if a jump occured we dont want to change it
-                    self.cpu.pc += instr.length as u16;
+                    self.cpu.pc += instr.length;
                 }
             }
-            Err(e) => {
-                eprintln!("Error: {}", e);
-                self.state = State::HALTED
-            }
+            Err(e) => self.fault(&e),
         }
     }
 
@@ -61,8 +341,7 @@ impl VM {
             Instruction::MovImm { reg, imm } => {
                 // out of bounds check
                 if reg >= 0x10 {
-                    eprintln!("Error: Register out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("Register out of bounds");
                     return;
                 }
 
@@ -73,8 +352,7 @@ impl VM {
             Instruction::Mov { src_reg, dest_reg } => {
                 // out of bounds check
                 if src_reg >= 0x10 || dest_reg >= 0x10 {
-                    eprintln!("Error: Register out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("Register out of bounds");
                     return;
                 }
 
@@ -88,8 +366,7 @@ impl VM {
             Instruction::Add { dest_reg, src_reg } => {
                 // out of bounds check
                 if src_reg >= 0x10 || dest_reg >= 0x10 {
-                    eprintln!("Error: Register out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("Register out of bounds");
                     return;
                 }
 
@@ -110,8 +387,7 @@ impl VM {
             Instruction::Sub { dest_reg, src_reg } => {
                 // out of bounds check
                 if src_reg >= 0x10 || dest_reg >= 0x10 {
-                    eprintln!("Error: Register out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("Register out of bounds");
                     return;
                 }
 
@@ -119,9 +395,9 @@ impl VM {
                 self.carry_flag =
                     self.cpu.registers[dest_reg as usize] < self.cpu.registers[src_reg as usize];
 
-                // do the addition in u32 so
+                // wrap on borrow, the carry flag above records it
                 let diff: u32 = (self.cpu.registers[dest_reg as usize] as u32)
-                    - (self.cpu.registers[src_reg as usize] as u32);
+                    .wrapping_sub(self.cpu.registers[src_reg as usize] as u32);
 
                 // R[dest_reg] = difference
                 self.cpu.registers[dest_reg as usize] = diff as u16;
@@ -133,8 +409,7 @@ impl VM {
             Instruction::Compare { reg_1, reg_2 } => {
                 // out of bounds check
                 if reg_1 >= 0x10 || reg_2 >= 0x10 {
-                    eprintln!("Error: Register out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("Register out of bounds");
                     return;
                 }
 
@@ -148,8 +423,7 @@ impl VM {
             Instruction::Jump { addr } => {
                 // out of bounds check
                 if addr >= 0x1000 {
-                    eprintln!("Error: address out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("address out of bounds");
                     return;
                 }
 
@@ -159,12 +433,11 @@ impl VM {
             Instruction::JumpZ { addr } => {
                 // out of bounds check
                 if addr >= 0x1000 {
-                    eprintln!("Error: address out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("address out of bounds");
                     return;
                 }
 
-                if self.zero_flag == true {
+                if self.zero_flag {
                     self.cpu.pc = addr;
                 }
             }
@@ -172,12 +445,11 @@ impl VM {
             Instruction::JumpNZ { addr } => {
                 // out of bounds check
                 if addr >= 0x1000 {
-                    eprintln!("Error: address out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("address out of bounds");
                     return;
                 }
 
-                if self.zero_flag == false {
+                if !self.zero_flag {
                     self.cpu.pc = addr;
                 }
             }
@@ -185,44 +457,141 @@ impl VM {
             Instruction::Store { addr, reg } => {
                 // out of bounds checks for memory and register
                 if addr >= 0x1000 || addr + 1 >= 0x1000 {
-                    eprintln!("Error: address out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("address out of bounds");
                     return;
                 }
 
                 if reg >= 0x10 {
-                    eprintln!("Error: Register out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("Register out of bounds");
                     return;
                 }
 
                 let reg_value: u16 = self.cpu.registers[reg as usize];
-                self.memory.data[addr as usize] = (reg_value >> 8) as u8; // move 8 bits to the right then u8 takes lowest 8 bytes
-                self.memory.data[(addr + 1) as usize] = reg_value as u8; // u8 will already take lower 8 bytes
+                self.write_word(addr, reg_value);
             }
 
             Instruction::Load { reg, addr } => {
                 // out of bounds checks for memory and register
                 if addr >= 0x1000 || addr + 1 >= 0x1000 {
-                    eprintln!("Error: address out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("address out of bounds");
                     return;
                 }
 
                 if reg >= 0x10 {
-                    eprintln!("Error: Register out of bounds");
-                    self.state = State::HALTED;
+                    self.fault("Register out of bounds");
                     return;
                 }
 
-                let memory_value: u16 = (self.memory.data[addr as usize] as u16) << 8
-                    | (self.memory.data[(addr + 1) as usize]) as u16;
+                let memory_value: u16 = self.read_word(addr);
                 self.cpu.registers[reg as usize] = memory_value;
             }
 
+            Instruction::RdCycle { reg } => {
+                if reg >= 0x10 {
+                    self.fault("Register out of bounds");
+                    return;
+                }
+
+                // counters are 64-bit, guests see the low 16 bits
+                self.cpu.registers[reg as usize] = self.cycles as u16;
+            }
+
+            Instruction::RdInstret { reg } => {
+                if reg >= 0x10 {
+                    self.fault("Register out of bounds");
+                    return;
+                }
+
+                self.cpu.registers[reg as usize] = self.instret as u16;
+            }
+
+            Instruction::In { reg, port } => {
+                if reg >= 0x10 {
+                    self.fault("Register out of bounds");
+                    return;
+                }
+
+                match self.devices.read(port, self.instret) {
+                    Ok(value) => self.cpu.registers[reg as usize] = value,
+                    Err(e) => self.fault(&e),
+                }
+            }
+
+            Instruction::Out { port, reg } => {
+                if reg >= 0x10 {
+                    self.fault("Register out of bounds");
+                    return;
+                }
+
+                let value: u16 = self.cpu.registers[reg as usize];
+                if let Err(e) = self.devices.write(port, value) {
+                    self.fault(&e);
+                }
+            }
+
             Instruction::Halt => {
                 self.state = State::HALTED;
             }
         }
     }
+
+    // every guest data access goes through read_word and write_word, so
+    // watchpoints see them all. addr has already been bounds checked
+    fn read_word(&mut self, addr: u16) -> u16 {
+        let value: u16 = (self.memory.data[addr as usize] as u16) << 8
+            | (self.memory.data[(addr + 1) as usize]) as u16;
+
+        if !self.watchpoints.is_empty() {
+            let mut watchpoints = std::mem::take(&mut self.watchpoints);
+            watchpoints.memory_access(self, self.cpu.pc, addr, false, value, value);
+            self.watchpoints = watchpoints;
+        }
+        self.notify(|o, vm| o.on_mem_read(vm, addr, value));
+        value
+    }
+
+    fn write_word(&mut self, addr: u16, value: u16) {
+        let old: u16 = (self.memory.data[addr as usize] as u16) << 8
+            | (self.memory.data[(addr + 1) as usize]) as u16;
+
+        self.write_byte(addr, (value >> 8) as u8); // move 8 bits to the right then u8 takes lowest 8 bytes
+        self.write_byte(addr + 1, value as u8); // u8 will already take lower 8 bytes
+
+        if !self.watchpoints.is_empty() {
+            let mut watchpoints = std::mem::take(&mut self.watchpoints);
+            watchpoints.memory_access(self, self.cpu.pc, addr, true, old, value);
+            self.watchpoints = watchpoints;
+        }
+        self.notify(|o, vm| o.on_mem_write(vm, addr, old, value));
+    }
+
+    // every guest memory write goes through here so it can be journaled
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.record_write(addr, self.memory.data[addr as usize]);
+        }
+        self.memory.data[addr as usize] = value;
+    }
+
+    // stops the machine and records why
+    fn fault(&mut self, e: &str) {
+        self.fault = Some(e.to_string());
+        self.state = State::HALTED;
+
+        let pc = self.cpu.pc;
+        self.notify(|o, vm| o.on_trap(vm, pc, e));
+    }
+
+    // calls f on every attached observer, which step aside meanwhile so
+    // they can be handed the VM; does nothing when none are attached
+    #[inline]
+    fn notify<F: FnMut(&mut dyn Observer, &VM)>(&mut self, mut f: F) {
+        if self.observers.is_empty() {
+            return;
+        }
+
+        let mut observers = std::mem::take(&mut self.observers);
+        observers.each(|o| f(o, self));
+        self.observers = observers;
+    }
 }
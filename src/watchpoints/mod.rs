@@ -0,0 +1,305 @@
+// src/watchpoints/mod.rs
+//
+// Watchpoints on memory ranges and registers. Every guest data access goes
+// through the VM's read_word and write_word, which report here, and register
+// watches compare the registers before and after each step. A hit is always
+// appended to the log; a watchpoint with the Stop action also ends run,
+// run_for and run_until with ExitReason::WatchpointHit once the instruction
+// that triggered it has finished.
+//
+// The log keeps the latest LOG_LIMIT hits; take_log hands them over and
+// starts afresh.
+//
+// A watchpoint may carry a condition, which sees the VM's names plus hits,
+// old and new, and only fires when it holds. hits counts every matching
+// access or change, this one included.
+
+use crate::expr::{Bindings, Condition, Env};
+use std::collections::VecDeque;
+use std::fmt;
+
+// hits kept in the log, older ones are dropped
+pub const LOG_LIMIT: usize = 1024;
+
+// which memory accesses a watchpoint fires on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    Any,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Stop,
+    Log,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Memory {
+        start: u16,
+        len: u16,
+        access: Access,
+    },
+    Register(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub target: Target,
+    pub action: Action,
+    pub condition: Option<Condition>,
+    pub hits: u64,             // matching accesses or changes so far
+    pub error: Option<String>, // why the condition last failed to evaluate
+}
+
+// what a hit saw happen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    Read { addr: u16 },
+    Write { addr: u16 },
+    Register(u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WatchHit {
+    pub id: usize,
+    pub pc: u16, // the instruction that made the access
+    pub event: Event,
+    pub old: u16,
+    pub new: u16, // same as old for reads
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.event {
+            Event::Read { addr } => write!(
+                f,
+                "watchpoint {}: read {:#06X} at PC {:#06X}, value {:#06X}",
+                self.id, addr, self.pc, self.old
+            ),
+            Event::Write { addr } => write!(
+                f,
+                "watchpoint {}: write {:#06X} at PC {:#06X}, {:#06X} -> {:#06X}",
+                self.id, addr, self.pc, self.old, self.new
+            ),
+            Event::Register(reg) => write!(
+                f,
+                "watchpoint {}: r{} changed at PC {:#06X}, {:#06X} -> {:#06X}",
+                self.id, reg, self.pc, self.old, self.new
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Watchpoints {
+    watches: Vec<Watchpoint>,
+    next_id: usize,
+    pub log: VecDeque<WatchHit>, // the latest hits, oldest first
+    stop: Option<WatchHit>,      // first stopping hit of the step in progress
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.watches
+    }
+
+    // empties the log, returning what it held
+    pub fn take_log(&mut self) -> Vec<WatchHit> {
+        self.log.drain(..).collect()
+    }
+
+    // watches len bytes from start, returns the new watchpoint's id
+    pub fn watch_memory(
+        &mut self,
+        start: u16,
+        len: u16,
+        access: Access,
+        action: Action,
+    ) -> Result<usize, String> {
+        if len == 0 {
+            return Err("a memory watchpoint must cover at least one byte".to_string());
+        }
+
+        Ok(self.add(Target::Memory { start, len, access }, action))
+    }
+
+    // watches a register for changes, returns the new watchpoint's id
+    pub fn watch_register(&mut self, reg: u8, action: Action) -> Result<usize, String> {
+        if reg >= 0x10 {
+            return Err("Register out of bounds".to_string());
+        }
+
+        Ok(self.add(Target::Register(reg), action))
+    }
+
+    // returns false if there was no watchpoint with that id
+    pub fn remove(&mut self, id: usize) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|w| w.id != id);
+        self.watches.len() != before
+    }
+
+    pub fn clear(&mut self) {
+        self.watches.clear();
+    }
+
+    // replaces a watchpoint's condition and resets its hit count, returns
+    // false if there was no watchpoint with that id
+    pub fn set_condition(&mut self, id: usize, condition: Option<Condition>) -> bool {
+        match self.watches.iter_mut().find(|w| w.id == id) {
+            Some(watch) => {
+                watch.condition = condition;
+                watch.hits = 0;
+                watch.error = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn add(&mut self, target: Target, action: Action) -> usize {
+        self.next_id += 1;
+        self.watches.push(Watchpoint {
+            id: self.next_id,
+            target,
+            action,
+            condition: None,
+            hits: 0,
+            error: None,
+        });
+        self.next_id
+    }
+
+    // counts a matching event for the watchpoint at index i and records the
+    // hit if its condition allows
+    fn hit(&mut self, i: usize, hit: WatchHit, env: &dyn Env) {
+        let watch = &mut self.watches[i];
+        watch.hits += 1;
+
+        if let Some(condition) = &watch.condition {
+            let names = [
+                ("hits", watch.hits as i64),
+                ("old", hit.old as i64),
+                ("new", hit.new as i64),
+            ];
+            match condition.holds(&Bindings { env, names: &names }) {
+                Ok(true) => watch.error = None,
+                Ok(false) => {
+                    watch.error = None;
+                    return;
+                }
+                Err(e) => watch.error = Some(e),
+            }
+        }
+
+        if watch.action == Action::Stop && self.stop.is_none() {
+            self.stop = Some(hit.clone());
+        }
+        if self.log.len() == LOG_LIMIT {
+            self.log.pop_front();
+        }
+        self.log.push_back(hit);
+    }
+
+    // a 2-byte access at addr by the instruction at pc
+    pub(crate) fn memory_access(
+        &mut self,
+        env: &dyn Env,
+        pc: u16,
+        addr: u16,
+        write: bool,
+        old: u16,
+        new: u16,
+    ) {
+        let end = addr as u32 + 2;
+
+        for i in 0..self.watches.len() {
+            let (id, target) = (self.watches[i].id, self.watches[i].target);
+
+            let Target::Memory { start, len, access } = target else {
+                continue;
+            };
+
+            let overlaps = (start as u32) < end && (addr as u32) < start as u32 + len as u32;
+            let wanted = match access {
+                Access::Read => !write,
+                Access::Write => write,
+                Access::Any => true,
+            };
+            if !overlaps || !wanted {
+                continue;
+            }
+
+            let event = match write {
+                true => Event::Write { addr },
+                false => Event::Read { addr },
+            };
+            self.hit(
+                i,
+                WatchHit {
+                    id,
+                    pc,
+                    event,
+                    old,
+                    new,
+                },
+                env,
+            );
+        }
+    }
+
+    // true if any register is watched, so the step needs to remember them
+    pub(crate) fn watches_registers(&self) -> bool {
+        self.watches
+            .iter()
+            .any(|w| matches!(w.target, Target::Register(_)))
+    }
+
+    pub(crate) fn registers_changed(
+        &mut self,
+        env: &dyn Env,
+        pc: u16,
+        before: &[u16; 16],
+        after: &[u16; 16],
+    ) {
+        for i in 0..self.watches.len() {
+            let (id, target) = (self.watches[i].id, self.watches[i].target);
+
+            let Target::Register(reg) = target else {
+                continue;
+            };
+
+            let (old, new) = (before[reg as usize], after[reg as usize]);
+            if old != new {
+                self.hit(
+                    i,
+                    WatchHit {
+                        id,
+                        pc,
+                        event: Event::Register(reg),
+                        old,
+                        new,
+                    },
+                    env,
+                );
+            }
+        }
+    }
+
+    // the stopping hit of the last step, if there was one
+    pub(crate) fn take_stop(&mut self) -> Option<WatchHit> {
+        self.stop.take()
+    }
+}
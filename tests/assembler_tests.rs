@@ -1,7 +1,12 @@
-use risa16::assembler::assemble;
+use risa16::assembler::{
+    AsmError, Config, ErrorFormat, assemble, assemble_file, assemble_named, assemble_with_config,
+    assemble_with_debug,
+};
 use risa16::decoder::decode;
 use risa16::instructions::Instruction;
-use risa16::vm::{State, VM};
+use risa16::vm::VM;
+use std::fs;
+use std::path::PathBuf;
 
 //
 // ---------- helpers ----------
@@ -13,13 +18,23 @@ fn run_program(src: &str) -> VM {
     let mut vm = VM::new();
     vm.memory.data[..bytes.len()].copy_from_slice(&bytes);
 
-    while vm.state == State::RUNNING {
-        vm.step();
-    }
+    vm.run();
 
     vm
 }
 
+// a fresh directory holding files, each a relative path and its contents
+fn source_dir(name: &str, files: &[(&str, &[u8])]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("risa16-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, contents) in files.iter() {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    dir
+}
+
 //
 // ---------- assembler → bytes ----------
 //
@@ -48,6 +63,27 @@ fn assemble_halt() {
     assert_eq!(bytes, vec![0xFF]);
 }
 
+#[test]
+fn assemble_counter_reads() {
+    let src = "rdcycle r1\nrdinstret r2";
+    let bytes = assemble(src).unwrap();
+
+    assert_eq!(bytes, vec![0x0B, 0x01, 0x0C, 0x02]);
+}
+
+#[test]
+fn assemble_port_io() {
+    let src = "in r1 0x01\nout 0 r2";
+    let bytes = assemble(src).unwrap();
+
+    assert_eq!(bytes, vec![0x0D, 0x01, 0x01, 0x0E, 0x00, 0x02]);
+}
+
+#[test]
+fn assemble_port_out_of_range_fails() {
+    assert!(assemble("in r0 256").is_err());
+}
+
 //
 // ---------- labels ----------
 //
@@ -113,6 +149,964 @@ fn assemble_decode_roundtrip() {
     assert_eq!(decoded.length, 4);
 }
 
+//
+// ---------- data directives ----------
+//
+
+#[test]
+fn assemble_bytes_and_words() {
+    let src = ".byte 1, 0x7F 255\n.word 0x1234 7";
+    let bytes = assemble(src).unwrap();
+
+    assert_eq!(bytes, vec![0x01, 0x7F, 0xFF, 0x12, 0x34, 0x00, 0x07]);
+}
+
+#[test]
+fn assemble_strings_with_escapes() {
+    let src = r#".ascii "Hi, // there\n"
+.asciz "a\"b" "\x41\0""#;
+    let bytes = assemble(src).unwrap();
+
+    assert_eq!(bytes, b"Hi, // there\na\"b\0A\0\0".to_vec());
+}
+
+#[test]
+fn assemble_fill_and_zero() {
+    let bytes = assemble(".fill 3 0xAA\n.zero 2\n.fill 1").unwrap();
+
+    assert_eq!(bytes, vec![0xAA, 0xAA, 0xAA, 0, 0, 0]);
+}
+
+#[test]
+fn data_labels_resolve() {
+    let src = r#"
+            jmp start
+        table:
+            .word end, table
+        msg: .asciz "ok"
+        start:
+            load r0 0x0003
+            halt
+        end:
+    "#;
+    let (bytes, debug) = assemble_with_debug(src).unwrap();
+
+    assert_eq!(debug.symbols["table"], 0x0003);
+    assert_eq!(debug.symbols["msg"], 0x0007);
+    assert_eq!(debug.symbols["start"], 0x000A);
+    assert_eq!(debug.symbols["end"], 0x000F);
+    assert_eq!(&bytes[0..3], &[0x08, 0x00, 0x0A]);
+    assert_eq!(&bytes[3..7], &[0x00, 0x0F, 0x00, 0x03]);
+
+    // data has no line table entries
+    assert_eq!(debug.line_for(0x0003), None);
+    assert_eq!(debug.line_for(0x000A), Some(7));
+
+    let vm = run_program(src);
+    assert_eq!(vm.cpu.registers[0], 0x000F);
+}
+
+#[test]
+fn duplicate_labels_are_errors() {
+    let errors = assemble("a: halt\n    halt\na: halt").unwrap_err().errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].code, errors[0].line), ("E004", 3));
+    assert_eq!((errors[0].column, errors[0].span), (1, 2));
+    assert_eq!(errors[0].message, "`a` is already defined at line 1");
+}
+
+#[test]
+fn data_directive_errors() {
+    assert!(assemble(".byte 256").is_err());
+    assert!(assemble(".byte").is_err());
+    assert!(assemble(".word nowhere").is_err());
+    assert!(assemble(".ascii hello").is_err());
+    assert!(assemble(".ascii \"open").is_err());
+    assert!(assemble(r#".ascii "\q""#).is_err());
+    assert!(assemble(".fill many").is_err());
+    assert!(assemble(".fill 2 0x100").is_err());
+    assert!(assemble(".zero").is_err());
+    assert!(assemble(".quad 1").is_err());
+    assert!(assemble(".zero 60000\n.zero 6000").is_err());
+}
+
+//
+// ---------- placement ----------
+//
+
+#[test]
+fn org_places_code_and_fills_gaps() {
+    let src = "jmp main\n.org 0x0008\nmain: halt\n.org 0x0004\n.byte 7";
+    let (bytes, debug) = assemble_with_debug(src).unwrap();
+
+    assert_eq!(bytes, vec![0x08, 0x00, 0x08, 0, 7, 0, 0, 0, 0xFF]);
+    assert_eq!(debug.symbols["main"], 0x0008);
+    assert_eq!(debug.line_for(0x0008), Some(3));
+
+    let config = Config {
+        fill: 0xEE,
+        ..Config::default()
+    };
+    let (bytes, _) = assemble_with_config("", src, &config).unwrap();
+    assert_eq!(&bytes[3..8], &[0xEE, 7, 0xEE, 0xEE, 0xEE]);
+}
+
+#[test]
+fn align_pads_to_a_multiple() {
+    let src = ".byte 1\n.align 4\nwords: .word 2\n.align 4\n.align 2\nend:";
+    let (bytes, debug) = assemble_with_debug(src).unwrap();
+
+    assert_eq!(bytes, vec![1, 0, 0, 0, 0x00, 0x02]);
+    assert_eq!(debug.symbols["words"], 4);
+    assert_eq!(debug.symbols["end"], 8);
+}
+
+#[test]
+fn placement_errors() {
+    // the second region lands on the first
+    let err = assemble("movimm r0 1\n.org 2\nhalt")
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("0x0002"), "{}", err);
+
+    assert!(assemble(".org").is_err());
+    assert!(assemble(".org start\nstart:").is_err());
+    assert!(assemble(".align 0").is_err());
+    assert!(assemble(".org 0xFFFF\n.align 2").is_err());
+}
+
+//
+// ---------- constants and expressions ----------
+//
+
+#[test]
+fn operands_take_expressions() {
+    let src = r#"
+        .equ BASE 0x0200
+        .equ COUNT 3
+            movimm r0 COUNT*2+1
+            movimm r1 -1
+            load r2 BASE+(COUNT << 1)
+            store (BASE | 0x10) r0
+            out COUNT-3 r0
+            jmp end-1
+        table:
+            .byte hi(table) lo(table) (~0 & 0xFF) 7%4
+            .word end-table, ((end - table) / 2)
+        end:
+    "#;
+    let (bytes, debug) = assemble_with_debug(src).unwrap();
+
+    assert_eq!(&bytes[0..4], &[0x01, 0x00, 0x00, 0x07]);
+    assert_eq!(&bytes[4..8], &[0x01, 0x01, 0xFF, 0xFF]);
+    assert_eq!(&bytes[8..12], &[0x03, 0x02, 0x02, 0x06]);
+    assert_eq!(&bytes[12..16], &[0x04, 0x02, 0x10, 0x00]);
+    assert_eq!(&bytes[16..19], &[0x0E, 0x00, 0x00]);
+
+    let table = debug.symbols["table"];
+    let end = debug.symbols["end"];
+    assert_eq!(&bytes[19..22], &[0x08, 0x00, end as u8 - 1]);
+    assert_eq!(&bytes[22..26], &[0x00, table as u8, 0xFF, 3]);
+    assert_eq!(&bytes[26..30], &[0x00, 8, 0x00, 4]);
+}
+
+#[test]
+fn spaced_operands_need_parentheses() {
+    let src = |operand: &str| format!("    movimm r0 {}\ntable: halt", operand);
+    assert_eq!(assemble(&src("table+4")).unwrap()[2..4], [0x00, 0x08]);
+    assert_eq!(assemble(&src("(table + 4)")).unwrap()[2..4], [0x00, 0x08]);
+
+    // each word of a bare expression is an operand of its own
+    assert_eq!(
+        assemble(&src("table + 4")).unwrap_err().to_string(),
+        "line 1: movimm expects 2 operands"
+    );
+}
+
+#[test]
+fn constants_follow_definition_order() {
+    let src = r#"
+        .set N 1
+            .byte N
+        .set N N+1
+            .byte N
+        .equ SIZE end - start
+        start:
+            .zero 4
+        end:
+            .byte SIZE
+            .fill N 9
+    "#;
+
+    assert_eq!(assemble(src).unwrap(), vec![1, 2, 0, 0, 0, 0, 4, 9, 9]);
+}
+
+#[test]
+fn constant_and_expression_errors() {
+    assert!(assemble(".byte nowhere").is_err());
+    assert!(assemble("movimm r0 0x10000").is_err());
+    assert!(assemble("movimm r0 -0x8001").is_err());
+    assert!(assemble(".byte 0x100").is_err());
+    assert!(assemble("in r0 -1").is_err());
+    assert!(assemble("movimm r0 1/0").is_err());
+    assert!(assemble("movimm r0 mem8[0]").is_err());
+    assert!(assemble("movimm r0 (1+").is_err());
+
+    // .equ is permanent, names are shared with labels
+    assert!(assemble(".equ A 1\n.equ A 2").is_err());
+    assert!(assemble(".equ A 1\n.set A 2").is_err());
+    assert!(assemble("A:\n.equ A 1").is_err());
+    assert!(assemble(".set A 1\nA: halt").is_err());
+    assert!(assemble(".equ 1A 1").is_err());
+    assert!(assemble(".equ A").is_err());
+    assert!(assemble(".org 1 +").is_err());
+
+    // counts must be known in pass 1
+    assert!(assemble(".fill LATER\n.equ LATER 2").is_err());
+}
+
+#[test]
+fn arithmetic_overflow_is_an_error() {
+    for value in [
+        "0x7FFFFFFFFFFFFFFF+1",
+        "-0x7FFFFFFFFFFFFFFF-2",
+        "0x7FFFFFFFFFFFFFFF*2",
+        "1<<64",
+        "1<<63",
+        "1<<-1",
+        "1>>64",
+        "-(-0x7FFFFFFFFFFFFFFF-1)",
+        "(-0x7FFFFFFFFFFFFFFF-1)/-1",
+    ] {
+        let error = &assemble(&format!("    movimm r0 {}", value))
+            .unwrap_err()
+            .errors[0];
+        assert_eq!(error.code, "E002", "{}", value);
+        assert_eq!((error.column, error.span), (15, value.len()), "{}", value);
+    }
+
+    // a constant that overflows is reported where it is defined
+    let error = &assemble(".equ X, 1 << 64\n    movimm r0 X")
+        .unwrap_err()
+        .errors[0];
+    assert_eq!((error.line, error.code), (1, "E002"));
+    assert_eq!(error.message, "`1 << 64`: shift by 64 is out of range");
+
+    assert_eq!(
+        assemble("    movimm r0 (1<<61)*2/4>>58").unwrap(),
+        vec![0x01, 0x00, 0x00, 0x04]
+    );
+}
+
+//
+// ---------- macros ----------
+//
+
+#[test]
+fn macros_expand_with_arguments_and_defaults() {
+    let src = r#"
+        .macro load_pair a, b=r0, base=0x0200
+            load \a \base
+            movimm \b (\base + 2)
+        .endm
+            load_pair r1
+            load_pair r2 r3 0x0300
+            load_pair b=r4, a=r5
+    "#;
+    let expected = r#"
+            load r1 0x0200
+            movimm r0 0x0202
+            load r2 0x0300
+            movimm r3 0x0302
+            load r5 0x0200
+            movimm r4 0x0202
+    "#;
+
+    assert_eq!(assemble(src).unwrap(), assemble(expected).unwrap());
+}
+
+#[test]
+fn macro_labels_are_local_to_each_expansion() {
+    let src = r#"
+        .macro countdown reg
+            movimm r15 1
+        loop:
+            sub \reg r15
+            jmpnz loop
+        .endm
+        start: countdown r0
+            countdown r1
+            halt
+    "#;
+    let (bytes, debug) = assemble_with_debug(src).unwrap();
+
+    assert_eq!(debug.symbols["start"], 0);
+    assert!(!debug.symbols.contains_key("loop"));
+    // each jmpnz goes back to its own loop
+    assert_eq!(&bytes[7..10], &[0x0A, 0x00, 0x04]);
+    assert_eq!(&bytes[17..20], &[0x0A, 0x00, 0x0E]);
+
+    // expanded instructions map to the lines of the definition
+    assert_eq!(debug.line_for(0x0004), Some(5));
+    assert_eq!(debug.location(0x000E).unwrap().text, "sub r1 r15");
+}
+
+#[test]
+fn macros_call_macros() {
+    let src = r#"
+        .macro inc reg
+            movimm r15 1
+            add \reg r15
+        .endm
+        .macro inc2 reg
+            inc \reg
+            inc \reg
+        .endm
+            inc2 r3
+            halt
+    "#;
+
+    let vm = run_program(src);
+    assert_eq!(vm.cpu.registers[3], 2);
+}
+
+#[test]
+fn macro_errors_point_at_definition_and_call() {
+    let src = ".macro bad reg\n    add \\reg\n.endm\nnop_line:\n    bad r1";
+    let err = assemble(src).unwrap_err().to_string();
+    assert_eq!(
+        err,
+        "line 2: add expects 2 operands (in macro `bad` called at line 5)"
+    );
+
+    let nested = ".macro a\n    sub r0\n.endm\n.macro b\n    a\n.endm\n    b";
+    assert_eq!(
+        assemble(nested).unwrap_err().to_string(),
+        "line 2: sub expects 2 operands (in macro `a` called at line 5, from macro `b` called at line 7)"
+    );
+}
+
+#[test]
+fn macro_definition_errors() {
+    let recursive = ".macro again\n    again\n.endm\n    again";
+    assert!(
+        assemble(recursive)
+            .unwrap_err()
+            .to_string()
+            .contains("is `again` recursive?")
+    );
+
+    assert!(assemble(".macro open\n    halt").is_err());
+    assert!(assemble(".endm").is_err());
+    assert!(assemble(".macro add\n.endm").is_err());
+    assert!(assemble(".macro m a a\n.endm").is_err());
+    assert!(assemble(".macro m a\n.endm\n    m").is_err());
+    assert!(assemble(".macro m a\n.endm\n    m 1 2").is_err());
+    assert!(assemble(".macro m a\n.endm\n    m 1 a=2").is_err());
+    assert!(assemble(".macro m\n    movimm \\x 1\n.endm\n    m").is_err());
+}
+
+//
+// ---------- includes ----------
+//
+
+#[test]
+fn include_splices_in_source() {
+    let dir = source_dir(
+        "include",
+        &[
+            (
+                "main.asm",
+                b".include \"lib/consts.asm\"\n    movimm r0 ANSWER\n    halt",
+            ),
+            ("lib/consts.asm", b".equ ANSWER 42\n.include \"more.asm\""),
+            ("lib/more.asm", b"    movimm r1 7"),
+        ],
+    );
+
+    let (bytes, debug) = assemble_file(&dir.join("main.asm"), &Config::default()).unwrap();
+    assert_eq!(
+        bytes,
+        vec![0x01, 0x01, 0x00, 0x07, 0x01, 0x00, 0x00, 0x2A, 0xFF]
+    );
+
+    // the included line keeps its own file and line number
+    let location = debug.location(0x0000).unwrap();
+    assert!(debug.file_name(location).ends_with("more.asm"));
+    assert_eq!(location.line, 1);
+    assert_eq!(debug.location(0x0004).unwrap().file, 0);
+    assert_eq!(debug.line_for(0x0004), Some(2));
+}
+
+#[test]
+fn incbin_inserts_raw_bytes() {
+    let dir = source_dir(
+        "incbin",
+        &[
+            (
+                "main.asm",
+                b"    jmp end\ntable: .incbin \"table.bin\"\nend: halt",
+            ),
+            ("table.bin", &[0x00, 0xFF, 0x10]),
+        ],
+    );
+
+    let (bytes, debug) = assemble_file(&dir.join("main.asm"), &Config::default()).unwrap();
+    assert_eq!(bytes, vec![0x08, 0x00, 0x06, 0x00, 0xFF, 0x10, 0xFF]);
+    assert_eq!(debug.symbols["table"], 0x0003);
+}
+
+#[test]
+fn incbin_larger_than_memory_is_an_error() {
+    // 64 KiB would wrap a 16-bit length around to nothing
+    let big = vec![0u8; 0x10000];
+    let dir = source_dir(
+        "incbin-big",
+        &[("main.asm", b".incbin \"big.bin\"\n"), ("big.bin", &big)],
+    );
+
+    let err = assemble_file(&dir.join("main.asm"), &Config::default()).unwrap_err();
+    assert_eq!(err.errors.len(), 1);
+    assert_eq!(
+        err.errors[0].message,
+        "program does not fit in the address space"
+    );
+}
+
+#[test]
+fn include_paths_are_searched_after_the_file_directory() {
+    let dir = source_dir(
+        "search",
+        &[
+            ("src/main.asm", b".include \"io.asm\"\n    halt"),
+            ("src/io.asm", b"    movimm r0 1"),
+            ("lib/io.asm", b"    movimm r0 2"),
+            ("lib/extra.asm", b"    movimm r1 3"),
+        ],
+    );
+    let main = dir.join("src/main.asm");
+
+    // without a search path only the file's own directory is looked in
+    let (bytes, _) = assemble_file(&main, &Config::default()).unwrap();
+    assert_eq!(bytes[3], 1);
+
+    fs::write(&main, ".include \"extra.asm\"\n    halt").unwrap();
+    let err = assemble_file(&main, &Config::default())
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.ends_with("main.asm:1: cannot find `extra.asm`"),
+        "{}",
+        err
+    );
+
+    let config = Config {
+        include_paths: vec![dir.join("lib")],
+        ..Config::default()
+    };
+    let (bytes, _) = assemble_file(&main, &config).unwrap();
+    assert_eq!(bytes, vec![0x01, 0x01, 0x00, 0x03, 0xFF]);
+}
+
+#[test]
+fn errors_are_grouped_by_file() {
+    let dir = source_dir(
+        "grouped",
+        &[
+            ("main.asm", b".include \"lib.asm\"\n    frob\n    halt"),
+            ("lib.asm", b"    frob"),
+        ],
+    );
+
+    let errors = assemble_file(&dir.join("main.asm"), &Config::default())
+        .unwrap_err()
+        .errors;
+    let found: Vec<(bool, usize)> = errors
+        .iter()
+        .map(|e| (e.file.ends_with("lib.asm"), e.line))
+        .collect();
+    assert_eq!(found, vec![(false, 2), (true, 1)]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn including_a_file_twice_defines_its_labels_twice() {
+    let dir = source_dir(
+        "twice",
+        &[
+            (
+                "main.asm",
+                b".include \"lib.asm\"\n.include \"lib.asm\"\n    jmp lib_start",
+            ),
+            ("lib.asm", b"lib_start:\n    halt"),
+        ],
+    );
+
+    let errors = assemble_file(&dir.join("main.asm"), &Config::default())
+        .unwrap_err()
+        .errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].code, errors[0].line), ("E004", 1));
+    assert!(errors[0].file.ends_with("lib.asm"));
+    assert!(
+        errors[0]
+            .message
+            .starts_with("`lib_start` is already defined at "),
+        "{}",
+        errors[0].message
+    );
+    assert!(errors[0].message.ends_with("lib.asm:1"));
+}
+
+#[test]
+fn include_cycles_are_errors() {
+    let dir = source_dir(
+        "cycle",
+        &[
+            ("a.asm", b".include \"b.asm\"\n    halt"),
+            ("b.asm", b"    movimm r1 1\n.include \"a.asm\""),
+        ],
+    );
+
+    let err = assemble_file(&dir.join("a.asm"), &Config::default())
+        .unwrap_err()
+        .to_string();
+    let (at, cycle) = err.split_once(": include cycle: ").unwrap();
+    assert!(at.ends_with("b.asm:2"), "{}", err);
+
+    let cycle: Vec<&str> = cycle.split(" -> ").collect();
+    assert_eq!(cycle.len(), 3);
+    assert!(cycle[0].ends_with("a.asm") && cycle[1].ends_with("b.asm"));
+    assert_eq!(cycle[0], cycle[2]);
+}
+
+#[test]
+fn errors_in_included_files_name_the_file() {
+    let dir = source_dir(
+        "errors",
+        &[
+            ("main.asm", b"    halt\n.include \"bad.asm\""),
+            ("bad.asm", b"\n    add r1"),
+        ],
+    );
+
+    let err = assemble_file(&dir.join("main.asm"), &Config::default())
+        .unwrap_err()
+        .to_string();
+    assert!(
+        err.ends_with("bad.asm:2: add expects 2 operands"),
+        "{}",
+        err
+    );
+
+    let err = assemble_file(&dir.join("missing.asm"), &Config::default())
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("cannot read"), "{}", err);
+}
+
+#[test]
+fn string_sources_cannot_include() {
+    assert_eq!(
+        assemble(".include \"lib.asm\"").unwrap_err().to_string(),
+        "line 1: .include needs files, assemble with assemble_file"
+    );
+    assert!(assemble(".incbin \"data.bin\"").is_err());
+    assert!(assemble(".include").is_err());
+}
+
+//
+// ---------- conditional assembly ----------
+//
+
+#[test]
+fn if_else_picks_a_branch() {
+    let src = "
+.equ DEBUG 0
+.if DEBUG
+    out 1 r0
+.elif DEBUG == 0 && 1
+    movimm r0 1
+.else
+    movimm r0 2
+.endif
+    halt";
+    assert_eq!(assemble(src).unwrap(), vec![0x01, 0x00, 0x00, 0x01, 0xFF]);
+
+    let src = src.replace(".equ DEBUG 0", ".equ DEBUG 5");
+    assert_eq!(assemble(&src).unwrap(), vec![0x0E, 0x01, 0x00, 0xFF]);
+}
+
+#[test]
+fn ifdef_and_nesting() {
+    let src = "
+start:
+.ifdef start
+  .ifndef missing
+    .if 0
+        movimm r0 1
+    .else
+        movimm r0 2
+    .endif
+  .endif
+.else
+  .if undefined_name
+  .endif
+    movimm r0 3
+.endif
+    halt";
+    let bytes = assemble(src).unwrap();
+    assert_eq!(bytes, vec![0x01, 0x00, 0x00, 0x02, 0xFF]);
+}
+
+#[test]
+fn conditions_in_macro_bodies_see_arguments() {
+    let src = "
+.macro set_reg reg, value
+.if \\value == 0
+    sub \\reg \\reg
+.else
+    movimm \\reg \\value
+.endif
+.endm
+    set_reg r1 0
+    set_reg r2 7";
+    let bytes = assemble(src).unwrap();
+    assert_eq!(bytes, vec![0x06, 0x01, 0x01, 0x01, 0x02, 0x00, 0x07]);
+}
+
+#[test]
+fn skipped_blocks_define_nothing() {
+    let src = "
+.if 0
+skipped:
+    movimm r0 1
+.equ SIZE 4
+.endif
+    halt";
+    let (bytes, debug) = assemble_with_debug(src).unwrap();
+    assert_eq!(bytes, vec![0xFF]);
+    assert!(debug.symbols.is_empty());
+    assert_eq!(debug.line_for(0x0000), Some(7));
+
+    // a label in a skipped block is free to be defined again
+    let src = ".if 0
+here:
+.else
+here:
+.endif
+    jmp here";
+    assert_eq!(assemble(src).unwrap(), vec![0x08, 0x00, 0x00]);
+    assert!(
+        assemble(
+            ".if 0
+.equ X 1
+.endif
+    movimm r0 X"
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn skipped_blocks_include_define_and_expand_nothing() {
+    // only the taken branch defines the macro
+    let src = ".equ USE 1
+.if USE
+.macro m
+    movimm r0 1
+.endm
+.else
+.macro m
+    movimm r0 2
+.endm
+.endif
+    m
+    halt";
+    assert_eq!(run_program(src).cpu.registers[0], 1);
+
+    // nor is a skipped file read
+    let dir = source_dir(
+        "skipped-include",
+        &[(
+            "main.asm",
+            b".if 0\n.include \"missing.asm\"\n.endif\n    halt\n",
+        )],
+    );
+    let (bytes, _) = assemble_file(&dir.join("main.asm"), &Config::default()).unwrap();
+    assert_eq!(bytes, vec![0xFF]);
+
+    // nor a skipped call checked
+    let src = ".macro m x
+    movimm r0 \\x
+.endm
+.if 0
+    m
+.endif
+    halt";
+    assert_eq!(assemble(src).unwrap(), vec![0xFF]);
+}
+
+#[test]
+fn defines_come_from_the_config() {
+    let src = ".ifndef DEBUG
+.equ DEBUG 0
+.endif
+.if DEBUG
+    out 1 r0
+.endif
+    movimm r1 LEVEL";
+    let config = |defines: &[(&str, i64)]| Config {
+        defines: defines.iter().map(|(n, v)| (n.to_string(), *v)).collect(),
+        ..Config::default()
+    };
+
+    let (bytes, _) = assemble_with_config("", src, &config(&[("LEVEL", 3)])).unwrap();
+    assert_eq!(bytes, vec![0x01, 0x01, 0x00, 0x03]);
+
+    let defines = [("LEVEL", -1), ("DEBUG", 1)];
+    let (bytes, _) = assemble_with_config("", src, &config(&defines)).unwrap();
+    assert_eq!(bytes, vec![0x0E, 0x01, 0x00, 0x01, 0x01, 0xFF, 0xFF]);
+
+    let err = assemble_with_config("", "halt", &config(&[("2x", 1)]))
+        .unwrap_err()
+        .to_string();
+    assert_eq!(err, "invalid constant name `2x`");
+}
+
+#[test]
+fn unbalanced_blocks_are_errors() {
+    assert_eq!(
+        assemble(
+            "    halt
+.if 1
+    halt"
+        )
+        .unwrap_err()
+        .to_string(),
+        "line 2: .if without .endif"
+    );
+    assert_eq!(
+        assemble(
+            ".ifdef X
+.if 1
+.endif"
+        )
+        .unwrap_err()
+        .to_string(),
+        "line 1: .ifdef without .endif"
+    );
+    assert_eq!(
+        assemble(
+            "    halt
+.endif"
+        )
+        .unwrap_err()
+        .to_string(),
+        "line 2: .endif without .if"
+    );
+    assert_eq!(
+        assemble(".else").unwrap_err().to_string(),
+        "line 1: .else without .if"
+    );
+    assert_eq!(
+        assemble(
+            ".if 1
+.else
+.else
+.endif"
+        )
+        .unwrap_err()
+        .to_string(),
+        "line 3: .else after .else"
+    );
+    assert!(
+        assemble(
+            ".if 1
+.else
+.elif 1
+.endif"
+        )
+        .is_err()
+    );
+    assert!(
+        assemble(
+            ".if
+.endif"
+        )
+        .is_err()
+    );
+    assert!(
+        assemble(
+            ".ifdef
+.endif"
+        )
+        .is_err()
+    );
+    assert!(
+        assemble(
+            ".if 1
+.endif 1"
+        )
+        .is_err()
+    );
+    assert!(
+        assemble(
+            "here: .if 1
+.endif"
+        )
+        .is_err()
+    );
+
+    // conditions can only use names defined above them
+    assert!(
+        assemble(
+            ".if later
+.endif
+later:"
+        )
+        .is_err()
+    );
+}
+
+//
+// ---------- diagnostics ----------
+//
+
+#[test]
+fn every_error_is_reported_in_source_order() {
+    let src =
+        "start:\n    frob r1\n    mov r1 r16\n    movimm r0 0x12345\n    jmp nowhere\n    halt";
+    let errors = assemble_named("prog.asm", src).unwrap_err().errors;
+
+    let found: Vec<(usize, usize, usize, &str)> = errors
+        .iter()
+        .map(|e| (e.line, e.column, e.span, e.code))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (2, 5, 4, "E001"),
+            (3, 12, 3, "E002"),
+            (4, 15, 7, "E002"),
+            (5, 9, 7, "E003"),
+        ]
+    );
+    assert_eq!(errors[1].file, "prog.asm");
+    assert_eq!(errors[1].message, "Register out of bounds");
+    assert_eq!(errors[1].text, "    mov r1 r16");
+}
+
+#[test]
+fn errors_render_with_the_source_line() {
+    let err = assemble_named("prog.asm", "    halt\n\tmov r1 r16 // copy").unwrap_err();
+    assert_eq!(err.to_string(), "prog.asm:2: Register out of bounds");
+    assert_eq!(
+        err.render(),
+        "error[E002]: Register out of bounds
+ --> prog.asm:2:9
+  |
+2 | \tmov r1 r16
+  | \t       ^^^
+
+1 error
+"
+    );
+
+    // without a token to point at, the whole statement is underlined
+    let err = assemble("  add r1").unwrap_err();
+    assert!(err.render().contains("1 |   add r1\n  |   ^^^^^^\n"));
+}
+
+#[test]
+fn macro_errors_carry_the_calls_as_notes() {
+    let src = ".macro a\n    sub r0\n.endm\n.macro b\n    a\n.endm\n    b";
+    let error = &assemble(src).unwrap_err().errors[0];
+    assert_eq!(error.line, 2);
+    assert_eq!(
+        error.notes,
+        vec![
+            "in macro `a` called at line 5".to_string(),
+            "from macro `b` called at line 7".to_string(),
+        ]
+    );
+    assert!(error.render().ends_with(
+        "  = note: in macro `a` called at line 5\n  = note: from macro `b` called at line 7\n"
+    ));
+}
+
+#[test]
+fn errors_as_json() {
+    let err = assemble_named("prog.asm", "    halt\n    jmp nowhere").unwrap_err();
+    assert_eq!(
+        err.to_json_lines(),
+        concat!(
+            r#"{"file":"prog.asm","line":2,"column":9,"span":7,"code":"E003","#,
+            r#""message":"unknown name `nowhere`","notes":[]}"#,
+            "\n"
+        )
+    );
+    assert_eq!(err.report(ErrorFormat::Json), err.to_json_lines());
+    assert_eq!(err.report(ErrorFormat::Text), err.render());
+}
+
+#[test]
+fn one_mistake_is_one_error() {
+    // a bad condition skips its block and every branch after it
+    let errors = assemble(".if nowhere\n    bad\n.else\n    bad\n.endif\n.endif")
+        .unwrap_err()
+        .errors;
+    let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![1, 6]);
+    assert_eq!(errors[1].code, "E008");
+
+    // a bad macro definition still swallows its body
+    let errors = assemble(".macro add\n    bad\n.endm\n.endm")
+        .unwrap_err()
+        .errors;
+    let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+    assert_eq!(lines, vec![1, 4]);
+
+    // a failed include stops before the passes, which would only report
+    // what it failed to define
+    let errors = assemble(".include \"lib.asm\"\n    jmp lib_start\n    add")
+        .unwrap_err()
+        .errors;
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].code, errors[0].column), ("E007", 1));
+}
+
+#[test]
+fn errors_without_a_line() {
+    let config = Config {
+        defines: vec![("2x".to_string(), 1)],
+        ..Config::default()
+    };
+    let error: AsmError = assemble_with_config("", "halt", &config)
+        .unwrap_err()
+        .errors
+        .remove(0);
+    assert_eq!((error.line, error.column, error.span), (0, 0, 0));
+    assert_eq!(error.render(), "error[E002]: invalid constant name `2x`\n");
+
+    let error = assemble_file(std::path::Path::new("missing.asm"), &Config::default())
+        .unwrap_err()
+        .errors
+        .remove(0);
+    assert!(error.render().ends_with(" --> missing.asm\n"));
+}
+
+#[test]
+fn errors_convert_to_strings() {
+    fn check(src: &str) -> Result<Vec<u8>, String> {
+        Ok(assemble(src)?)
+    }
+    assert_eq!(
+        check("    add r1\n    sub r1"),
+        Err("line 1: add expects 2 operands\nline 2: sub expects 2 operands".to_string())
+    );
+}
+
 //
 // ---------- assembler → vm execution ----------
 //
@@ -190,6 +1184,72 @@ fn store_then_load_big_endian() {
     assert_eq!(vm.memory.data[0x0101], 0xCD);
 }
 
+//
+// ---------- debug info ----------
+//
+
+#[test]
+fn debug_info_maps_addresses_to_lines() {
+    let src = "// header\nstart:\n    movimm r0 1\n\nloop:  sub r0 r0\n    jmpz loop\n    halt";
+    let (bytes, debug) = assemble_with_debug(src).unwrap();
+
+    assert_eq!(bytes, assemble(src).unwrap());
+    assert_eq!(debug.symbols["start"], 0x0000);
+    assert_eq!(debug.symbols["loop"], 0x0004);
+
+    assert_eq!(debug.line_for(0x0000), Some(3));
+    assert_eq!(debug.line_for(0x0004), Some(5));
+    assert_eq!(debug.line_for(0x0007), Some(6));
+    assert_eq!(debug.line_for(0x000A), Some(7));
+    assert_eq!(debug.line_for(0x0001), None);
+
+    assert_eq!(debug.symbol_at(0x0004), Some("loop"));
+    assert_eq!(debug.symbol_at(0x0007), None);
+}
+
+#[test]
+fn debug_info_records_columns_and_text() {
+    let src = "movimm r0 1\nloop:  sub r0 r0   // count down\n\tload r1 0x2000";
+    let (_, debug) = assemble_named("prog.asm", src).unwrap();
+
+    assert_eq!(debug.files, vec!["prog.asm".to_string()]);
+
+    let sub = debug.location(0x0004).unwrap();
+    assert_eq!((sub.file, sub.line, sub.column), (0, 2, 8));
+    assert_eq!(sub.text, "sub r0 r0");
+
+    assert_eq!(
+        debug.describe(0x0007).as_deref(),
+        Some("prog.asm:3: load r1 0x2000")
+    );
+    assert_eq!(debug.describe(0x0008), None);
+
+    // unnamed source falls back to the line number alone
+    let (_, debug) = assemble_with_debug(src).unwrap();
+    assert_eq!(
+        debug.describe(0x0000).as_deref(),
+        Some("line 1: movimm r0 1")
+    );
+}
+
+#[test]
+fn disassembly_reassembles_to_same_bytes() {
+    let src = "movimm r3 0x1234\nmov r1 r2\nload r4 0x0200\nstore 0x0200 r4\nadd r1 r2\nsub r1 r2\ncmp r1 r2\njmp 0\njmpz 3\njmpnz 6\nrdcycle r1\nrdinstret r2\nin r1 1\nout 0 r2\nhalt";
+    let bytes = assemble(src).unwrap();
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut pc: u16 = 0;
+    while (pc as usize) < bytes.len() {
+        let decoded = decode(&bytes, pc).unwrap();
+        lines.push(decoded.instr.to_string());
+        pc += decoded.length;
+    }
+
+    assert_eq!(lines[0], "movimm r3 0x1234");
+    assert_eq!(lines[1], "mov r1 r2");
+    assert_eq!(assemble(&lines.join("\n")).unwrap(), bytes);
+}
+
 //
 // ---------- error cases ----------
 //
@@ -197,7 +1257,8 @@ fn store_then_load_big_endian() {
 #[test]
 fn assemble_unknown_instruction_fails() {
     let src = "foo r0 r1";
-    assert!(assemble(src).is_err());
+    let errors = assemble(src).unwrap_err().errors;
+    assert_eq!(errors[0].message, "unknown instruction `foo`");
 }
 
 #[test]
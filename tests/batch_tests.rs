@@ -0,0 +1,127 @@
+use risa16::assembler::assemble;
+use risa16::batch::{Job, JobConfig, run_batch, run_job};
+use risa16::vm::{ExitReason, VM};
+
+//
+// ---------- helpers ----------
+//
+
+const ECHO: &str = r#"
+    movimm r1 0xFFFF
+loop:
+    in r0 0
+    cmp r0 r1
+    jmpz done
+    out 0 r0
+    jmp loop
+done:
+    halt
+"#;
+
+const SPIN: &str = r#"
+loop:
+    movimm r0 1
+    jmp loop
+"#;
+
+fn job(name: &str, src: &str, input: &[u8]) -> Job {
+    Job {
+        name: name.to_string(),
+        program: assemble(src).expect("assembly failed"),
+        config: JobConfig::default(),
+        input: input.to_vec(),
+    }
+}
+
+fn assert_send_clone<T: Send + Clone>() {}
+
+//
+// ---------- jobs ----------
+//
+
+#[test]
+fn vm_is_send_and_clone() {
+    assert_send_clone::<VM>();
+}
+
+#[test]
+fn job_echoes_input() {
+    let result = run_job(&job("echo", ECHO, b"hi!"));
+
+    assert_eq!(result.exit, ExitReason::Halted);
+    assert_eq!(result.output, b"hi!");
+    assert_eq!(result.registers[1], 0xFFFF);
+}
+
+#[test]
+fn job_budget_stops_runaway_program() {
+    let mut spin = job("spin", SPIN, b"");
+    spin.config.max_steps = 50;
+
+    let result = run_job(&spin);
+
+    assert_eq!(result.exit, ExitReason::BudgetExhausted);
+    assert_eq!(result.instret, 50);
+}
+
+#[test]
+fn oversized_program_faults() {
+    let mut big = job("big", "halt", b"");
+    big.program = vec![0xFF; 0x2000];
+
+    let result = run_job(&big);
+
+    assert!(matches!(result.exit, ExitReason::Faulted(_)));
+}
+
+//
+// ---------- batches ----------
+//
+
+#[test]
+fn batch_returns_results_in_job_order() {
+    let mut jobs: Vec<Job> = Vec::new();
+    for i in 0..40 {
+        let input = format!("job {}", i);
+        jobs.push(job(&format!("echo-{}", i), ECHO, input.as_bytes()));
+    }
+    let mut spin = job("spin", SPIN, b"");
+    spin.config.max_steps = 1000;
+    jobs.push(spin);
+
+    let results = run_batch(&jobs, 4);
+
+    assert_eq!(results.len(), 41);
+    for (i, result) in results.iter().take(40).enumerate() {
+        assert_eq!(result.name, format!("echo-{}", i));
+        assert_eq!(result.exit, ExitReason::Halted);
+        assert_eq!(result.output, format!("job {}", i).as_bytes());
+    }
+    assert_eq!(results[40].exit, ExitReason::BudgetExhausted);
+}
+
+#[test]
+fn batch_matches_single_threaded_runs() {
+    let jobs = vec![
+        job("countdown", include_str!("corpus/countdown.asm"), b""),
+        job("fibonacci", include_str!("corpus/fibonacci.asm"), b""),
+        job("flags", include_str!("corpus/flags.asm"), b""),
+    ];
+
+    let results = run_batch(&jobs, 3);
+
+    for (job, result) in jobs.iter().zip(results.iter()) {
+        let single = run_job(job);
+        assert_eq!(result.registers, single.registers);
+        assert_eq!(result.memory_digest, single.memory_digest);
+        assert_eq!(result.cycles, single.cycles);
+    }
+
+    // fibonacci stores to memory, countdown does not
+    assert_ne!(results[0].memory_digest, results[1].memory_digest);
+}
+
+#[test]
+fn empty_batch() {
+    assert!(run_batch(&[], 8).is_empty());
+}
@@ -0,0 +1,9 @@
+// counts r0 down from 10 to 0
+    movimm r0 10
+    movimm r1 1
+    movimm r2 0
+loop:
+    sub r0 r1
+    cmp r0 r2
+    jmpnz loop
+    halt
@@ -0,0 +1,5 @@
+// stores a value then faults on an out of bounds load
+    movimm r0 0x1234
+    store 0x0100 r0
+    load r1 0x0FFF
+    halt
@@ -0,0 +1,19 @@
+// computes fibonacci numbers, storing each one at 0x0200 as it goes
+    movimm r0 0        // fib(n)
+    movimm r1 1        // fib(n + 1)
+    movimm r2 12       // remaining
+    movimm r3 1        // one
+    movimm r4 0        // zero
+next:
+    store 0x0200 r0
+    mov r5 r0
+    add r5 r1
+    mov r0 r1
+    mov r1 r5
+    sub r2 r3
+    cmp r2 r4
+    jmpz done
+    jmp next
+done:
+    load r6 0x0200
+    halt
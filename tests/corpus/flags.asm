@@ -0,0 +1,18 @@
+// exercises carry, borrow and the conditional jumps
+    movimm r0 0xFFFF
+    movimm r1 1
+    add r0 r1          // wraps to 0, sets carry and zero
+    jmpz wrapped
+    movimm r7 0xDEAD
+    halt
+wrapped:
+    movimm r2 3
+    movimm r3 5
+    sub r2 r3          // borrows
+    cmp r2 r3
+    jmpnz differ
+    halt
+differ:
+    mov r4 r2
+    cmp r3 r2
+    halt
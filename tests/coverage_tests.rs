@@ -0,0 +1,198 @@
+use risa16::assembler::{Config, DebugInfo, assemble_file, assemble_with_debug};
+use risa16::coverage::{Branch, Coverage, Lcov, SourceCoverage};
+use risa16::vm::VM;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+//
+// ---------- helpers ----------
+//
+
+// r0 comes from the console; 0 skips the loop, anything else counts down
+const PROGRAM: &str = r#"// line 1
+    in r0 0
+    movimm r1 1
+    movimm r2 0
+    cmp r0 r2
+    jmpz done
+loop:
+    sub r0 r1
+    cmp r0 r2
+    jmpnz loop
+done:
+    halt
+"#;
+
+// runs PROGRAM once with input as r0 and returns what it covered
+fn covered(input: u8) -> (Coverage, Vec<u8>, DebugInfo) {
+    let (bytes, debug) = assemble_with_debug(PROGRAM).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm.devices.console.input.push_back(input);
+
+    let coverage = Arc::new(Mutex::new(Coverage::new()));
+    vm.observers.attach(Box::new(coverage.clone()));
+    vm.run();
+
+    let coverage = coverage.lock().unwrap().clone();
+    (coverage, bytes, debug)
+}
+
+//
+// ---------- recording ----------
+//
+
+#[test]
+fn lines_and_branches() {
+    let (coverage, bytes, debug) = covered(3);
+    let source = coverage.source(&debug, &bytes);
+
+    assert_eq!(source.lines[&2], 1);
+    assert_eq!(source.lines[&8], 3);
+    assert!(!source.lines.contains_key(&7)); // a label, no code
+
+    assert_eq!(
+        source.branches[&6],
+        Branch {
+            taken: 0,
+            not_taken: 1
+        }
+    );
+    assert_eq!(
+        source.branches[&10],
+        Branch {
+            taken: 2,
+            not_taken: 1
+        }
+    );
+
+    assert_eq!(source.line_totals(), (9, 9));
+    assert_eq!(source.branch_totals(), (4, 3));
+}
+
+#[test]
+fn code_that_never_ran() {
+    let (coverage, bytes, debug) = covered(0);
+    let source = coverage.source(&debug, &bytes);
+
+    assert_eq!(source.lines[&8], 0);
+    assert_eq!(source.line_totals(), (9, 6));
+
+    // the loop's jump never ran, both of its directions are missed
+    assert_eq!(source.branches[&10], Branch::default());
+    assert_eq!(source.branch_totals(), (4, 1));
+}
+
+#[test]
+fn unconditional_jumps_are_not_branches() {
+    let (bytes, debug) = assemble_with_debug("jmp end\nend:\nhalt").unwrap();
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+
+    let coverage = Arc::new(Mutex::new(Coverage::new()));
+    vm.observers.attach(Box::new(coverage.clone()));
+    vm.run();
+
+    let coverage = coverage.lock().unwrap();
+    assert!(coverage.branches.is_empty());
+    assert!(coverage.source(&debug, &bytes).branches.is_empty());
+}
+
+#[test]
+fn included_files_are_covered_separately() {
+    let dir = std::env::temp_dir().join(format!("risa16-coverage-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("main.asm"),
+        "    movimm r0 1\n.include \"lib.asm\"",
+    )
+    .unwrap();
+    fs::write(dir.join("lib.asm"), "\n    halt").unwrap();
+
+    let (bytes, debug) = assemble_file(&dir.join("main.asm"), &Config::default()).unwrap();
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+
+    let coverage = Arc::new(Mutex::new(Coverage::new()));
+    vm.observers.attach(Box::new(coverage.clone()));
+    vm.run();
+
+    let coverage = coverage.lock().unwrap();
+    let lines = |file| coverage.file(&debug, &bytes, file).lines;
+    assert_eq!(lines(0).into_iter().collect::<Vec<_>>(), vec![(1, 1)]);
+    assert_eq!(lines(1).into_iter().collect::<Vec<_>>(), vec![(2, 1)]);
+    assert_eq!(coverage.source(&debug, &bytes).lines, lines(0));
+}
+
+//
+// ---------- merging ----------
+//
+
+#[test]
+fn runs_merge() {
+    let (mut skipped, bytes, debug) = covered(0);
+    let (looped, _, _) = covered(2);
+    skipped.merge(&looped);
+
+    let source = skipped.source(&debug, &bytes);
+    assert_eq!(source.lines[&2], 2);
+    assert_eq!(source.line_totals(), (9, 9));
+    assert_eq!(source.branch_totals(), (4, 4));
+}
+
+#[test]
+fn lcov_roundtrip_and_merge() {
+    let (coverage, bytes, debug) = covered(0);
+    let mut lcov = Lcov::new();
+    lcov.add("prog.asm", &coverage.source(&debug, &bytes));
+
+    let text = lcov.to_string();
+    assert!(text.contains("SF:prog.asm\n"));
+    assert!(text.contains("DA:8,0\n"));
+    assert!(text.contains("BRDA:6,0,0,1\n"));
+    assert!(text.contains("BRDA:10,0,0,-\n"));
+    assert!(text.contains("LF:9\nLH:6\n"));
+    assert!(text.ends_with("end_of_record\n"));
+
+    let mut parsed = Lcov::parse(&text).unwrap();
+    assert_eq!(parsed, lcov);
+
+    // a second run and a second file
+    let (coverage, _, _) = covered(2);
+    let mut other = Lcov::new();
+    other.add("prog.asm", &coverage.source(&debug, &bytes));
+    other.add("other.asm", &SourceCoverage::default());
+    parsed.merge(&other);
+
+    assert_eq!(parsed.sources.len(), 2);
+    assert_eq!(parsed.sources["prog.asm"].line_totals(), (9, 9));
+}
+
+#[test]
+fn lcov_errors() {
+    assert!(Lcov::parse("DA:1,1\n").is_err());
+    assert!(Lcov::parse("SF:a.asm\nDA:x,1\nend_of_record\n").is_err());
+    assert!(Lcov::parse("SF:a.asm\nBRDA:1,0,2,1\nend_of_record\n").is_err());
+    assert!(Lcov::parse("SF:a.asm\nDA:1,1\n").is_err());
+}
+
+//
+// ---------- listing ----------
+//
+
+#[test]
+fn annotated_listing() {
+    let (coverage, bytes, debug) = covered(0);
+    let listing = coverage.source(&debug, &bytes).annotate(PROGRAM);
+    let lines: Vec<&str> = listing.lines().collect();
+
+    assert_eq!(lines[0], "           // line 1");
+    assert_eq!(lines[1], "        1      in r0 0");
+    assert_eq!(
+        lines[5],
+        "        1      jmpz done                [taken 1, not taken 0]"
+    );
+    assert_eq!(lines[7], "    #####      sub r0 r1");
+    assert!(listing.ends_with("Lines: 6/9 (66.7%), branches: 1/4 (25.0%)\n"));
+}
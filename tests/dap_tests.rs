@@ -0,0 +1,608 @@
+use risa16::dap::{DapServer, read_message, write_message};
+use risa16::json::Json;
+use std::io::{self, BufReader, Cursor, Read, Write};
+
+//
+// ---------- helpers ----------
+//
+
+const COUNTDOWN: &str = "tests/corpus/countdown.asm";
+const FAULT: &str = "tests/corpus/fault.asm";
+
+fn request(server: &mut DapServer, seq: u64, command: &str, arguments: Json) -> Vec<Json> {
+    server.handle(&Json::object(vec![
+        ("seq", seq.into()),
+        ("type", "request".into()),
+        ("command", command.into()),
+        ("arguments", arguments),
+    ]))
+}
+
+fn launched(program: &str, stop_on_entry: bool) -> DapServer {
+    let mut server = DapServer::new();
+
+    request(&mut server, 1, "initialize", Json::Null);
+    let reply = request(
+        &mut server,
+        2,
+        "launch",
+        Json::object(vec![
+            ("program", program.into()),
+            ("stopOnEntry", stop_on_entry.into()),
+        ]),
+    );
+    assert_eq!(reply[0].get("success"), Some(&Json::Bool(true)));
+
+    server
+}
+
+fn set_breakpoints(server: &mut DapServer, lines: &[u64]) -> Vec<Json> {
+    let breakpoints: Vec<Json> = lines
+        .iter()
+        .map(|l| Json::object(vec![("line", (*l).into())]))
+        .collect();
+
+    request(
+        server,
+        3,
+        "setBreakpoints",
+        Json::object(vec![
+            ("source", Json::object(vec![("path", COUNTDOWN.into())])),
+            ("breakpoints", breakpoints.into()),
+        ]),
+    )
+}
+
+fn body(message: &Json) -> &Json {
+    message.get("body").expect("message has no body")
+}
+
+fn field<'a>(message: &'a Json, path: &[&str]) -> &'a Json {
+    path.iter().fold(message, |value, key| {
+        value.get(key).unwrap_or_else(|| panic!("no `{}`", key))
+    })
+}
+
+// the events among the messages, by name
+fn events(messages: &[Json]) -> Vec<&str> {
+    messages
+        .iter()
+        .filter_map(|m| m.get("event").and_then(Json::as_str))
+        .collect()
+}
+
+fn current_line(server: &mut DapServer) -> u64 {
+    let reply = request(
+        server,
+        10,
+        "stackTrace",
+        Json::object(vec![("threadId", 1u64.into())]),
+    );
+    let frames = field(&reply[0], &["body", "stackFrames"])
+        .as_array()
+        .unwrap();
+    frames[0].get("line").and_then(Json::as_u64).unwrap()
+}
+
+fn variables(server: &mut DapServer, reference: u64) -> Vec<(String, String)> {
+    let reply = request(
+        server,
+        11,
+        "variables",
+        Json::object(vec![("variablesReference", reference.into())]),
+    );
+
+    field(&reply[0], &["body", "variables"])
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| {
+            (
+                v.get("name").and_then(Json::as_str).unwrap().to_string(),
+                v.get("value").and_then(Json::as_str).unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+//
+// ---------- session ----------
+//
+
+#[test]
+fn initialize_sends_capabilities_then_initialized() {
+    let mut server = DapServer::new();
+    let reply = request(&mut server, 1, "initialize", Json::Null);
+
+    assert_eq!(reply.len(), 2);
+    assert_eq!(reply[0].get("request_seq"), Some(&Json::Number(1.0)));
+    assert_eq!(
+        field(&reply[0], &["body", "supportsConfigurationDoneRequest"]),
+        &Json::Bool(true)
+    );
+    assert_eq!(events(&reply), vec!["initialized"]);
+
+    // seq keeps counting across messages
+    assert_eq!(reply[1].get("seq").and_then(Json::as_u64), Some(2));
+}
+
+#[test]
+fn launch_failures_are_reported() {
+    let mut server = DapServer::new();
+
+    let reply = request(
+        &mut server,
+        1,
+        "launch",
+        Json::object(vec![("program", "tests/corpus/missing.asm".into())]),
+    );
+    assert_eq!(reply[0].get("success"), Some(&Json::Bool(false)));
+    assert!(reply[0].get("message").is_some());
+
+    let reply = request(&mut server, 2, "stackTrace", Json::Null);
+    assert_eq!(reply[0].get("success"), Some(&Json::Bool(false)));
+}
+
+#[test]
+fn unsupported_request_fails() {
+    let mut server = DapServer::new();
+    let reply = request(&mut server, 1, "stepBack", Json::Null);
+
+    assert_eq!(reply[0].get("success"), Some(&Json::Bool(false)));
+}
+
+//
+// ---------- breakpoints ----------
+//
+
+#[test]
+fn breakpoints_by_source_line() {
+    let mut server = launched(COUNTDOWN, false);
+
+    // line 5 is the label, so it moves down to the sub on line 6
+    let reply = set_breakpoints(&mut server, &[5, 8, 40]);
+    let placed = field(&reply[0], &["body", "breakpoints"])
+        .as_array()
+        .unwrap();
+
+    assert_eq!(placed[0].get("line").and_then(Json::as_u64), Some(6));
+    assert_eq!(placed[0].get("verified"), Some(&Json::Bool(true)));
+    assert_eq!(placed[1].get("line").and_then(Json::as_u64), Some(8));
+    assert_eq!(placed[2].get("verified"), Some(&Json::Bool(false)));
+
+    let reply = request(&mut server, 4, "configurationDone", Json::Null);
+    assert_eq!(events(&reply), vec!["stopped"]);
+    assert_eq!(
+        field(&reply[1], &["body", "reason"]).as_str(),
+        Some("breakpoint")
+    );
+    assert_eq!(current_line(&mut server), 6);
+
+    request(&mut server, 5, "continue", Json::Null);
+    assert_eq!(current_line(&mut server), 8);
+
+    // replacing the set drops the old breakpoints
+    set_breakpoints(&mut server, &[]);
+    let reply = request(&mut server, 6, "continue", Json::Null);
+    assert_eq!(events(&reply), vec!["exited", "terminated"]);
+}
+
+#[test]
+fn breakpoints_in_included_files() {
+    let dir = std::env::temp_dir().join(format!("risa16-dap-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (main, lib) = (dir.join("main.asm"), dir.join("lib.asm"));
+    std::fs::write(&main, "    movimm r0 1\n.include \"lib.asm\"\n    halt").unwrap();
+    std::fs::write(&lib, "// library\n    movimm r1 2\n    movimm r2 3").unwrap();
+    let main = main.display().to_string();
+    let lib = lib.display().to_string();
+
+    let mut server = launched(&main, false);
+    let set = |server: &mut DapServer, path: &str, line: u64| {
+        let reply = request(
+            server,
+            3,
+            "setBreakpoints",
+            Json::object(vec![
+                ("source", Json::object(vec![("path", path.into())])),
+                (
+                    "breakpoints",
+                    vec![Json::object(vec![("line", line.into())])].into(),
+                ),
+            ]),
+        );
+        field(&reply[0], &["body", "breakpoints"])
+            .as_array()
+            .unwrap()[0]
+            .clone()
+    };
+
+    // line 3 of the library, not of the main file
+    let placed = set(&mut server, &lib, 3);
+    assert_eq!(placed.get("verified"), Some(&Json::Bool(true)));
+    assert_eq!(
+        placed.get("instructionReference").and_then(Json::as_str),
+        Some("0x0008")
+    );
+    // setting the main file's breakpoints leaves the library's alone
+    set(&mut server, &main, 3);
+    let placed = set(&mut server, "elsewhere.asm", 1);
+    assert_eq!(placed.get("verified"), Some(&Json::Bool(false)));
+
+    let source = |server: &mut DapServer| {
+        let reply = request(
+            server,
+            10,
+            "stackTrace",
+            Json::object(vec![("threadId", 1u64.into())]),
+        );
+        let frame = &field(&reply[0], &["body", "stackFrames"])
+            .as_array()
+            .unwrap()[0];
+        let path = field(frame, &["source", "path"])
+            .as_str()
+            .unwrap()
+            .to_string();
+        (path, frame.get("line").and_then(Json::as_u64).unwrap())
+    };
+
+    request(&mut server, 4, "configurationDone", Json::Null);
+    assert_eq!(source(&mut server), (lib, 3));
+    request(&mut server, 5, "continue", Json::Null);
+    assert_eq!(source(&mut server), (main, 3));
+}
+
+#[test]
+fn conditional_breakpoint() {
+    let mut server = launched(COUNTDOWN, false);
+
+    let reply = request(
+        &mut server,
+        3,
+        "setBreakpoints",
+        Json::object(vec![
+            ("source", Json::object(vec![("path", COUNTDOWN.into())])),
+            (
+                "breakpoints",
+                vec![
+                    Json::object(vec![("line", 6u64.into()), ("condition", "r0 == 4".into())]),
+                    Json::object(vec![("line", 7u64.into()), ("condition", "r0 ==".into())]),
+                    Json::object(vec![
+                        ("line", 8u64.into()),
+                        ("condition", "count == 1".into()),
+                    ]),
+                ]
+                .into(),
+            ),
+        ]),
+    );
+    let placed = field(&reply[0], &["body", "breakpoints"])
+        .as_array()
+        .unwrap();
+    assert_eq!(placed[0].get("verified"), Some(&Json::Bool(true)));
+    assert_eq!(placed[1].get("verified"), Some(&Json::Bool(false)));
+    assert_eq!(placed[2].get("verified"), Some(&Json::Bool(false)));
+    assert_eq!(
+        placed[2].get("message").and_then(Json::as_str),
+        Some("invalid condition: unknown name `count`")
+    );
+
+    request(&mut server, 4, "configurationDone", Json::Null);
+    assert_eq!(current_line(&mut server), 6);
+    assert_eq!(server.vm.as_ref().unwrap().cpu.registers[0], 4);
+}
+
+//
+// ---------- stepping ----------
+//
+
+#[test]
+fn stop_on_entry_and_step() {
+    let mut server = launched(COUNTDOWN, true);
+
+    let reply = request(&mut server, 3, "configurationDone", Json::Null);
+    assert_eq!(
+        field(&reply[1], &["body", "reason"]).as_str(),
+        Some("entry")
+    );
+    assert_eq!(current_line(&mut server), 2);
+
+    let reply = request(
+        &mut server,
+        4,
+        "stepIn",
+        Json::object(vec![("threadId", 1u64.into())]),
+    );
+    assert_eq!(field(&reply[1], &["body", "reason"]).as_str(), Some("step"));
+    assert_eq!(current_line(&mut server), 3);
+
+    request(&mut server, 5, "next", Json::Null);
+    request(&mut server, 6, "next", Json::Null);
+    assert_eq!(current_line(&mut server), 6);
+}
+
+#[test]
+fn step_from_a_breakpoint_on_entry() {
+    let mut server = launched(COUNTDOWN, true);
+    set_breakpoints(&mut server, &[2, 3]);
+    request(&mut server, 4, "configurationDone", Json::Null);
+    assert_eq!(current_line(&mut server), 2);
+
+    request(&mut server, 5, "stepIn", Json::Null);
+    assert_eq!(current_line(&mut server), 3);
+    request(&mut server, 6, "next", Json::Null);
+    assert_eq!(current_line(&mut server), 4);
+}
+
+#[test]
+fn next_runs_loop_to_completion() {
+    let mut server = launched(COUNTDOWN, true);
+    request(&mut server, 3, "configurationDone", Json::Null);
+
+    for seq in 0..5 {
+        request(&mut server, 4 + seq, "stepIn", Json::Null);
+    }
+    assert_eq!(current_line(&mut server), 8);
+
+    request(&mut server, 10, "next", Json::Null);
+    assert_eq!(current_line(&mut server), 9);
+    assert_eq!(server.vm.as_ref().unwrap().cpu.registers[0], 0);
+}
+
+#[test]
+fn trap_stops_with_message() {
+    let mut server = launched(FAULT, false);
+
+    let reply = request(&mut server, 3, "configurationDone", Json::Null);
+    let stopped = body(&reply[1]);
+    assert_eq!(
+        stopped.get("reason").and_then(Json::as_str),
+        Some("exception")
+    );
+    assert_eq!(
+        stopped.get("text").and_then(Json::as_str),
+        Some("address out of bounds")
+    );
+    assert_eq!(current_line(&mut server), 4);
+
+    let reply = request(&mut server, 4, "exceptionInfo", Json::Null);
+    assert_eq!(
+        field(&reply[0], &["body", "description"]).as_str(),
+        Some("address out of bounds")
+    );
+
+    let reply = request(&mut server, 5, "continue", Json::Null);
+    assert_eq!(reply[0].get("success"), Some(&Json::Bool(false)));
+}
+
+//
+// ---------- inspecting state ----------
+//
+
+#[test]
+fn stack_trace_names_the_enclosing_label() {
+    let mut server = launched(COUNTDOWN, false);
+    set_breakpoints(&mut server, &[7]);
+    request(&mut server, 4, "configurationDone", Json::Null);
+
+    let reply = request(&mut server, 5, "stackTrace", Json::Null);
+    let frame = &field(&reply[0], &["body", "stackFrames"])
+        .as_array()
+        .unwrap()[0];
+
+    assert_eq!(frame.get("name").and_then(Json::as_str), Some("loop"));
+    assert_eq!(
+        field(frame, &["source", "name"]).as_str(),
+        Some("countdown.asm")
+    );
+    assert_eq!(
+        frame
+            .get("instructionPointerReference")
+            .and_then(Json::as_str),
+        Some("0x000F")
+    );
+}
+
+#[test]
+fn scopes_and_variables() {
+    let mut server = launched(FAULT, false);
+    request(&mut server, 3, "configurationDone", Json::Null);
+
+    let reply = request(
+        &mut server,
+        4,
+        "scopes",
+        Json::object(vec![("frameId", 0u64.into())]),
+    );
+    let scopes: Vec<&str> = field(&reply[0], &["body", "scopes"])
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|s| s.get("name").and_then(Json::as_str))
+        .collect();
+    assert_eq!(scopes, vec!["Registers", "Flags", "Memory"]);
+
+    let registers = variables(&mut server, 1);
+    assert_eq!(registers.len(), 17);
+    assert_eq!(registers[0], ("r0".to_string(), "0x1234".to_string()));
+    assert_eq!(registers[16], ("pc".to_string(), "0x0008".to_string()));
+
+    let flags = variables(&mut server, 2);
+    assert_eq!(flags[0], ("Z".to_string(), "false".to_string()));
+
+    let regions = variables(&mut server, 3);
+    assert_eq!(regions.len(), 16);
+    assert_eq!(regions[1].0, "0x0100-0x01FF");
+
+    let rows = variables(&mut server, 17);
+    assert_eq!(rows.len(), 16);
+    assert!(rows[0].1.starts_with("12 34 00"));
+
+    let reply = request(
+        &mut server,
+        5,
+        "variables",
+        Json::object(vec![("variablesReference", 99u64.into())]),
+    );
+    assert_eq!(reply[0].get("success"), Some(&Json::Bool(false)));
+}
+
+//
+// ---------- wire format ----------
+//
+
+#[test]
+fn serve_over_streams() {
+    let mut input: Vec<u8> = Vec::new();
+    for (seq, command, arguments) in [
+        (1u64, "initialize", Json::Null),
+        (
+            2,
+            "launch",
+            Json::object(vec![("program", COUNTDOWN.into())]),
+        ),
+        (3, "configurationDone", Json::Null),
+        (4, "disconnect", Json::Null),
+        (5, "threads", Json::Null),
+    ] {
+        let message = Json::object(vec![
+            ("seq", seq.into()),
+            ("type", "request".into()),
+            ("command", command.into()),
+            ("arguments", arguments),
+        ]);
+        write_message(&mut input, &message).unwrap();
+    }
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut server = DapServer::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    assert!(server.is_done());
+
+    let mut output = Cursor::new(output);
+    let mut messages: Vec<Json> = Vec::new();
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+
+    let commands: Vec<&str> = messages
+        .iter()
+        .filter_map(|m| m.get("command").and_then(Json::as_str))
+        .collect();
+    assert_eq!(
+        commands,
+        vec!["initialize", "launch", "configurationDone", "disconnect"]
+    );
+    assert_eq!(
+        events(&messages),
+        vec!["initialized", "exited", "terminated"]
+    );
+}
+
+#[test]
+fn pause_stops_a_running_program() {
+    let dir = std::env::temp_dir().join(format!("risa16-dap-pause-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let program = dir.join("spin.asm");
+    std::fs::write(&program, "spin:\n    movimm r0 1\n    jmp spin\n").unwrap();
+
+    let mut input: Vec<u8> = Vec::new();
+    for (seq, command, arguments) in [
+        (1u64, "initialize", Json::Null),
+        (
+            2,
+            "launch",
+            Json::object(vec![("program", program.display().to_string().into())]),
+        ),
+        (3, "configurationDone", Json::Null),
+        (4, "pause", Json::object(vec![("threadId", 1u64.into())])),
+        (5, "disconnect", Json::Null),
+    ] {
+        let message = Json::object(vec![
+            ("seq", seq.into()),
+            ("type", "request".into()),
+            ("command", command.into()),
+            ("arguments", arguments),
+        ]);
+        write_message(&mut input, &message).unwrap();
+    }
+
+    let mut output: Vec<u8> = Vec::new();
+    let mut server = DapServer::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    assert!(server.is_done());
+
+    let mut output = Cursor::new(output);
+    let mut messages: Vec<Json> = Vec::new();
+    while let Some(message) = read_message(&mut output).unwrap() {
+        messages.push(message);
+    }
+
+    let commands: Vec<&str> = messages
+        .iter()
+        .filter_map(|m| m.get("command").and_then(Json::as_str))
+        .collect();
+    assert_eq!(
+        commands,
+        vec![
+            "initialize",
+            "launch",
+            "configurationDone",
+            "pause",
+            "disconnect"
+        ]
+    );
+    assert_eq!(events(&messages), vec!["initialized", "stopped"]);
+    let stopped = messages.iter().find(|m| {
+        m.get("event")
+            .is_some_and(|e| e.as_str() == Some("stopped"))
+    });
+    assert_eq!(
+        field(stopped.unwrap(), &["body", "reason"]).as_str(),
+        Some("pause")
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// input that hands over its bytes and then waits forever, like an idle client
+struct Idle(Cursor<Vec<u8>>);
+
+impl Read for Idle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf)? {
+            0 => loop {
+                std::thread::park();
+            },
+            n => Ok(n),
+        }
+    }
+}
+
+// output whose reader has gone away
+struct Closed;
+
+impl Write for Closed {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn closed_output_ends_serving() {
+    let mut input: Vec<u8> = Vec::new();
+    let message = Json::object(vec![
+        ("seq", 1u64.into()),
+        ("type", "request".into()),
+        ("command", "initialize".into()),
+    ]);
+    write_message(&mut input, &message).unwrap();
+
+    // the reader is still waiting for more when the write fails
+    let input = BufReader::new(Idle(Cursor::new(input)));
+    let err = DapServer::new().serve(input, Closed).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+}
@@ -0,0 +1,303 @@
+use risa16::assembler::Config;
+use risa16::debugger::{Debugger, PROMPT};
+use risa16::vm::State;
+use std::io::Cursor;
+
+//
+// ---------- helpers ----------
+//
+
+const PROGRAM: &str = r#"// sums 3 + 2 + 1 into r3
+    movimm r0 3
+    movimm r1 1
+    movimm r2 0
+loop:
+    add r3 r0
+    sub r0 r1
+    cmp r0 r2
+    jmpnz loop
+    store 0x0200 r3
+    halt
+"#;
+
+fn debugger() -> Debugger {
+    Debugger::new(PROGRAM).expect("assembly failed")
+}
+
+fn run(debugger: &mut Debugger, commands: &[&str]) -> Vec<Result<String, String>> {
+    commands.iter().map(|c| debugger.command(c)).collect()
+}
+
+//
+// ---------- breakpoints ----------
+//
+
+#[test]
+fn break_on_label_and_continue() {
+    let mut dbg = debugger();
+
+    assert_eq!(
+        dbg.command("break loop").unwrap(),
+        "Breakpoint at 0x000C <loop>"
+    );
+
+    let reply = dbg.command("continue").unwrap();
+    assert!(reply.contains("Breakpoint at 0x000C <loop>"));
+    assert!(reply.contains("line 6: add r3 r0"));
+    assert_eq!(dbg.vm.cpu.pc, 0x000C);
+
+    // next time around the loop
+    dbg.command("c").unwrap();
+    assert_eq!(dbg.vm.cpu.registers[3], 3);
+}
+
+#[test]
+fn break_on_address_and_delete() {
+    let mut dbg = debugger();
+
+    dbg.command("b 0x0018").unwrap();
+    dbg.command("b loop").unwrap();
+    assert_eq!(
+        dbg.command("break").unwrap(),
+        "Breakpoint at 0x000C <loop>\nBreakpoint at 0x0018"
+    );
+
+    dbg.command("delete loop").unwrap();
+    assert!(dbg.command("delete loop").is_err());
+
+    dbg.command("c").unwrap();
+    assert_eq!(dbg.vm.cpu.pc, 0x0018);
+
+    dbg.command("delete").unwrap();
+    assert_eq!(dbg.command("break").unwrap(), "No breakpoints.");
+}
+
+#[test]
+fn unknown_label_is_an_error() {
+    let mut dbg = debugger();
+
+    assert!(dbg.command("break nowhere").is_err());
+    assert!(dbg.vm.breakpoints.is_empty());
+}
+
+//
+// ---------- stepping ----------
+//
+
+#[test]
+fn step_shows_source_line() {
+    let mut dbg = debugger();
+
+    assert_eq!(dbg.command("step").unwrap(), "0x0004  line 3: movimm r1 1");
+    assert_eq!(
+        dbg.command("s 2").unwrap(),
+        "0x000C <loop>  line 6: add r3 r0"
+    );
+    assert_eq!(dbg.vm.instret, 3);
+}
+
+#[test]
+fn step_leaves_a_breakpoint_it_is_on() {
+    let mut dbg = debugger();
+    dbg.command("b 0").unwrap();
+    dbg.command("b 0x0015").unwrap();
+
+    assert_eq!(dbg.command("step").unwrap(), "0x0004  line 3: movimm r1 1");
+
+    let reply = dbg.command("s 5").unwrap();
+    assert!(reply.starts_with("Breakpoint at 0x0015"));
+    assert_eq!(
+        dbg.command("step").unwrap(),
+        "0x000C <loop>  line 6: add r3 r0"
+    );
+}
+
+#[test]
+fn empty_line_repeats_last_command() {
+    let mut dbg = debugger();
+
+    dbg.command("step").unwrap();
+    dbg.command("").unwrap();
+    dbg.command("  ").unwrap();
+
+    assert_eq!(dbg.vm.cpu.pc, 0x000C);
+}
+
+#[test]
+fn next_runs_loop_to_completion() {
+    let mut dbg = debugger();
+
+    dbg.command("s 6").unwrap();
+    assert_eq!(dbg.vm.cpu.pc, 0x0015);
+
+    // the backward jump runs the remaining iterations
+    let reply = dbg.command("next").unwrap();
+    assert_eq!(reply, "0x0018  line 10: store 0x0200 r3");
+    assert_eq!(dbg.vm.cpu.registers[3], 6);
+    assert_eq!(dbg.vm.cpu.registers[0], 0);
+
+    // an ordinary instruction is a single step
+    dbg.command("next").unwrap();
+    assert_eq!(dbg.vm.cpu.pc, 0x001C);
+}
+
+#[test]
+fn next_stops_at_breakpoint_inside_loop() {
+    let mut dbg = debugger();
+
+    dbg.command("s 6").unwrap();
+    dbg.command("b loop").unwrap();
+
+    let reply = dbg.command("n").unwrap();
+    assert!(reply.starts_with("Breakpoint at 0x000C <loop>"));
+}
+
+#[test]
+fn halted_program_cannot_resume() {
+    let mut dbg = debugger();
+
+    let reply = dbg.command("c").unwrap();
+    assert!(reply.starts_with("Program halted."));
+    assert_eq!(dbg.vm.state, State::HALTED);
+
+    assert!(dbg.command("step").is_err());
+    assert!(dbg.command("next").is_err());
+}
+
+#[test]
+fn fault_is_reported() {
+    let mut dbg = Debugger::new("movimm r0 1\nload r0 0x0FFF").unwrap();
+
+    let reply = dbg.command("c").unwrap();
+    assert!(reply.contains("Program faulted: address out of bounds"));
+    assert!(reply.ends_with("line 2: load r0 0x0FFF"));
+}
+
+//
+// ---------- inspecting and changing state ----------
+//
+
+#[test]
+fn regs_and_flags() {
+    let mut dbg = debugger();
+    dbg.command("s 3").unwrap();
+
+    let regs = dbg.command("regs").unwrap();
+    assert!(regs.starts_with("r0  = 0x0003  r1  = 0x0001"));
+    assert!(regs.ends_with("pc  = 0x000C"));
+
+    dbg.command("s 3").unwrap();
+    assert_eq!(dbg.command("flags").unwrap(), "Z=0 C=0");
+}
+
+#[test]
+fn set_register_and_pc() {
+    let mut dbg = debugger();
+
+    assert_eq!(dbg.command("set r3 = 5").unwrap(), "r3 = 0x0005");
+    dbg.command("set r0=0x10").unwrap();
+    dbg.command("set pc = loop").unwrap();
+
+    assert_eq!(dbg.vm.cpu.registers[3], 5);
+    assert_eq!(dbg.vm.cpu.registers[0], 0x10);
+    assert_eq!(dbg.vm.cpu.pc, 0x000C);
+
+    assert!(dbg.command("set r16 = 1").is_err());
+    assert!(dbg.command("set r1 5").is_err());
+}
+
+#[test]
+fn examine_memory() {
+    let mut dbg = debugger();
+
+    assert_eq!(dbg.command("x/4x 0").unwrap(), "0x0000:  01 00 00 03");
+    assert_eq!(dbg.command("x/2w loop").unwrap(), "0x000C:  0503 0006");
+    assert_eq!(dbg.command("x/20x 0").unwrap().lines().count(), 2);
+
+    assert!(dbg.command("x/4x 0x0FFE").is_err());
+    assert!(dbg.command("x/18446744073709551615w 0").is_err());
+    assert!(dbg.command("x/9223372036854775807w 2").is_err());
+    assert!(dbg.command("x/4q 0").is_err());
+}
+
+#[test]
+fn disasm_marks_pc_and_breakpoints() {
+    let mut dbg = debugger();
+    dbg.command("b loop").unwrap();
+    dbg.command("c").unwrap();
+
+    let listing = dbg.command("disasm loop 4").unwrap();
+    let lines: Vec<&str> = listing.lines().collect();
+
+    assert_eq!(lines[0], "=>* 0x000C <loop>:  add r3 r0");
+    assert_eq!(lines[3], "    0x0015:  jmpnz 0x000C <loop>");
+}
+
+#[test]
+fn list_shows_source_around_pc() {
+    let mut dbg = debugger();
+    dbg.command("s 3").unwrap();
+
+    let listing = dbg.command("list").unwrap();
+    assert!(listing.contains("=>    6      add r3 r0"));
+    assert!(listing.lines().count() > 5);
+}
+
+#[test]
+fn included_files_show_their_own_lines() {
+    let dir = std::env::temp_dir().join(format!("risa16-debugger-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let main = dir.join("main.asm");
+    std::fs::write(&main, "    movimm r0 N\n.include \"lib.asm\"\n    halt").unwrap();
+    std::fs::write(dir.join("lib.asm"), "// library\n    movimm r1 2\n").unwrap();
+
+    let config = Config {
+        defines: vec![("N".to_string(), 7)],
+        ..Config::default()
+    };
+    let mut dbg = Debugger::open(&main, &config).expect("assembly failed");
+
+    assert!(
+        dbg.command("step")
+            .unwrap()
+            .ends_with("lib.asm:2: movimm r1 2")
+    );
+    assert_eq!(dbg.vm.cpu.registers[0], 7);
+    let listing = dbg.command("list").unwrap();
+    assert!(listing.lines().next().unwrap().ends_with("lib.asm"));
+    assert!(listing.contains("=>    2      movimm r1 2"));
+
+    assert_eq!(dbg.command("step").unwrap(), "0x0008  line 3: halt");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unknown_command_is_an_error() {
+    let mut dbg = debugger();
+
+    for reply in run(&mut dbg, &["frobnicate", "step 1 2", "disasm 0 x"]) {
+        assert!(reply.is_err());
+    }
+}
+
+//
+// ---------- repl ----------
+//
+
+#[test]
+fn repl_prints_prompt_and_replies() {
+    let mut dbg = debugger();
+    let input = Cursor::new("break loop\nc\nbogus\nquit\nstep\n");
+    let mut output: Vec<u8> = Vec::new();
+
+    dbg.repl(input, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output.starts_with("0x0000  line 2: movimm r0 3\n"));
+    assert!(output.contains(&format!("{}Breakpoint at 0x000C <loop>\n", PROMPT)));
+    assert!(output.contains("error: unknown command `bogus`"));
+
+    // nothing after quit runs
+    assert!(dbg.is_done());
+    assert_eq!(dbg.vm.cpu.pc, 0x000C);
+}
@@ -211,6 +211,55 @@ fn decode_jump_nz() {
     assert_eq!(decoded.length, 3);
 }
 
+#[test]
+fn decode_rdcycle() {
+    let bytes = vec![0x0B, 0x03];
+
+    let decoded = decode(&bytes, 0).expect("decode failed");
+
+    match decoded.instr {
+        Instruction::RdCycle { reg } => assert_eq!(reg, 3),
+        _ => panic!("Expected RdCycle"),
+    }
+
+    assert_eq!(decoded.length, 2);
+}
+
+#[test]
+fn decode_rdinstret() {
+    let bytes = vec![0x0C, 0x0F];
+
+    let decoded = decode(&bytes, 0).expect("decode failed");
+
+    match decoded.instr {
+        Instruction::RdInstret { reg } => assert_eq!(reg, 15),
+        _ => panic!("Expected RdInstret"),
+    }
+
+    assert_eq!(decoded.length, 2);
+}
+
+#[test]
+fn decode_in_out() {
+    let bytes = vec![0x0D, 0x02, 0x01, 0x0E, 0x00, 0x05];
+
+    match decode(&bytes, 0).expect("decode failed").instr {
+        Instruction::In { reg, port } => {
+            assert_eq!(reg, 2);
+            assert_eq!(port, 1);
+        }
+        _ => panic!("Expected In"),
+    }
+
+    match decode(&bytes, 3).expect("decode failed").instr {
+        Instruction::Out { port, reg } => {
+            assert_eq!(port, 0);
+            assert_eq!(reg, 5);
+        }
+        _ => panic!("Expected Out"),
+    }
+}
+
 #[test]
 fn decode_halt() {
     let bytes = vec![0xFF];
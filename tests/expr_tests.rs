@@ -0,0 +1,337 @@
+use risa16::assembler::assemble;
+use risa16::debugger::Debugger;
+use risa16::expr::{Bindings, Condition, Env, Expr};
+use risa16::vm::{ExitReason, VM};
+use risa16::watchpoints::{Access, Action};
+
+//
+// ---------- helpers ----------
+//
+
+const COUNTDOWN: &str = include_str!("corpus/countdown.asm");
+
+// the address of `loop:` in the countdown
+const LOOP: u16 = 0x000C;
+
+fn load_program(src: &str) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm
+}
+
+// a VM with some registers, flags and memory set up to evaluate against
+fn machine() -> VM {
+    let mut vm = VM::new();
+    vm.cpu.registers[3] = 0x10;
+    vm.cpu.pc = 0x0040;
+    vm.zero_flag = true;
+    vm.memory.data[0x0200] = 0x12;
+    vm.memory.data[0x0201] = 0x34;
+    vm
+}
+
+fn eval(src: &str) -> Result<i64, String> {
+    Expr::parse(src)?.eval(&machine())
+}
+
+//
+// ---------- parsing ----------
+//
+
+#[test]
+fn precedence_follows_c() {
+    assert_eq!(eval("1 + 2 * 3"), Ok(7));
+    assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+    assert_eq!(eval("1 << 2 + 1"), Ok(8));
+    assert_eq!(eval("6 & 3 == 3"), Ok(0));
+    assert_eq!(eval("1 || 0 && 0"), Ok(1));
+    assert_eq!(eval("10 - 4 - 3"), Ok(3));
+    assert_eq!(eval("-2 * -3"), Ok(6));
+}
+
+#[test]
+fn byte_functions() {
+    assert_eq!(eval("hi(0x1234)"), Ok(0x12));
+    assert_eq!(eval("lo(0x1234) + 1"), Ok(0x35));
+    assert_eq!(eval("hi(-1)"), Ok(0xFF));
+    assert!(Expr::parse("hi 1").is_err());
+    assert!(Expr::parse("lo(1").is_err());
+    assert!(Expr::parse("hi(mem8[0])").unwrap().reads_memory());
+    assert!(!Expr::parse("hi(r0)").unwrap().reads_memory());
+}
+
+#[test]
+fn malformed_expressions_are_errors() {
+    for src in [
+        "",
+        "1 +",
+        "(1",
+        "mem16[0x200",
+        "r3 = 1",
+        "1 2",
+        "0xZZ",
+        "r3 $ 1",
+    ] {
+        assert!(Expr::parse(src).is_err(), "`{}` parsed", src);
+    }
+}
+
+#[test]
+fn deep_nesting_is_an_error() {
+    let too_deep = "expression nested too deeply".to_string();
+
+    let parens = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+    assert_eq!(Expr::parse(&parens), Err(too_deep.clone()));
+    assert_eq!(Expr::parse(&"-".repeat(100_000)), Err(too_deep.clone()));
+    assert_eq!(Expr::parse(&"1+".repeat(100_000)), Err(too_deep));
+
+    // the deepest accepted forms parse and evaluate on a test thread's stack
+    let nested = format!("{}1{}", "(".repeat(127), ")".repeat(127));
+    assert_eq!(eval(&nested), Ok(1));
+    assert_eq!(eval(&format!("{}1", "-".repeat(127))), Ok(-1));
+    assert_eq!(eval(&format!("{}1", "1+".repeat(127))), Ok(128));
+    let mixed = format!("{}1{}", "-(".repeat(63), ")".repeat(63));
+    assert_eq!(eval(&mixed), Ok(-1));
+}
+
+#[test]
+fn condition_keeps_its_source() {
+    let condition = Condition::parse("  r3 == 0x10 && Z ").unwrap();
+    assert_eq!(condition.source, "r3 == 0x10 && Z");
+    assert!(condition.holds(&machine()).unwrap());
+}
+
+//
+// ---------- evaluation ----------
+//
+
+#[test]
+fn machine_state_names() {
+    assert_eq!(eval("r3 == 0x10 && Z"), Ok(1));
+    assert_eq!(eval("C"), Ok(0));
+    assert_eq!(eval("pc"), Ok(0x40));
+    assert_eq!(eval("mem8[0x200]"), Ok(0x12));
+    assert_eq!(eval("mem16[0x200]"), Ok(0x1234));
+    assert_eq!(eval("mem16[0x1FF + 1] > 5"), Ok(1));
+    assert_eq!(eval("instret + cycles"), Ok(0));
+}
+
+#[test]
+fn evaluation_errors() {
+    assert!(eval("r16").unwrap_err().contains("unknown name `r16`"));
+    assert!(eval("hits").is_err());
+    assert!(eval("r3 / r0").unwrap_err().contains("division by zero"));
+    assert!(eval("mem16[0x0FFF]").unwrap_err().contains("out of bounds"));
+    assert!(eval("mem8[-1]").is_err());
+
+    // the right side of && is not evaluated when the left is false
+    assert_eq!(eval("C && r3 / r0"), Ok(0));
+}
+
+#[test]
+fn checked_evaluation_rejects_overflow() {
+    let checked = |src: &str| Expr::parse(src).unwrap().eval_checked(&machine());
+
+    assert_eq!(eval("0x7FFFFFFFFFFFFFFF + 1"), Ok(i64::MIN));
+    assert_eq!(eval("1 << 64"), Ok(0));
+    assert_eq!(
+        checked("0x7FFFFFFFFFFFFFFF + 1"),
+        Err("arithmetic overflow".into())
+    );
+    assert_eq!(
+        checked("1 << 64"),
+        Err("shift by 64 is out of range".into())
+    );
+    assert_eq!(checked("r3 * 2 - 1 << 2"), Ok(124));
+    assert_eq!(checked("r3 / 0"), Err("division by zero".into()));
+}
+
+#[test]
+fn bindings_add_names() {
+    let vm = machine();
+    let names = [("hits", 7), ("r3", 1)];
+    let env = Bindings {
+        env: &vm,
+        names: &names,
+    };
+
+    assert_eq!(env.lookup("hits"), Some(7));
+    assert_eq!(env.lookup("r3"), Some(1));
+    assert_eq!(
+        Expr::parse("hits * r3 + mem8[0x201]").unwrap().eval(&env),
+        Ok(7 + 0x34)
+    );
+}
+
+#[test]
+fn check_names_does_not_evaluate() {
+    let expr = Expr::parse("mem16[0x0FFF] / 0").unwrap();
+    assert!(expr.check_names(&machine()).is_ok());
+
+    let expr = Expr::parse("r1 + count").unwrap();
+    assert!(expr.check_names(&machine()).is_err());
+}
+
+//
+// ---------- breakpoints ----------
+//
+
+#[test]
+fn conditional_breakpoint() {
+    let mut vm = load_program(COUNTDOWN);
+    let condition = Condition::parse("r0 == 5").unwrap();
+    vm.breakpoints.set(LOOP, Some(condition));
+
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(LOOP));
+    assert_eq!(vm.cpu.registers[0], 5);
+    assert_eq!(vm.breakpoints.get(LOOP).unwrap().hits, 6);
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+    assert_eq!(vm.breakpoints.get(LOOP).unwrap().hits, 10);
+}
+
+#[test]
+fn breakpoint_on_hit_count() {
+    let mut vm = load_program(COUNTDOWN);
+    vm.breakpoints
+        .set(LOOP, Some(Condition::parse("hits > 8").unwrap()));
+
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(LOOP));
+    assert_eq!(vm.cpu.registers[0], 2);
+
+    // every later arrival satisfies it too
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(LOOP));
+    assert_eq!(vm.cpu.registers[0], 1);
+}
+
+#[test]
+fn failing_condition_stops_with_the_error() {
+    let mut vm = load_program(COUNTDOWN);
+    vm.breakpoints
+        .set(LOOP, Some(Condition::parse("r0 / r2").unwrap()));
+
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(LOOP));
+    let error = vm.breakpoints.get(LOOP).unwrap().error.clone();
+    assert_eq!(error, Some("division by zero".to_string()));
+}
+
+#[test]
+fn plain_insert_keeps_an_existing_condition() {
+    let mut vm = load_program(COUNTDOWN);
+    vm.breakpoints
+        .set(LOOP, Some(Condition::parse("r0 == 1").unwrap()));
+
+    assert!(!vm.breakpoints.insert(LOOP));
+    assert_eq!(vm.breakpoints.len(), 1);
+
+    vm.run();
+    assert_eq!(vm.cpu.registers[0], 1);
+}
+
+//
+// ---------- watchpoints ----------
+//
+
+#[test]
+fn conditional_watchpoint_sees_old_and_new() {
+    let mut vm = load_program(COUNTDOWN);
+    let id = vm.watchpoints.watch_register(0, Action::Stop).unwrap();
+    vm.watchpoints
+        .set_condition(id, Some(Condition::parse("new < old && new == 3").unwrap()));
+
+    match vm.run() {
+        ExitReason::WatchpointHit(hit) => assert_eq!((hit.old, hit.new), (4, 3)),
+        other => panic!("expected a watchpoint, got {:?}", other),
+    }
+
+    // every change counts towards hits, not just the ones that stop
+    assert_eq!(vm.watchpoints.list()[0].hits, 8);
+    assert_eq!(vm.watchpoints.log.len(), 1);
+}
+
+#[test]
+fn memory_watchpoint_on_hit_count() {
+    let mut vm =
+        load_program("movimm r0 1\nstore 0x0200 r0\nstore 0x0200 r0\nstore 0x0200 r0\nhalt");
+    let id = vm
+        .watchpoints
+        .watch_memory(0x0200, 2, Access::Write, Action::Log)
+        .unwrap();
+    vm.watchpoints
+        .set_condition(id, Some(Condition::parse("hits >= 2").unwrap()));
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+    let pcs: Vec<u16> = vm.watchpoints.log.iter().map(|h| h.pc).collect();
+    assert_eq!(pcs, vec![0x0008, 0x000C]);
+
+    assert!(!vm.watchpoints.set_condition(id + 1, None));
+}
+
+//
+// ---------- debugger ----------
+//
+
+#[test]
+fn debugger_break_if() {
+    let mut dbg = Debugger::new(COUNTDOWN).unwrap();
+
+    assert_eq!(
+        dbg.command("break loop if r0 == 5").unwrap(),
+        "Breakpoint at 0x000C <loop> if r0 == 5"
+    );
+    assert!(dbg.command("break loop if r0 ==").is_err());
+    assert!(dbg.command("break loop if count > 1").is_err());
+
+    dbg.command("c").unwrap();
+    assert_eq!(dbg.vm.cpu.registers[0], 5);
+    assert_eq!(
+        dbg.command("break").unwrap(),
+        "Breakpoint at 0x000C <loop> if r0 == 5, hit 6 times"
+    );
+
+    assert_eq!(
+        dbg.command("condition loop hits % 2 == 0").unwrap(),
+        "Breakpoint at 0x000C <loop> if hits % 2 == 0"
+    );
+    // changing the condition restarts the count
+    dbg.command("c").unwrap();
+    assert_eq!(dbg.vm.cpu.registers[0], 3);
+    dbg.command("c").unwrap();
+    assert_eq!(dbg.vm.cpu.registers[0], 1);
+
+    assert_eq!(
+        dbg.command("condition loop").unwrap(),
+        "Breakpoint at 0x000C <loop>"
+    );
+    assert!(dbg.command("condition 0x0000 r0").is_err());
+}
+
+#[test]
+fn debugger_reports_condition_errors() {
+    let mut dbg = Debugger::new(COUNTDOWN).unwrap();
+
+    dbg.command("break loop if mem16[r0 * 0x1000]").unwrap();
+    let reply = dbg.command("c").unwrap();
+    assert!(reply.contains("Breakpoint at 0x000C <loop>"));
+    assert!(reply.contains("Error in condition: address"));
+}
+
+#[test]
+fn debugger_watch_if() {
+    let mut dbg = Debugger::new(COUNTDOWN).unwrap();
+
+    assert_eq!(
+        dbg.command("watch r0 if new == 2").unwrap(),
+        "Watchpoint 1: changes to r0 if new == 2"
+    );
+    assert!(dbg.command("watch r0 if hits > bogus").is_err());
+
+    let reply = dbg.command("c").unwrap();
+    assert!(reply.starts_with("watchpoint 1: r0 changed at PC 0x000C, 0x0003 -> 0x0002"));
+    assert_eq!(
+        dbg.command("watch").unwrap(),
+        "Watchpoint 1: changes to r0 if new == 2, hit 9 times"
+    );
+}
@@ -0,0 +1,349 @@
+use risa16::assembler::assemble;
+use risa16::gdbstub::{GdbStub, TARGET_XML, checksum, frame};
+use risa16::vm::VM;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+//
+// ---------- helpers ----------
+//
+
+const PROGRAM: &str = r#"
+    movimm r0 3
+    movimm r1 1
+    movimm r2 0
+loop:
+    sub r0 r1
+    cmp r0 r2
+    jmpnz loop
+    halt
+"#;
+
+// a jump to its own address falls through, so spin over two instructions
+const SPIN: &str = "loop:\n    movimm r0 1\n    jmp loop";
+
+fn stub(src: &str) -> GdbStub {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    GdbStub::new(vm)
+}
+
+fn send(stub: &mut GdbStub, packet: &str) -> String {
+    stub.handle(packet, &mut || false).expect("no reply")
+}
+
+// reads one framed reply and acks it
+fn receive(stream: &mut TcpStream) -> String {
+    let mut byte = [0u8; 1];
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+
+    let mut data: Vec<u8> = Vec::new();
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        if byte[0] == b'#' {
+            break;
+        }
+        data.push(byte[0]);
+    }
+
+    let mut sum = [0u8; 2];
+    stream.read_exact(&mut sum).unwrap();
+    assert_eq!(
+        u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap(),
+        checksum(&data)
+    );
+
+    stream.write_all(b"+").unwrap();
+    String::from_utf8(data).unwrap()
+}
+
+// sends a packet, checks it was acked and returns the reply
+fn request(stream: &mut TcpStream, packet: &str) -> String {
+    stream.write_all(frame(packet).as_bytes()).unwrap();
+
+    let mut ack = [0u8; 1];
+    stream.read_exact(&mut ack).unwrap();
+    assert_eq!(ack[0], b'+');
+
+    receive(stream)
+}
+
+//
+// ---------- framing ----------
+//
+
+#[test]
+fn frame_adds_checksum() {
+    assert_eq!(frame("OK"), "$OK#9a");
+    assert_eq!(frame(""), "$#00");
+}
+
+#[test]
+fn frame_escapes_special_bytes() {
+    assert_eq!(
+        frame("a#b"),
+        format!("$a}}\x03b#{:02x}", checksum(b"a}\x03b"))
+    );
+}
+
+//
+// ---------- registers ----------
+//
+
+#[test]
+fn read_all_registers() {
+    let mut stub = stub(PROGRAM);
+    send(&mut stub, "s");
+    stub.vm.carry_flag = true;
+
+    let regs = send(&mut stub, "g");
+
+    assert_eq!(regs.len(), 18 * 4);
+    assert!(regs.starts_with("0003"));
+    assert_eq!(&regs[16 * 4..], "00040002");
+}
+
+#[test]
+fn write_all_registers() {
+    let mut stub = stub(PROGRAM);
+    let regs: String = (0..18).map(|n| format!("{:04x}", n + 0x100)).collect();
+
+    assert_eq!(send(&mut stub, &format!("G{}", regs)), "OK");
+
+    assert_eq!(stub.vm.cpu.registers[15], 0x010F);
+    assert_eq!(stub.vm.cpu.pc, 0x0110);
+    assert!(stub.vm.zero_flag);
+    assert!(!stub.vm.carry_flag);
+
+    assert_eq!(send(&mut stub, "G0000"), "E01");
+}
+
+#[test]
+fn single_register_access() {
+    let mut stub = stub(PROGRAM);
+
+    assert_eq!(send(&mut stub, "P3=beef"), "OK");
+    assert_eq!(send(&mut stub, "p3"), "beef");
+
+    assert_eq!(send(&mut stub, "P10=000c"), "OK");
+    assert_eq!(stub.vm.cpu.pc, 0x000C);
+
+    assert_eq!(send(&mut stub, "P11=0001"), "OK");
+    assert!(stub.vm.zero_flag);
+    assert_eq!(send(&mut stub, "p11"), "0001");
+
+    assert_eq!(send(&mut stub, "p12"), "E01");
+    assert_eq!(send(&mut stub, "P3=1"), "E01");
+}
+
+//
+// ---------- memory ----------
+//
+
+#[test]
+fn read_and_write_memory() {
+    let mut stub = stub(PROGRAM);
+
+    assert_eq!(send(&mut stub, "m0,4"), "01000003");
+
+    assert_eq!(send(&mut stub, "M200,2:abcd"), "OK");
+    assert_eq!(stub.vm.memory.data[0x200], 0xAB);
+    assert_eq!(send(&mut stub, "m200,2"), "abcd");
+
+    assert_eq!(send(&mut stub, "mfff,2"), "E01");
+    assert_eq!(send(&mut stub, "M200,2:ab"), "E01");
+}
+
+#[test]
+fn ranges_past_the_end_of_the_address_space() {
+    let mut stub = stub(PROGRAM);
+
+    assert_eq!(send(&mut stub, "mffffffffffffffff,2"), "E01");
+    assert_eq!(send(&mut stub, "M2,ffffffffffffffff:ab"), "E01");
+    assert_eq!(
+        send(
+            &mut stub,
+            "qXfer:features:read:target.xml:10,ffffffffffffffff"
+        ),
+        "E01"
+    );
+}
+
+//
+// ---------- execution ----------
+//
+
+#[test]
+fn step_and_continue_to_breakpoint() {
+    let mut stub = stub(PROGRAM);
+
+    assert_eq!(send(&mut stub, "?"), "S05");
+    assert_eq!(send(&mut stub, "s"), "S05");
+    assert_eq!(stub.vm.cpu.pc, 0x0004);
+
+    assert_eq!(send(&mut stub, "Z0,c,1"), "OK");
+    assert_eq!(send(&mut stub, "c"), "S05");
+    assert_eq!(stub.vm.cpu.pc, 0x000C);
+    assert_eq!(stub.vm.cpu.registers[0], 3);
+
+    assert_eq!(send(&mut stub, "c"), "S05");
+    assert_eq!(stub.vm.cpu.registers[0], 2);
+
+    assert_eq!(send(&mut stub, "z0,c,1"), "OK");
+    assert_eq!(send(&mut stub, "c"), "W00");
+    assert_eq!(send(&mut stub, "?"), "W00");
+}
+
+#[test]
+fn continue_from_address() {
+    let mut stub = stub(PROGRAM);
+
+    assert_eq!(send(&mut stub, "c15"), "W00");
+    assert_eq!(stub.vm.cpu.registers[0], 0);
+}
+
+#[test]
+fn fault_is_reported_as_segv() {
+    let mut stub = stub("load r0 0x0FFF");
+
+    assert_eq!(send(&mut stub, "c"), "S0B");
+    assert_eq!(stub.vm.cpu.pc, 0);
+}
+
+#[test]
+fn interrupt_stops_continue() {
+    let mut stub = stub(SPIN);
+    let mut polls = 0;
+
+    let reply = stub.handle("c", &mut || {
+        polls += 1;
+        polls == 3
+    });
+
+    assert_eq!(reply.as_deref(), Some("S02"));
+    assert_eq!(polls, 3);
+}
+
+#[test]
+fn watchpoints_stop_continue() {
+    let mut stub = stub("    movimm r0 5\n    store 0x0200 r0\n    load r1 0x0200\n    halt");
+
+    assert_eq!(send(&mut stub, "Z2,200,2"), "OK");
+    assert_eq!(send(&mut stub, "Z3,200,2"), "OK");
+    assert_eq!(send(&mut stub, "c"), "T05watch:200;");
+    assert_eq!(stub.vm.cpu.pc, 0x0008);
+    assert_eq!(send(&mut stub, "c"), "T05rwatch:200;");
+
+    assert_eq!(send(&mut stub, "z2,200,2"), "OK");
+    assert_eq!(send(&mut stub, "z2,200,2"), "E01");
+    assert_eq!(send(&mut stub, "Z2,200,0"), "E01");
+    assert_eq!(send(&mut stub, "c"), "W00");
+}
+
+#[test]
+fn unsupported_packets_get_empty_reply() {
+    let mut stub = stub(PROGRAM);
+
+    assert_eq!(send(&mut stub, "Z1,200,2"), "");
+    assert_eq!(send(&mut stub, "vMustReplyEmpty"), "");
+    assert_eq!(send(&mut stub, "Hg0"), "OK");
+}
+
+//
+// ---------- target description ----------
+//
+
+#[test]
+fn target_xml_is_served_in_chunks() {
+    let mut stub = stub(PROGRAM);
+
+    assert!(send(&mut stub, "qSupported:multiprocess+").contains("qXfer:features:read+"));
+
+    let mut xml = String::new();
+    loop {
+        let reply = send(
+            &mut stub,
+            &format!("qXfer:features:read:target.xml:{:x},40", xml.len()),
+        );
+        xml.push_str(&reply[1..]);
+        if reply.starts_with('l') {
+            break;
+        }
+        assert!(reply.starts_with('m'));
+    }
+
+    assert_eq!(xml, TARGET_XML);
+    assert!(xml.contains(r#"<reg name="pc" bitsize="16" type="code_ptr"/>"#));
+    assert_eq!(xml.matches("<reg ").count(), 18);
+}
+
+//
+// ---------- over tcp ----------
+//
+
+#[test]
+fn session_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut stub = stub(PROGRAM);
+        stub.listen(&listener).unwrap();
+        stub
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+
+    assert_eq!(request(&mut client, "?"), "S05");
+    assert_eq!(request(&mut client, "Z0,f,1"), "OK");
+    assert_eq!(request(&mut client, "c"), "S05");
+    assert_eq!(request(&mut client, "p10"), "000f");
+
+    // a corrupted packet is nacked
+    client.write_all(b"$g#00").unwrap();
+    let mut nack = [0u8; 1];
+    client.read_exact(&mut nack).unwrap();
+    assert_eq!(nack[0], b'-');
+
+    assert_eq!(request(&mut client, "QStartNoAckMode"), "OK");
+    client.write_all(frame("m0,1").as_bytes()).unwrap();
+    assert_eq!(receive(&mut client), "01");
+
+    client.write_all(frame("D").as_bytes()).unwrap();
+    assert_eq!(receive(&mut client), "OK");
+
+    let stub = server.join().unwrap();
+    assert!(stub.is_done());
+    assert_eq!(stub.vm.cpu.pc, 0x000F);
+}
+
+#[test]
+fn interrupt_over_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut stub = stub(SPIN);
+        stub.listen(&listener).unwrap();
+    });
+
+    let mut client = TcpStream::connect(addr).unwrap();
+
+    client.write_all(frame("c").as_bytes()).unwrap();
+    let mut ack = [0u8; 1];
+    client.read_exact(&mut ack).unwrap();
+
+    client.write_all(&[0x03]).unwrap();
+    assert_eq!(receive(&mut client), "S02");
+
+    client.write_all(frame("k").as_bytes()).unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,178 @@
+use risa16::assembler::assemble;
+use risa16::vm::{ExitReason, State, VM};
+
+//
+// ---------- helpers ----------
+//
+
+const PROGRAM: &str = r#"
+    movimm r0 3
+    movimm r1 1
+    movimm r2 0
+loop:
+    sub r0 r1
+    store 0x0200 r0
+    in r3 0
+    out 0 r3
+    cmp r0 r2
+    jmpnz loop
+    store 0x0300 r1
+    load r4 0x0FFF
+"#;
+
+fn load_program(src: &str) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm.devices.console.input.extend(b"abc");
+    vm.enable_journal(1 << 20);
+    vm
+}
+
+fn assert_same_machine(a: &VM, b: &VM) {
+    assert_eq!(a.cpu.registers, b.cpu.registers);
+    assert_eq!(a.cpu.pc, b.cpu.pc);
+    assert_eq!(a.zero_flag, b.zero_flag);
+    assert_eq!(a.carry_flag, b.carry_flag);
+    assert_eq!(a.state, b.state);
+    assert_eq!(a.fault, b.fault);
+    assert_eq!(a.fuel, b.fuel);
+    assert_eq!(a.instret, b.instret);
+    assert_eq!(a.cycles, b.cycles);
+    assert_eq!(a.memory.data, b.memory.data);
+    assert_eq!(a.devices.console.input, b.devices.console.input);
+    assert_eq!(a.devices.console.output, b.devices.console.output);
+}
+
+//
+// ---------- step_back ----------
+//
+
+#[test]
+fn step_back_undoes_every_step() {
+    let mut vm = load_program(PROGRAM);
+    let mut history: Vec<VM> = Vec::new();
+
+    while vm.state == State::RUNNING {
+        history.push(vm.clone());
+        vm.step();
+    }
+    assert!(vm.fault.is_some());
+
+    while let Some(before) = history.pop() {
+        assert!(vm.step_back());
+        assert_same_machine(&vm, &before);
+    }
+
+    assert!(!vm.step_back());
+}
+
+#[test]
+fn replay_after_step_back_is_identical() {
+    let mut straight = load_program(PROGRAM);
+    straight.run();
+
+    let mut vm = load_program(PROGRAM);
+    vm.run();
+    for _ in 0..10 {
+        vm.step_back();
+    }
+    assert_eq!(vm.state, State::RUNNING);
+
+    vm.run();
+    assert_same_machine(&vm, &straight);
+}
+
+#[test]
+fn no_journal_means_no_history() {
+    let mut vm = load_program(PROGRAM);
+    vm.journal = None;
+
+    vm.run();
+    assert!(!vm.step_back());
+}
+
+//
+// ---------- reverse continue ----------
+//
+
+#[test]
+fn run_back_to_address() {
+    let mut vm = load_program(PROGRAM);
+    vm.run();
+
+    // the last time through the loop body
+    assert!(vm.run_back_to(0x000C));
+    assert_eq!(vm.cpu.registers[0], 1);
+
+    // and the time before that
+    assert!(vm.run_back_to(0x000C));
+    assert_eq!(vm.cpu.registers[0], 2);
+
+    assert!(!vm.run_back_to(0x0100));
+}
+
+#[test]
+fn run_back_to_last_write() {
+    let mut vm = load_program(PROGRAM);
+    vm.run();
+
+    // stops on the store itself, before it ran
+    assert!(vm.run_back_to_write(0x0201));
+    assert_eq!(vm.cpu.pc, 0x000F);
+    assert_eq!(vm.cpu.registers[0], 0);
+    assert_eq!(vm.memory.data[0x0201], 1);
+
+    assert!(vm.run_back_to_write(0x0201));
+    assert_eq!(vm.memory.data[0x0201], 2);
+
+    // high byte of the same word, written by the first store
+    assert!(vm.run_back_to_write(0x0200));
+    assert_eq!(vm.memory.data[0x0201], 0);
+    assert_eq!(vm.cpu.registers[0], 2);
+
+    // nothing wrote it before the first store
+    assert!(!vm.run_back_to_write(0x0201));
+    assert_eq!(vm.cpu.pc, 0);
+}
+
+#[test]
+fn step_back_resumes_after_fault() {
+    let mut vm = load_program(PROGRAM);
+
+    assert!(matches!(vm.run(), ExitReason::Faulted(_)));
+    assert!(vm.step_back());
+
+    assert_eq!(vm.state, State::RUNNING);
+    assert_eq!(vm.fault, None);
+    assert_eq!(vm.cpu.pc, 0x0023);
+
+    // the store before the faulting load still happened
+    assert_eq!(vm.memory.data[0x0301], 1);
+    assert!(vm.step_back());
+    assert_eq!(vm.memory.data[0x0301], 0);
+}
+
+//
+// ---------- budget ----------
+//
+
+#[test]
+fn budget_drops_oldest_entries() {
+    let mut vm = load_program(PROGRAM);
+    vm.enable_journal(1024);
+
+    vm.run();
+
+    let journal = vm.journal.as_ref().unwrap();
+    assert!(!journal.is_empty());
+    assert!((journal.len() as u64) < vm.instret);
+
+    let mut undone = 0;
+    while vm.step_back() {
+        undone += 1;
+    }
+    assert!(undone > 0);
+    assert_ne!(vm.cpu.pc, 0);
+}
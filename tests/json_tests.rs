@@ -0,0 +1,88 @@
+use risa16::json::Json;
+
+//
+// ---------- parsing ----------
+//
+
+#[test]
+fn parse_scalars() {
+    assert_eq!(Json::parse("null").unwrap(), Json::Null);
+    assert_eq!(Json::parse(" true ").unwrap(), Json::Bool(true));
+    assert_eq!(Json::parse("-12.5e1").unwrap(), Json::Number(-125.0));
+    assert_eq!(
+        Json::parse(r#""a\"b\\c\né😀""#).unwrap(),
+        Json::String("a\"b\\c\né😀".to_string())
+    );
+}
+
+#[test]
+fn parse_nested() {
+    let value = Json::parse(r#"{"seq": 3, "arguments": {"lines": [1, 2], "ok": false}}"#).unwrap();
+
+    assert_eq!(value.get("seq").and_then(Json::as_u64), Some(3));
+
+    let args = value.get("arguments").unwrap();
+    let lines: Vec<u64> = args
+        .get("lines")
+        .and_then(Json::as_array)
+        .unwrap()
+        .iter()
+        .filter_map(Json::as_u64)
+        .collect();
+    assert_eq!(lines, vec![1, 2]);
+    assert_eq!(args.get("ok").and_then(Json::as_bool), Some(false));
+    assert_eq!(args.get("missing"), None);
+}
+
+#[test]
+fn parse_errors() {
+    for bad in [
+        "",
+        "{",
+        "[1,]",
+        r#"{"a" 1}"#,
+        r#""open"#,
+        "tru",
+        "1 2",
+        r#""\x""#,
+    ] {
+        assert!(Json::parse(bad).is_err(), "{:?} should not parse", bad);
+    }
+}
+
+#[test]
+fn as_u64_rejects_fractions_and_negatives() {
+    assert_eq!(Json::Number(1.5).as_u64(), None);
+    assert_eq!(Json::Number(-1.0).as_u64(), None);
+    assert_eq!(Json::String("1".into()).as_u64(), None);
+}
+
+//
+// ---------- writing ----------
+//
+
+#[test]
+fn write_compact() {
+    let value = Json::object(vec![
+        ("name", "r\"0\"\n".into()),
+        ("value", 7u64.into()),
+        (
+            "items",
+            vec![Json::Null, true.into(), Json::Number(0.5)].into(),
+        ),
+        ("empty", Json::Object(Vec::new())),
+    ]);
+
+    assert_eq!(
+        value.to_string(),
+        r#"{"name":"r\"0\"\n","value":7,"items":[null,true,0.5],"empty":{}}"#
+    );
+}
+
+#[test]
+fn write_then_parse_roundtrip() {
+    let src = r#"{"a":[1,{"b":"\u0001\t"}],"c":-3}"#;
+    let value = Json::parse(src).unwrap();
+
+    assert_eq!(Json::parse(&value.to_string()).unwrap(), value);
+}
@@ -0,0 +1,132 @@
+use risa16::assembler::{DebugInfo, assemble_with_debug};
+use risa16::memdump::{self, Change};
+use risa16::memory::Memory;
+use risa16::vm::VM;
+
+//
+// ---------- helpers ----------
+//
+
+// writes two words, at 0x0200 and 0x0300
+const PROGRAM: &str = r#"
+    movimm r0 0x1234
+    movimm r1 0x4142
+    store 0x0200 r0
+    store 0x0300 r1
+    halt
+"#;
+
+fn ran(src: &str) -> (VM, DebugInfo) {
+    let (bytes, debug) = assemble_with_debug(src).expect("assembly failed");
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm.run();
+    (vm, debug)
+}
+
+//
+// ---------- hexdump ----------
+//
+
+#[test]
+fn hexdump_rows() {
+    let data: Vec<u8> = b"Hello, world!\n".to_vec();
+    let dump = memdump::hexdump(&data, 0x0200);
+
+    assert_eq!(
+        dump,
+        "0x0200  48 65 6C 6C 6F 2C 20 77  6F 72 6C 64 21 0A        |Hello, world!.|\n"
+    );
+}
+
+#[test]
+fn hexdump_collapses_repeated_rows() {
+    let mut data = vec![0u8; 64];
+    data[63] = 1;
+    let dump = memdump::hexdump(&data, 0);
+    let lines: Vec<&str> = dump.lines().collect();
+
+    // the two rows repeating the first are one *
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[0],
+        "0x0000  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|"
+    );
+    assert_eq!(lines[1], "*");
+    assert!(lines[2].starts_with("0x0030  00"));
+    assert!(lines[2].ends_with("01  |................|"));
+}
+
+#[test]
+fn region_clips_to_memory() {
+    let memory = Memory::new();
+    assert_eq!(memdump::region(&memory, 0x0FF0..=0xFFFF).unwrap().len(), 16);
+    assert_eq!(memdump::region(&memory, 0..=3).unwrap().len(), 4);
+    assert!(memdump::region(&memory, 0x1000..=0x1001).is_err());
+}
+
+//
+// ---------- diff ----------
+//
+
+#[test]
+fn diff_groups_consecutive_bytes() {
+    let old = Memory::new();
+    let mut new = Memory::new();
+    new.data[0x10] = 1;
+    new.data[0x11] = 2;
+    new.data[0x20] = 3;
+
+    assert_eq!(
+        memdump::diff(&old, &new),
+        vec![
+            Change {
+                start: 0x10,
+                old: vec![0, 0],
+                new: vec![1, 2]
+            },
+            Change {
+                start: 0x20,
+                old: vec![0],
+                new: vec![3]
+            },
+        ]
+    );
+    assert!(memdump::diff(&new, &new.clone()).is_empty());
+}
+
+#[test]
+fn snapshots_diff_with_symbols() {
+    let (bytes, _) = assemble_with_debug(PROGRAM).unwrap();
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    let before = vm.snapshot();
+    vm.run();
+    let after = vm.snapshot();
+
+    let changes = memdump::diff_snapshots(&before, &after).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].start, 0x0200);
+    assert_eq!(changes[1].start, 0x0300);
+
+    // a label at 0x0300 names the second change
+    let mut debug = DebugInfo::default();
+    debug.symbols.insert("buffer".to_string(), 0x0300);
+    debug.symbols.insert("data".to_string(), 0x01F0);
+    let text = memdump::diff_to_text(&changes, &debug);
+    let lines: Vec<&str> = text.lines().collect();
+
+    assert!(lines[0].starts_with("0x0200 data+0x10  2 bytes  00 00 -> "));
+    assert!(lines[1].starts_with("0x0300 buffer  "));
+
+    assert!(memdump::diff_snapshots(b"junk", &after).is_err());
+}
+
+#[test]
+fn symbols_and_offsets() {
+    let (_, debug) = ran("start:\nmovimm r0 1\nend:\nhalt");
+
+    assert_eq!(memdump::symbol_for(&debug, 0).as_deref(), Some("start"));
+    assert_eq!(memdump::symbol_for(&debug, 2).as_deref(), Some("start+0x2"));
+    assert_eq!(memdump::symbol_for(&DebugInfo::default(), 0x0200), None);
+}
@@ -0,0 +1,184 @@
+use risa16::assembler::assemble;
+use risa16::instructions::Instruction;
+use risa16::observer::Observer;
+use risa16::vm::{ExitReason, VM};
+use std::sync::{Arc, Mutex};
+
+//
+// ---------- helpers ----------
+//
+
+// three trips round a loop that stores and loads, then a faulting load
+const PROGRAM: &str = r#"
+    movimm r0 3
+    movimm r1 1
+    movimm r2 0
+loop:
+    store 0x0200 r0
+    load r3 0x0200
+    sub r0 r1
+    cmp r0 r2
+    jmpnz loop
+    load r4 0x0FFF
+"#;
+
+fn load_program(src: &str) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm
+}
+
+// counts every callback and keeps the interesting ones
+#[derive(Default)]
+struct Recorder {
+    fetches: u64,
+    before: u64,
+    after: u64,
+    events: Vec<String>,
+}
+
+impl Observer for Recorder {
+    fn on_fetch(&mut self, vm: &VM, pc: u16) {
+        assert_eq!(vm.cpu.pc, pc);
+        self.fetches += 1;
+    }
+
+    fn on_execute_before(&mut self, vm: &VM, pc: u16, _instr: &Instruction) {
+        assert_eq!(vm.cpu.pc, pc);
+        self.before += 1;
+    }
+
+    fn on_execute_after(&mut self, _vm: &VM, _pc: u16, _instr: &Instruction) {
+        self.after += 1;
+    }
+
+    fn on_mem_read(&mut self, _vm: &VM, addr: u16, value: u16) {
+        self.events.push(format!("read {:#06X} {}", addr, value));
+    }
+
+    fn on_mem_write(&mut self, vm: &VM, addr: u16, old: u16, new: u16) {
+        // the write has already happened
+        assert_eq!(vm.memory.data[addr as usize + 1], new as u8);
+        self.events
+            .push(format!("write {:#06X} {} -> {}", addr, old, new));
+    }
+
+    fn on_branch(&mut self, _vm: &VM, pc: u16, target: u16, taken: bool) {
+        self.events
+            .push(format!("branch {:#06X} -> {:#06X} {}", pc, target, taken));
+    }
+
+    fn on_trap(&mut self, _vm: &VM, pc: u16, message: &str) {
+        self.events.push(format!("trap {:#06X} {}", pc, message));
+    }
+}
+
+//
+// ---------- callbacks ----------
+//
+
+#[test]
+fn observer_sees_every_hook() {
+    let mut vm = load_program(PROGRAM);
+    let recorder = Arc::new(Mutex::new(Recorder::default()));
+    vm.observers.attach(Box::new(recorder.clone()));
+
+    assert!(matches!(vm.run(), ExitReason::Faulted(_)));
+
+    let recorder = recorder.lock().unwrap();
+    assert_eq!(recorder.fetches, 19);
+    assert_eq!(recorder.before, 19);
+    assert_eq!(recorder.after, 18); // the faulting load never retires
+    assert_eq!(
+        recorder.events,
+        vec![
+            "write 0x0200 0 -> 3",
+            "read 0x0200 3",
+            "branch 0x001A -> 0x000C true",
+            "write 0x0200 3 -> 2",
+            "read 0x0200 2",
+            "branch 0x001A -> 0x000C true",
+            "write 0x0200 2 -> 1",
+            "read 0x0200 1",
+            "branch 0x001A -> 0x000C false",
+            "trap 0x001D address out of bounds",
+        ]
+    );
+}
+
+#[test]
+fn decode_errors_are_traps() {
+    let mut vm = VM::new();
+    vm.memory.data[0] = 0xEE;
+    let recorder = Arc::new(Mutex::new(Recorder::default()));
+    vm.observers.attach(Box::new(recorder.clone()));
+
+    vm.run();
+
+    let recorder = recorder.lock().unwrap();
+    assert_eq!((recorder.fetches, recorder.before), (1, 0));
+    assert_eq!(recorder.events.len(), 1);
+    assert!(recorder.events[0].starts_with("trap 0x0000"));
+}
+
+//
+// ---------- attaching ----------
+//
+
+// logs its name on every fetch
+struct Named(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+impl Observer for Named {
+    fn on_fetch(&mut self, _vm: &VM, _pc: u16) {
+        self.1.lock().unwrap().push(self.0);
+    }
+}
+
+#[test]
+fn observers_run_in_attach_order() {
+    let mut vm = load_program("halt");
+    let log = Arc::new(Mutex::new(Vec::new()));
+
+    vm.observers.attach(Box::new(Named("first", log.clone())));
+    let second = vm.observers.attach(Box::new(Named("second", log.clone())));
+    vm.observers.attach(Box::new(Named("third", log.clone())));
+    assert_eq!(vm.observers.len(), 3);
+
+    assert!(vm.observers.detach(second).is_some());
+    assert!(vm.observers.detach(second).is_none());
+
+    vm.run();
+    assert_eq!(*log.lock().unwrap(), vec!["first", "third"]);
+}
+
+#[test]
+fn observers_do_not_change_execution() {
+    let mut plain = load_program(PROGRAM);
+    plain.run();
+
+    let mut observed = load_program(PROGRAM);
+    observed
+        .observers
+        .attach(Box::new(Arc::new(Mutex::new(Recorder::default()))));
+    observed.run();
+
+    assert_eq!(observed.cpu.registers, plain.cpu.registers);
+    assert_eq!(observed.memory.data, plain.memory.data);
+    assert_eq!(
+        (observed.instret, observed.cycles),
+        (plain.instret, plain.cycles)
+    );
+    assert_eq!(observed.fault, plain.fault);
+}
+
+#[test]
+fn clone_starts_without_observers() {
+    let mut vm = load_program(PROGRAM);
+    vm.observers.attach(Box::new(Recorder::default()));
+
+    let copy = vm.clone();
+    assert!(copy.observers.is_empty());
+    assert_eq!(vm.observers.len(), 1);
+}
@@ -0,0 +1,133 @@
+use risa16::assembler::{DebugInfo, assemble_with_debug};
+use risa16::profiler::Profiler;
+use risa16::vm::{ExitReason, VM};
+use std::sync::{Arc, Mutex};
+
+//
+// ---------- helpers ----------
+//
+
+// three trips round the outer loop, four round the inner one each time
+const NESTED: &str = r#"
+    movimm r1 1
+    movimm r2 0
+    movimm r0 3
+outer:
+    movimm r3 4
+inner:
+    sub r3 r1
+    cmp r3 r2
+    jmpnz inner
+    sub r0 r1
+    cmp r0 r2
+    jmpnz outer
+    halt
+"#;
+
+// runs src to the end with a profiler attached
+fn profiled(src: &str) -> (VM, Profiler, DebugInfo) {
+    let (bytes, debug) = assemble_with_debug(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+
+    let profiler = Arc::new(Mutex::new(Profiler::new()));
+    vm.observers.attach(Box::new(profiler.clone()));
+    assert_eq!(vm.run(), ExitReason::Halted);
+
+    let profiler = profiler.lock().unwrap().clone();
+    (vm, profiler, debug)
+}
+
+//
+// ---------- counting ----------
+//
+
+#[test]
+fn counts_match_the_machine() {
+    let (vm, profiler, debug) = profiled(NESTED);
+    let report = profiler.report(&debug);
+
+    assert_eq!(report.total.executions, vm.instret);
+    assert_eq!(report.total.cycles, vm.cycles);
+
+    // sub r3 r1 runs four times for each of the three outer trips
+    let (_, inner) = debug.symbols.iter().find(|(n, _)| *n == "inner").unwrap();
+    assert_eq!(profiler.addresses[inner].executions, 12);
+}
+
+#[test]
+fn labels_enclose_the_code_after_them() {
+    let (_, profiler, debug) = profiled(NESTED);
+    let report = profiler.report(&debug);
+
+    let names: Vec<&str> = report.labels.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["inner", "(start)", "outer"]);
+
+    // inner covers its loop and the outer loop's tail and halt
+    assert_eq!(report.labels[0].counts.executions, 12 * 3 + 3 * 3 + 1);
+    // equal costs stay in name order
+    assert_eq!(report.labels[1].counts, report.labels[2].counts);
+}
+
+#[test]
+fn back_edges_are_loops() {
+    let (_, profiler, debug) = profiled(NESTED);
+    let report = profiler.report(&debug);
+
+    assert_eq!(report.loops.len(), 2);
+
+    // the outer loop contains the inner one, so it costs more
+    let outer = &report.loops[0];
+    assert_eq!(outer.label, "outer");
+    assert_eq!(outer.iterations, 2);
+    assert_eq!(outer.start, debug.symbols["outer"]);
+
+    let inner = &report.loops[1];
+    assert_eq!(inner.label, "inner");
+    assert_eq!(inner.iterations, 9);
+    assert!(inner.cycles < outer.cycles);
+}
+
+//
+// ---------- output ----------
+//
+
+#[test]
+fn text_report() {
+    let (_, profiler, debug) = profiled(NESTED);
+    let text = profiler.report(&debug).to_text(3);
+
+    assert!(text.starts_with("Total: 52 instructions, "));
+    assert!(text.contains("\nBy label:\n"));
+    assert!(text.contains("\nHottest loops:\n"));
+
+    // only the top three addresses are listed
+    let addresses = text.split("Hottest addresses:\n").nth(1).unwrap();
+    assert_eq!(addresses.lines().count(), 4);
+    // the inner loop's jump, with its taken-branch penalty
+    assert!(addresses.lines().nth(1).unwrap().ends_with("line 10"));
+}
+
+#[test]
+fn folded_stacks_add_up() {
+    let (vm, profiler, debug) = profiled(NESTED);
+    let folded = profiler.folded(&debug);
+
+    let total: u64 = folded
+        .lines()
+        .map(|l| l.rsplit_once(' ').unwrap().1.parse::<u64>().unwrap())
+        .sum();
+    assert_eq!(total, vm.cycles);
+    assert!(folded.starts_with("inner "));
+}
+
+#[test]
+fn program_without_labels() {
+    let (_, profiler, debug) = profiled("movimm r0 1\nhalt");
+    let report = profiler.report(&debug);
+
+    assert_eq!(report.labels.len(), 1);
+    assert_eq!(report.labels[0].name, "(start)");
+    assert!(report.loops.is_empty());
+}
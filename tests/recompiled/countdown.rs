@@ -0,0 +1,35 @@
+// Generated by risa16::recompiler. Do not edit.
+#![allow(unused, clippy::all)]
+
+use risa16::cpu::CPU;
+use risa16::memory::Memory;
+
+pub fn run(cpu: &mut CPU, memory: &mut Memory, zero_flag: &mut bool, carry_flag: &mut bool) -> Result<(), String> {
+    loop {
+        match cpu.pc {
+            0x0000 => {
+                cpu.registers[0] = 0x000A;
+                cpu.registers[1] = 0x0001;
+                cpu.registers[2] = 0x0000;
+                cpu.pc = 0x000C;
+            }
+            0x000C => {
+                *carry_flag = cpu.registers[0] < cpu.registers[1];
+                cpu.registers[0] = cpu.registers[0].wrapping_sub(cpu.registers[1]);
+                *zero_flag = cpu.registers[0] == 0;
+                *zero_flag = cpu.registers[0] == cpu.registers[2];
+                *carry_flag = cpu.registers[0] < cpu.registers[2];
+                if !*zero_flag {
+                    cpu.pc = 0x000C;
+                } else {
+                    cpu.pc = 0x0015;
+                }
+            }
+            0x0015 => {
+                cpu.pc = 0x0015;
+                return Ok(());
+            }
+            pc => return Err(format!("no recompiled block at {:#06X}", pc)),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+// Generated by risa16::recompiler. Do not edit.
+#![allow(unused, clippy::all)]
+
+use risa16::cpu::CPU;
+use risa16::memory::Memory;
+
+pub fn run(cpu: &mut CPU, memory: &mut Memory, zero_flag: &mut bool, carry_flag: &mut bool) -> Result<(), String> {
+    loop {
+        match cpu.pc {
+            0x0000 => {
+                cpu.registers[0] = 0x1234;
+                memory.data[0x0100] = (cpu.registers[0] >> 8) as u8;
+                memory.data[0x0101] = cpu.registers[0] as u8;
+                cpu.pc = 0x0008;
+                return Err("address out of bounds".to_string());
+            }
+            pc => return Err(format!("no recompiled block at {:#06X}", pc)),
+        }
+    }
+}
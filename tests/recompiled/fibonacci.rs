@@ -0,0 +1,53 @@
+// Generated by risa16::recompiler. Do not edit.
+#![allow(unused, clippy::all)]
+
+use risa16::cpu::CPU;
+use risa16::memory::Memory;
+
+pub fn run(cpu: &mut CPU, memory: &mut Memory, zero_flag: &mut bool, carry_flag: &mut bool) -> Result<(), String> {
+    loop {
+        match cpu.pc {
+            0x0000 => {
+                cpu.registers[0] = 0x0000;
+                cpu.registers[1] = 0x0001;
+                cpu.registers[2] = 0x000C;
+                cpu.registers[3] = 0x0001;
+                cpu.registers[4] = 0x0000;
+                cpu.pc = 0x0014;
+            }
+            0x0014 => {
+                memory.data[0x0200] = (cpu.registers[0] >> 8) as u8;
+                memory.data[0x0201] = cpu.registers[0] as u8;
+                *zero_flag = cpu.registers[5] == 0;
+                cpu.registers[5] = cpu.registers[0];
+                let sum = cpu.registers[5] as u32 + cpu.registers[1] as u32;
+                *carry_flag = sum >= 0x10000;
+                cpu.registers[5] = sum as u16;
+                *zero_flag = sum as u16 == 0;
+                *zero_flag = cpu.registers[0] == 0;
+                cpu.registers[0] = cpu.registers[1];
+                *zero_flag = cpu.registers[1] == 0;
+                cpu.registers[1] = cpu.registers[5];
+                *carry_flag = cpu.registers[2] < cpu.registers[3];
+                cpu.registers[2] = cpu.registers[2].wrapping_sub(cpu.registers[3]);
+                *zero_flag = cpu.registers[2] == 0;
+                *zero_flag = cpu.registers[2] == cpu.registers[4];
+                *carry_flag = cpu.registers[2] < cpu.registers[4];
+                if *zero_flag {
+                    cpu.pc = 0x0030;
+                } else {
+                    cpu.pc = 0x002D;
+                }
+            }
+            0x002D => {
+                cpu.pc = 0x0014;
+            }
+            0x0030 => {
+                cpu.registers[6] = (memory.data[0x0200] as u16) << 8 | memory.data[0x0201] as u16;
+                cpu.pc = 0x0034;
+                return Ok(());
+            }
+            pc => return Err(format!("no recompiled block at {:#06X}", pc)),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+// Generated by risa16::recompiler. Do not edit.
+#![allow(unused, clippy::all)]
+
+use risa16::cpu::CPU;
+use risa16::memory::Memory;
+
+pub fn run(cpu: &mut CPU, memory: &mut Memory, zero_flag: &mut bool, carry_flag: &mut bool) -> Result<(), String> {
+    loop {
+        match cpu.pc {
+            0x0000 => {
+                cpu.registers[0] = 0xFFFF;
+                cpu.registers[1] = 0x0001;
+                let sum = cpu.registers[0] as u32 + cpu.registers[1] as u32;
+                *carry_flag = sum >= 0x10000;
+                cpu.registers[0] = sum as u16;
+                *zero_flag = sum as u16 == 0;
+                if *zero_flag {
+                    cpu.pc = 0x0013;
+                } else {
+                    cpu.pc = 0x000E;
+                }
+            }
+            0x000E => {
+                cpu.registers[7] = 0xDEAD;
+                cpu.pc = 0x0012;
+                return Ok(());
+            }
+            0x0013 => {
+                cpu.registers[2] = 0x0003;
+                cpu.registers[3] = 0x0005;
+                *carry_flag = cpu.registers[2] < cpu.registers[3];
+                cpu.registers[2] = cpu.registers[2].wrapping_sub(cpu.registers[3]);
+                *zero_flag = cpu.registers[2] == 0;
+                *zero_flag = cpu.registers[2] == cpu.registers[3];
+                *carry_flag = cpu.registers[2] < cpu.registers[3];
+                if !*zero_flag {
+                    cpu.pc = 0x0025;
+                } else {
+                    cpu.pc = 0x0024;
+                }
+            }
+            0x0024 => {
+                cpu.pc = 0x0024;
+                return Ok(());
+            }
+            0x0025 => {
+                *zero_flag = cpu.registers[4] == 0;
+                cpu.registers[4] = cpu.registers[2];
+                *zero_flag = cpu.registers[3] == cpu.registers[2];
+                *carry_flag = cpu.registers[3] < cpu.registers[2];
+                cpu.pc = 0x002B;
+                return Ok(());
+            }
+            pc => return Err(format!("no recompiled block at {:#06X}", pc)),
+        }
+    }
+}
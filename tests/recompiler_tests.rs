@@ -0,0 +1,163 @@
+use risa16::assembler::assemble;
+use risa16::recompiler::recompile;
+use risa16::vm::VM;
+
+// generated code is checked against the recompiler output verbatim
+#[rustfmt::skip]
+#[path = "recompiled/countdown.rs"]
+mod countdown;
+#[rustfmt::skip]
+#[path = "recompiled/fault.rs"]
+mod fault;
+#[rustfmt::skip]
+#[path = "recompiled/fibonacci.rs"]
+mod fibonacci;
+#[rustfmt::skip]
+#[path = "recompiled/flags.rs"]
+mod flags;
+
+type Compiled = fn(
+    &mut risa16::cpu::CPU,
+    &mut risa16::memory::Memory,
+    &mut bool,
+    &mut bool,
+) -> Result<(), String>;
+
+//
+// ---------- helpers ----------
+//
+
+fn load(src: &str) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.memory.data[..bytes.len()].copy_from_slice(&bytes);
+    vm
+}
+
+// runs the program through VM::step and through the recompiled module and
+// checks both end in the same machine state
+fn assert_same_as_vm(src: &str, compiled: Compiled) -> Result<(), String> {
+    let mut interpreted = load(src);
+    interpreted.run();
+
+    let mut native = load(src);
+    let result = compiled(
+        &mut native.cpu,
+        &mut native.memory,
+        &mut native.zero_flag,
+        &mut native.carry_flag,
+    );
+
+    assert_eq!(native.cpu.registers, interpreted.cpu.registers);
+    assert_eq!(native.cpu.pc, interpreted.cpu.pc);
+    assert_eq!(native.zero_flag, interpreted.zero_flag);
+    assert_eq!(native.carry_flag, interpreted.carry_flag);
+    assert_eq!(native.memory.data, interpreted.memory.data);
+
+    result
+}
+
+fn assert_up_to_date(src: &str, generated: &str) {
+    let bytes = assemble(src).expect("assembly failed");
+
+    // regenerate with: cargo run -- recompile tests/corpus/<name>.asm tests/recompiled/<name>.rs
+    assert_eq!(recompile(&bytes).unwrap(), generated);
+}
+
+//
+// ---------- generated sources ----------
+//
+
+#[test]
+fn generated_sources_are_up_to_date() {
+    assert_up_to_date(
+        include_str!("corpus/countdown.asm"),
+        include_str!("recompiled/countdown.rs"),
+    );
+    assert_up_to_date(
+        include_str!("corpus/fibonacci.asm"),
+        include_str!("recompiled/fibonacci.rs"),
+    );
+    assert_up_to_date(
+        include_str!("corpus/flags.asm"),
+        include_str!("recompiled/flags.rs"),
+    );
+    assert_up_to_date(
+        include_str!("corpus/fault.asm"),
+        include_str!("recompiled/fault.rs"),
+    );
+}
+
+//
+// ---------- recompiled → vm equivalence ----------
+//
+
+#[test]
+fn countdown_matches_vm() {
+    let result = assert_same_as_vm(include_str!("corpus/countdown.asm"), countdown::run);
+    assert!(result.is_ok());
+}
+
+#[test]
+fn fibonacci_matches_vm() {
+    let result = assert_same_as_vm(include_str!("corpus/fibonacci.asm"), fibonacci::run);
+    assert!(result.is_ok());
+}
+
+#[test]
+fn flags_match_vm() {
+    let result = assert_same_as_vm(include_str!("corpus/flags.asm"), flags::run);
+    assert!(result.is_ok());
+}
+
+#[test]
+fn fault_matches_vm() {
+    let result = assert_same_as_vm(include_str!("corpus/fault.asm"), fault::run);
+    assert_eq!(result, Err("address out of bounds".to_string()));
+}
+
+//
+// ---------- control flow recovery ----------
+//
+
+#[test]
+fn unreachable_code_is_not_translated() {
+    let bytes = assemble(
+        r#"
+            jmp end
+            movimm r0 1
+        end:
+            halt
+        "#,
+    )
+    .unwrap();
+
+    let rust = recompile(&bytes).unwrap();
+
+    assert!(rust.contains("0x0007 => {"));
+    assert!(!rust.contains("0x0003 => {"));
+    assert!(!rust.contains("cpu.registers[0] = 0x0001;"));
+}
+
+#[test]
+fn jump_to_itself_falls_through() {
+    // execute leaves the PC untouched so step advances past the jump
+    let bytes = assemble("spin: jmp spin\nhalt").unwrap();
+
+    let rust = recompile(&bytes).unwrap();
+
+    assert!(rust.contains("cpu.pc = 0x0003;\n                return Ok(());"));
+}
+
+#[test]
+fn oversized_image_is_rejected() {
+    assert!(recompile(&vec![0xFF; 0x1001]).is_err());
+}
+
+#[test]
+fn counter_reads_are_rejected() {
+    let bytes = assemble("rdcycle r0\nhalt").unwrap();
+
+    assert!(recompile(&bytes).is_err());
+}
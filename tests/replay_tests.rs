@@ -0,0 +1,169 @@
+use risa16::assembler::assemble;
+use risa16::devices::Devices;
+use risa16::replay::{self, Event, Mode};
+use risa16::vm::{ExitReason, VM};
+
+//
+// ---------- helpers ----------
+//
+
+// echoes console input with random noise added, then reads the timer
+const NOISY_ECHO: &str = r#"
+    movimm r1 0xFFFF
+loop:
+    in r0 0
+    cmp r0 r1
+    jmpz done
+    in r2 3
+    add r0 r2
+    out 0 r0
+    jmp loop
+done:
+    in r3 2
+    halt
+"#;
+
+fn load_program(src: &str, seed: u64, input: &[u8]) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm.devices = Devices::with_seed(seed);
+    vm.devices.console.input.extend(input);
+    vm
+}
+
+fn record(src: &str, seed: u64, input: &[u8]) -> (VM, Vec<Event>) {
+    let mut vm = load_program(src, seed, input);
+    vm.devices.replay = Mode::Recording(Vec::new());
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+
+    let events = match &vm.devices.replay {
+        Mode::Recording(events) => events.clone(),
+        _ => unreachable!(),
+    };
+    (vm, events)
+}
+
+//
+// ---------- devices ----------
+//
+
+#[test]
+fn random_port_depends_on_seed() {
+    let mut a = Devices::with_seed(1);
+    let mut b = Devices::with_seed(1);
+    let mut c = Devices::with_seed(2);
+
+    let a: Vec<u16> = (0..8).map(|i| a.read(0x03, i).unwrap()).collect();
+    let b: Vec<u16> = (0..8).map(|i| b.read(0x03, i).unwrap()).collect();
+    let c: Vec<u16> = (0..8).map(|i| c.read(0x03, i).unwrap()).collect();
+
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+//
+// ---------- record / replay ----------
+//
+
+#[test]
+fn recording_logs_every_read() {
+    let (_, events) = record(NOISY_ECHO, 7, b"ab");
+
+    let ports: Vec<u8> = events.iter().map(|e| e.port).collect();
+    assert_eq!(ports, vec![0x00, 0x03, 0x00, 0x03, 0x00, 0x02]);
+
+    assert_eq!(events[0].instret, 1);
+    assert_eq!(events[0].value, b'a' as u16);
+    assert_eq!(events[4].value, 0xFFFF);
+}
+
+#[test]
+fn replay_reproduces_run_without_input_or_seed() {
+    let (recorded, events) = record(NOISY_ECHO, 7, b"hello");
+
+    // different seed and no console input, everything comes from the log
+    let mut vm = load_program(NOISY_ECHO, 99, b"");
+    vm.devices.replay = Mode::Replaying { events, next: 0 };
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+    assert_eq!(vm.devices.replay.remaining(), 0);
+    assert_eq!(vm.cpu.registers, recorded.cpu.registers);
+    assert_eq!(vm.devices.console.output, recorded.devices.console.output);
+    assert_eq!(vm.instret, recorded.instret);
+}
+
+#[test]
+fn replay_detects_divergence() {
+    let (_, events) = record(NOISY_ECHO, 7, b"hi");
+
+    // same reads, but one instruction later
+    let shifted = format!("    movimm r9 0\n{}", NOISY_ECHO);
+    let mut vm = load_program(&shifted, 7, b"hi");
+    vm.devices.replay = Mode::Replaying { events, next: 0 };
+
+    match vm.run() {
+        ExitReason::Faulted(e) => assert!(e.starts_with("replay diverged")),
+        other => panic!("expected divergence, got {:?}", other),
+    }
+}
+
+#[test]
+fn replay_past_end_of_log_diverges() {
+    let (_, mut events) = record(NOISY_ECHO, 7, b"hi");
+    events.truncate(2);
+
+    let mut vm = load_program(NOISY_ECHO, 7, b"hi");
+    vm.devices.replay = Mode::Replaying { events, next: 0 };
+
+    assert!(matches!(vm.run(), ExitReason::Faulted(_)));
+    assert_eq!(vm.devices.replay.remaining(), 0);
+}
+
+#[test]
+fn step_back_rewinds_replay_and_rng() {
+    let mut vm = load_program(NOISY_ECHO, 7, b"xyz");
+    vm.devices.replay = Mode::Recording(Vec::new());
+    vm.enable_journal(1 << 20);
+
+    vm.run();
+    let output = vm.devices.console.output.clone();
+    let reads = vm.devices.replay.position();
+    let rng_state = vm.devices.rng_state;
+
+    for _ in 0..12 {
+        vm.step_back();
+    }
+    assert!(vm.devices.replay.position() < reads);
+    assert_ne!(vm.devices.rng_state, rng_state);
+
+    // the timer read is live again, so only the count is compared for it
+    vm.run();
+    assert_eq!(vm.devices.console.output, output);
+    assert_eq!(vm.devices.rng_state, rng_state);
+    assert_eq!(vm.devices.replay.position(), reads);
+}
+
+//
+// ---------- replay files ----------
+//
+
+#[test]
+fn replay_file_roundtrip() {
+    let (_, events) = record(NOISY_ECHO, 7, b"ok");
+
+    let text = replay::to_text(&events);
+
+    assert!(text.starts_with(replay::HEADER));
+    assert_eq!(replay::parse(&text).unwrap(), events);
+}
+
+#[test]
+fn replay_file_errors() {
+    assert!(replay::parse("1 0x00 0x0041").is_err());
+    assert!(replay::parse("# risa16 replay v1\n1 0x00").is_err());
+    assert!(replay::parse("# risa16 replay v1\nx 0x00 0x0041").is_err());
+    assert!(replay::parse("# risa16 replay v1\n1 0x100 0x0041").is_err());
+}
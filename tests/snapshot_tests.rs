@@ -0,0 +1,151 @@
+use risa16::assembler::assemble;
+use risa16::coverage::Coverage;
+use risa16::snapshot::VERSION;
+use risa16::vm::{ExitReason, State, VM};
+use std::sync::{Arc, Mutex};
+
+//
+// ---------- helpers ----------
+//
+
+const ECHO_COUNT: &str = r#"
+    movimm r1 0xFFFF
+    movimm r2 1
+    movimm r3 0
+loop:
+    in r0 0
+    cmp r0 r1
+    jmpz done
+    out 0 r0
+    add r3 r2
+    store 0x0200 r3
+    jmp loop
+done:
+    halt
+"#;
+
+fn load_program(src: &str, input: &[u8]) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm.devices.console.input.extend(input);
+    vm
+}
+
+fn assert_same_machine(a: &VM, b: &VM) {
+    assert_eq!(a.cpu.registers, b.cpu.registers);
+    assert_eq!(a.cpu.pc, b.cpu.pc);
+    assert_eq!(a.zero_flag, b.zero_flag);
+    assert_eq!(a.carry_flag, b.carry_flag);
+    assert_eq!(a.state, b.state);
+    assert_eq!(a.fault, b.fault);
+    assert_eq!(a.instret, b.instret);
+    assert_eq!(a.cycles, b.cycles);
+    assert_eq!(a.memory.data, b.memory.data);
+    assert_eq!(a.devices.console.input, b.devices.console.input);
+    assert_eq!(a.devices.console.output, b.devices.console.output);
+}
+
+//
+// ---------- save / restore ----------
+//
+
+#[test]
+fn resume_matches_uninterrupted_run() {
+    let mut straight = load_program(ECHO_COUNT, b"abcdef");
+    straight.run();
+
+    let mut first = load_program(ECHO_COUNT, b"abcdef");
+    assert_eq!(first.run_for(20), ExitReason::BudgetExhausted);
+    let snap = first.snapshot();
+
+    let mut resumed = VM::new();
+    resumed.restore(&snap).unwrap();
+    assert_same_machine(&resumed, &first);
+
+    assert_eq!(resumed.run(), ExitReason::Halted);
+    assert_same_machine(&resumed, &straight);
+    assert_eq!(resumed.devices.console.output, b"abcdef");
+}
+
+#[test]
+fn snapshot_keeps_fault_and_fuel() {
+    let mut vm = load_program("movimm r0 1\nload r0 0x0FFF", b"");
+    vm.fuel = Some(100);
+    vm.run();
+
+    let mut restored = VM::new();
+    restored.restore(&vm.snapshot()).unwrap();
+
+    assert_eq!(restored.state, State::HALTED);
+    assert_eq!(restored.fault, Some("address out of bounds".to_string()));
+    assert_eq!(restored.fuel, Some(98));
+    assert_eq!(
+        restored.run(),
+        ExitReason::Faulted("address out of bounds".to_string())
+    );
+}
+
+#[test]
+fn restore_keeps_host_breakpoints() {
+    let vm = load_program(ECHO_COUNT, b"xy");
+
+    let mut restored = VM::new();
+    restored.breakpoints.insert(0x000C);
+    restored.restore(&vm.snapshot()).unwrap();
+
+    assert_eq!(restored.run(), ExitReason::BreakpointHit(0x000C));
+}
+
+#[test]
+fn restore_keeps_observers() {
+    let vm = load_program(ECHO_COUNT, b"xy");
+
+    let coverage = Arc::new(Mutex::new(Coverage::new()));
+    let mut restored = VM::new();
+    restored.observers.attach(Box::new(coverage.clone()));
+    restored.restore(&vm.snapshot()).unwrap();
+
+    assert_eq!(restored.observers.len(), 1);
+    restored.run();
+    assert!(!coverage.lock().unwrap().executed.is_empty());
+}
+
+//
+// ---------- rejected snapshots ----------
+//
+
+#[test]
+fn incompatible_version_is_rejected() {
+    let mut snap = VM::new().snapshot();
+    let bumped = VERSION + 1;
+    snap[8..10].copy_from_slice(&bumped.to_be_bytes());
+
+    let err = VM::new().restore(&snap).unwrap_err();
+
+    assert!(err.contains("version"));
+    assert!(err.contains(&bumped.to_string()));
+}
+
+#[test]
+fn garbage_is_rejected() {
+    assert_eq!(
+        VM::new().restore(b"hello"),
+        Err("not a RISA16 snapshot".to_string())
+    );
+}
+
+#[test]
+fn truncated_snapshot_leaves_vm_untouched() {
+    let mut source = load_program("movimm r5 9\nhalt", b"");
+    source.run();
+    let snap = source.snapshot();
+
+    let mut vm = VM::new();
+    vm.cpu.registers[5] = 1;
+
+    assert!(vm.restore(&snap[..snap.len() - 3]).is_err());
+    assert_eq!(vm.cpu.registers[5], 1);
+    assert_eq!(vm.state, State::RUNNING);
+}
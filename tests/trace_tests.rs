@@ -0,0 +1,196 @@
+use risa16::assembler::{assemble, assemble_with_debug};
+use risa16::json::Json;
+use risa16::trace::{self, Format, Tracer};
+use risa16::vm::{ExitReason, VM};
+
+//
+// ---------- helpers ----------
+//
+
+const COUNTDOWN: &str = include_str!("corpus/countdown.asm");
+const FAULT: &str = include_str!("corpus/fault.asm");
+
+fn load_program(src: &str) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm
+}
+
+// runs src to the end and returns the trace it wrote
+fn traced(src: &str, format: Format) -> (ExitReason, String) {
+    let mut vm = load_program(src);
+    let mut tracer = Tracer::new(Vec::new(), format);
+
+    let exit = tracer.run(&mut vm, None).unwrap();
+    (exit, String::from_utf8(tracer.into_inner()).unwrap())
+}
+
+//
+// ---------- records ----------
+//
+
+#[test]
+fn record_shows_what_the_step_changed() {
+    let mut vm = load_program(FAULT);
+    let mut tracer = Tracer::new(Vec::new(), Format::Text);
+
+    let first = tracer.capture(&mut vm).unwrap();
+    assert_eq!(first.n, 0);
+    assert_eq!(first.pc, 0x0000);
+    assert_eq!(first.bytes, vec![0x01, 0x00, 0x12, 0x34]);
+    assert_eq!(first.asm, "movimm r0 0x1234");
+    assert_eq!(first.registers, vec![(0, 0x1234)]);
+
+    let store = tracer.capture(&mut vm).unwrap();
+    assert_eq!(store.registers, vec![]);
+    assert_eq!(
+        store.writes,
+        vec![(0x0100, 0x00, 0x12), (0x0101, 0x00, 0x34)]
+    );
+
+    let load = tracer.capture(&mut vm).unwrap();
+    assert_eq!(load.fault, Some("address out of bounds".to_string()));
+
+    // nothing runs once the machine has stopped
+    assert_eq!(tracer.capture(&mut vm), None);
+}
+
+#[test]
+fn only_changed_bytes_are_writes() {
+    let mut vm =
+        load_program("    movimm r0 0x0034\n    store 0x0100 r0\n    store 0x0100 r0\n    halt");
+    let mut tracer = Tracer::new(Vec::new(), Format::Text);
+
+    tracer.capture(&mut vm).unwrap();
+    assert_eq!(
+        tracer.capture(&mut vm).unwrap().writes,
+        vec![(0x0101, 0x00, 0x34)]
+    );
+    assert_eq!(tracer.capture(&mut vm).unwrap().writes, vec![]);
+
+    // the tracer only listens while it steps
+    assert!(vm.observers.is_empty());
+}
+
+#[test]
+fn flag_changes_are_recorded() {
+    let mut vm = load_program("movimm r0 1\ncmp r0 r0\nhalt");
+    let mut tracer = Tracer::new(Vec::new(), Format::Text);
+
+    tracer.capture(&mut vm);
+    let cmp = tracer.capture(&mut vm).unwrap();
+    assert_eq!(cmp.flags, vec![('Z', true)]);
+}
+
+//
+// ---------- formats ----------
+//
+
+#[test]
+fn text_trace() {
+    let (exit, text) = traced(COUNTDOWN, Format::Text);
+    assert_eq!(exit, ExitReason::Halted);
+
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 34);
+    assert_eq!(
+        lines[3],
+        "     3 0x000C  060001      sub r0 r1             r0=0x0009"
+    );
+    assert_eq!(lines[33], "    33 0x0015  FF          halt");
+}
+
+#[test]
+fn json_lines_trace() {
+    let (exit, text) = traced(FAULT, Format::JsonLines);
+    assert!(matches!(exit, ExitReason::Faulted(_)));
+
+    let records: Vec<Json> = text.lines().map(|l| Json::parse(l).unwrap()).collect();
+    assert_eq!(records.len(), 3);
+
+    let store = &records[1];
+    assert_eq!(store.get("pc").and_then(Json::as_u64), Some(4));
+    assert_eq!(
+        store.get("asm").and_then(Json::as_str),
+        Some("store 0x0100 r0")
+    );
+    let writes = store.get("writes").and_then(Json::as_array).unwrap();
+    assert_eq!(writes[1].get("addr").and_then(Json::as_u64), Some(0x0101));
+    assert_eq!(writes[1].get("new").and_then(Json::as_u64), Some(0x34));
+
+    let load = &records[2];
+    assert_eq!(
+        load.get("fault").and_then(Json::as_str),
+        Some("address out of bounds")
+    );
+}
+
+#[test]
+fn traced_run_matches_plain_run() {
+    let mut plain = load_program(COUNTDOWN);
+    plain.run();
+
+    let mut vm = load_program(COUNTDOWN);
+    Tracer::new(std::io::sink(), Format::Text)
+        .run(&mut vm, None)
+        .unwrap();
+
+    assert_eq!(vm.cpu.registers, plain.cpu.registers);
+    assert_eq!(vm.instret, plain.instret);
+    assert_eq!(vm.cycles, plain.cycles);
+}
+
+#[test]
+fn step_budget() {
+    let mut vm = load_program(COUNTDOWN);
+    let mut tracer = Tracer::new(Vec::new(), Format::Text);
+
+    assert_eq!(
+        tracer.run(&mut vm, Some(5)).unwrap(),
+        ExitReason::BudgetExhausted
+    );
+    assert_eq!(tracer.records, 5);
+    assert_eq!(vm.instret, 5);
+}
+
+//
+// ---------- filters ----------
+//
+
+#[test]
+fn filter_by_label_covers_up_to_the_next_label() {
+    let (_, debug) = assemble_with_debug(COUNTDOWN).unwrap();
+    assert_eq!(trace::parse_range("loop", &debug), Ok(0x000C..=0xFFFF));
+
+    let mut vm = load_program(COUNTDOWN);
+    let mut tracer = Tracer::new(Vec::new(), Format::Text);
+    tracer.only(trace::parse_range("loop", &debug).unwrap());
+    tracer.run(&mut vm, None).unwrap();
+
+    // all but the three movimms before the loop
+    assert_eq!(tracer.records, 31);
+    assert_eq!(vm.instret, 34);
+}
+
+#[test]
+fn filter_by_address_range() {
+    let (_, debug) = assemble_with_debug(COUNTDOWN).unwrap();
+
+    let range = trace::parse_range("0x000C..0x0012", &debug).unwrap();
+    assert_eq!(range, 0x000C..=0x0011);
+    assert_eq!(trace::parse_range("0x0012", &debug), Ok(0x0012..=0x0012));
+    assert!(trace::parse_range("0x0012..0x000C", &debug).is_err());
+    assert!(trace::parse_range("nowhere", &debug).is_err());
+
+    let mut vm = load_program(COUNTDOWN);
+    let mut tracer = Tracer::new(Vec::new(), Format::Text);
+    tracer.only(range);
+    tracer.run(&mut vm, None).unwrap();
+
+    // sub and cmp, ten times round the loop
+    let text = String::from_utf8(tracer.into_inner()).unwrap();
+    assert_eq!(text.lines().count(), 20);
+    assert!(text.lines().all(|l| !l.contains("jmpnz")));
+}
@@ -1,5 +1,8 @@
+use risa16::assembler::assemble;
+use risa16::cost::CostModel;
+use risa16::expr::Condition;
 use risa16::instructions::Instruction;
-use risa16::vm::{State, VM};
+use risa16::vm::{ExitReason, State, VM};
 
 //
 // -------- helpers --------
@@ -9,6 +12,22 @@ fn fresh_vm() -> VM {
     VM::new()
 }
 
+fn load_program(src: &str) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.memory.data[..bytes.len()].copy_from_slice(&bytes);
+    vm
+}
+
+const INFINITE_LOOP: &str = r#"
+    movimm r0 0
+    movimm r1 1
+loop:
+    add r0 r1
+    jmp loop
+"#;
+
 //
 // -------- initialization --------
 //
@@ -132,6 +151,23 @@ fn sub_sets_borrow_and_zero() {
     assert!(vm.zero_flag);
 }
 
+#[test]
+fn sub_wraps_on_borrow() {
+    let mut vm = fresh_vm();
+
+    vm.cpu.registers[0] = 3;
+    vm.cpu.registers[1] = 5;
+
+    vm.execute(Instruction::Sub {
+        dest_reg: 0,
+        src_reg: 1,
+    });
+
+    assert_eq!(vm.cpu.registers[0], 0xFFFE);
+    assert!(vm.carry_flag);
+    assert!(!vm.zero_flag);
+}
+
 //
 // -------- CMP --------
 //
@@ -264,3 +300,292 @@ fn invalid_memory_address_halts_vm() {
     });
     assert_eq!(vm.state, State::HALTED);
 }
+
+#[test]
+fn fault_is_recorded() {
+    let mut vm = fresh_vm();
+
+    vm.execute(Instruction::Jump { addr: 0x2000 });
+    assert_eq!(vm.fault, Some("address out of bounds".to_string()));
+}
+
+//
+// -------- RUN --------
+//
+
+#[test]
+fn run_until_halt() {
+    let mut vm = load_program("movimm r0 7\nhalt");
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+    assert_eq!(vm.cpu.registers[0], 7);
+    assert_eq!(vm.cpu.pc, 4);
+}
+
+#[test]
+fn run_reports_fault() {
+    let mut vm = load_program("load r0 0x0FFF");
+
+    assert_eq!(
+        vm.run(),
+        ExitReason::Faulted("address out of bounds".to_string())
+    );
+    assert_eq!(vm.state, State::HALTED);
+}
+
+#[test]
+fn run_for_stops_infinite_loop() {
+    let mut vm = load_program(INFINITE_LOOP);
+
+    assert_eq!(vm.run_for(10), ExitReason::BudgetExhausted);
+    assert_eq!(vm.state, State::RUNNING);
+
+    // 2 setup instructions then add, jmp pairs
+    assert_eq!(vm.cpu.registers[0], 4);
+}
+
+#[test]
+fn fuel_limits_every_run() {
+    let mut vm = load_program(INFINITE_LOOP);
+    vm.fuel = Some(6);
+
+    assert_eq!(vm.run(), ExitReason::BudgetExhausted);
+    assert_eq!(vm.fuel, Some(0));
+    assert_eq!(vm.cpu.registers[0], 2);
+
+    // step does nothing once fuel runs out
+    vm.step();
+    assert_eq!(vm.cpu.registers[0], 2);
+
+    vm.fuel = Some(2);
+    assert_eq!(vm.run(), ExitReason::BudgetExhausted);
+    assert_eq!(vm.cpu.registers[0], 3);
+}
+
+#[test]
+fn run_stops_at_breakpoint_and_resumes() {
+    let mut vm = load_program(INFINITE_LOOP);
+    vm.breakpoints.insert(8);
+
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    assert_eq!(vm.cpu.registers[0], 0);
+
+    // resuming steps off the breakpoint and comes back around the loop
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    assert_eq!(vm.cpu.registers[0], 1);
+}
+
+#[test]
+fn breakpoint_on_entry_is_reported() {
+    let mut vm = load_program(INFINITE_LOOP);
+    vm.breakpoints.insert(0);
+    vm.breakpoints.insert(8);
+
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(0));
+    assert_eq!(vm.instret, 0);
+
+    // resuming from it goes on to the next one
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+}
+
+#[test]
+fn slices_do_not_count_a_breakpoint_twice() {
+    let mut vm = load_program(INFINITE_LOOP);
+    vm.breakpoints
+        .set(8, Some(Condition::parse("hits == 2").unwrap()));
+
+    // the first slice ends on the breakpoint, which counts that arrival
+    assert_eq!(vm.run_for(2), ExitReason::BudgetExhausted);
+    assert_eq!(vm.cpu.pc, 8);
+
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    assert_eq!(vm.cpu.registers[0], 1);
+}
+
+#[test]
+fn step_for_executes_the_breakpoint_it_is_on() {
+    let mut vm = load_program(INFINITE_LOOP);
+    vm.breakpoints.insert(0);
+    vm.breakpoints.insert(8);
+
+    // reported or not, the breakpoint under the PC does not hold a step back
+    assert_eq!(vm.step_for(1), ExitReason::BudgetExhausted);
+    assert_eq!(vm.cpu.pc, 4);
+
+    // but the steps after it stop at one
+    assert_eq!(vm.step_for(5), ExitReason::BreakpointHit(8));
+    assert_eq!(vm.step_for(1), ExitReason::BudgetExhausted);
+    assert_eq!(vm.cpu.registers[0], 1);
+}
+
+#[test]
+fn only_a_reported_breakpoint_is_stepped_over() {
+    let mut vm = load_program(INFINITE_LOOP);
+    vm.breakpoints.insert(8);
+    vm.enable_journal(4096);
+
+    // stopping on it for another reason does not report it
+    assert_eq!(vm.run_until(|vm| vm.cpu.pc == 8), ExitReason::UserStop);
+    let snapshot = vm.snapshot();
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    assert_eq!(vm.cpu.registers[0], 0);
+
+    // going back to it, by undo or restore, reports it again
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    assert!(vm.step_back() && vm.step_back());
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    assert_eq!(vm.cpu.registers[0], 0);
+
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    vm.restore(&snapshot).unwrap();
+    assert_eq!(vm.run(), ExitReason::BreakpointHit(8));
+    assert_eq!(vm.cpu.registers[0], 0);
+}
+
+#[test]
+fn run_until_predicate() {
+    let mut vm = load_program(INFINITE_LOOP);
+
+    let exit = vm.run_until(|vm| vm.cpu.registers[0] == 5);
+
+    assert_eq!(exit, ExitReason::UserStop);
+    assert_eq!(vm.cpu.registers[0], 5);
+}
+
+//
+// -------- CYCLES --------
+//
+
+#[test]
+fn counters_track_default_costs() {
+    let mut vm = load_program(
+        r#"
+        movimm r0 1        // 1
+        store 0x0100 r0    // 1 + 2 memory
+        load r1 0x0100     // 1 + 2 memory
+        jmp end            // 1 + 1 taken
+    end:
+        halt               // 1
+        "#,
+    );
+
+    vm.run();
+
+    assert_eq!(vm.instret, 5);
+    assert_eq!(vm.cycles, 10);
+}
+
+#[test]
+fn untaken_branch_has_no_penalty() {
+    let mut vm = load_program("jmpz 0x0004\nhalt\nhalt");
+
+    vm.run();
+
+    assert_eq!(vm.instret, 2);
+    assert_eq!(vm.cycles, 2);
+}
+
+#[test]
+fn faulting_instruction_does_not_retire() {
+    let mut vm = load_program("movimm r0 1\nload r0 0x0FFF");
+
+    vm.run();
+
+    assert_eq!(vm.instret, 1);
+    assert_eq!(vm.cycles, 1);
+}
+
+#[test]
+fn guest_reads_counters() {
+    let mut vm = load_program(
+        r#"
+        movimm r0 0
+        load r1 0x0100
+        rdcycle r2
+        rdinstret r3
+        halt
+        "#,
+    );
+
+    vm.run();
+
+    assert_eq!(vm.cpu.registers[2], 4);
+    assert_eq!(vm.cpu.registers[3], 3);
+}
+
+#[test]
+fn custom_cost_model() {
+    let mut vm = load_program("load r0 0x0100\njmp 0x0007\nhalt");
+    vm.cost_model = CostModel::parse("load 5\nmemory 10\nbranch 3 // taken jumps").unwrap();
+
+    vm.run();
+
+    assert_eq!(vm.cycles, 15 + 4 + 1);
+}
+
+#[test]
+fn huge_costs_saturate() {
+    let mut vm = load_program("load r0 0x0100\njmp 0x0007\nhalt");
+    vm.cost_model = CostModel::parse("memory 18446744073709551615\nbranch 5").unwrap();
+
+    vm.run();
+
+    assert_eq!(vm.cycles, u64::MAX);
+}
+
+#[test]
+fn cost_model_rejects_unknown_instruction() {
+    assert!(CostModel::parse("frobnicate 3").is_err());
+    assert!(CostModel::parse("load").is_err());
+}
+
+//
+// -------- IN / OUT --------
+//
+
+#[test]
+fn in_reads_console_input() {
+    let mut vm = fresh_vm();
+    vm.devices.console.input.extend(b"A");
+
+    vm.execute(Instruction::In { reg: 2, port: 1 });
+    assert_eq!(vm.cpu.registers[2], 1);
+
+    vm.execute(Instruction::In { reg: 2, port: 0 });
+    assert_eq!(vm.cpu.registers[2], 0x41);
+
+    // end of input
+    vm.execute(Instruction::In { reg: 2, port: 0 });
+    assert_eq!(vm.cpu.registers[2], 0xFFFF);
+}
+
+#[test]
+fn out_writes_console_output() {
+    let mut vm = fresh_vm();
+
+    vm.cpu.registers[4] = 0x1234;
+    vm.execute(Instruction::Out { port: 0, reg: 4 });
+
+    assert_eq!(vm.devices.console.output, vec![0x34]);
+}
+
+#[test]
+fn unknown_port_halts_vm() {
+    let mut vm = fresh_vm();
+
+    vm.execute(Instruction::Out { port: 0x42, reg: 0 });
+    assert_eq!(vm.state, State::HALTED);
+    assert!(vm.fault.is_some());
+}
+
+#[test]
+fn clone_is_independent() {
+    let mut vm = load_program("movimm r0 1\nhalt");
+    let snapshot = vm.clone();
+
+    vm.run();
+
+    assert_eq!(vm.cpu.registers[0], 1);
+    assert_eq!(snapshot.cpu.registers[0], 0);
+    assert_eq!(snapshot.state, State::RUNNING);
+}
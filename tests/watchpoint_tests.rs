@@ -0,0 +1,256 @@
+use risa16::assembler::assemble;
+use risa16::debugger::Debugger;
+use risa16::vm::{ExitReason, VM};
+use risa16::watchpoints::{Access, Action, Event, LOG_LIMIT, WatchHit};
+
+//
+// ---------- helpers ----------
+//
+
+// two writers share 0x0200, the second one clobbers it
+const PROGRAM: &str = r#"
+    movimm r0 0x1111
+    movimm r1 0x2222
+    store 0x0200 r0
+    load r2 0x0200
+    store 0x01FF r1
+    load r3 0x0202
+    halt
+"#;
+
+fn load_program(src: &str) -> VM {
+    let bytes = assemble(src).expect("assembly failed");
+
+    let mut vm = VM::new();
+    vm.load(&bytes).unwrap();
+    vm
+}
+
+//
+// ---------- memory ----------
+//
+
+#[test]
+fn write_watchpoint_finds_the_writer() {
+    let mut vm = load_program(PROGRAM);
+    let id = vm
+        .watchpoints
+        .watch_memory(0x0200, 2, Access::Write, Action::Stop)
+        .unwrap();
+
+    let exit = vm.run();
+    assert_eq!(
+        exit,
+        ExitReason::WatchpointHit(WatchHit {
+            id,
+            pc: 0x0008,
+            event: Event::Write { addr: 0x0200 },
+            old: 0x0000,
+            new: 0x1111,
+        })
+    );
+
+    // stops after the store, before the next instruction
+    assert_eq!(vm.cpu.pc, 0x000C);
+
+    // the misaligned store overlaps the high byte
+    match vm.run() {
+        ExitReason::WatchpointHit(hit) => {
+            assert_eq!(hit.pc, 0x0010);
+            assert_eq!(hit.event, Event::Write { addr: 0x01FF });
+            assert_eq!(hit.old, 0x0011);
+            assert_eq!(hit.new, 0x2222);
+        }
+        other => panic!("expected a watchpoint, got {:?}", other),
+    }
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+}
+
+#[test]
+fn read_watchpoint_ignores_writes() {
+    let mut vm = load_program(PROGRAM);
+    vm.watchpoints
+        .watch_memory(0x0200, 1, Access::Read, Action::Stop)
+        .unwrap();
+
+    match vm.run() {
+        ExitReason::WatchpointHit(hit) => {
+            assert_eq!(hit.pc, 0x000C);
+            assert_eq!(hit.event, Event::Read { addr: 0x0200 });
+            assert_eq!(hit.old, 0x1111);
+            assert_eq!(hit.new, 0x1111);
+        }
+        other => panic!("expected a watchpoint, got {:?}", other),
+    }
+
+    // the load at 0x0202 is outside the one watched byte
+    assert_eq!(vm.run(), ExitReason::Halted);
+}
+
+#[test]
+fn access_watchpoint_sees_both() {
+    let mut vm = load_program(PROGRAM);
+    vm.watchpoints
+        .watch_memory(0x0201, 2, Access::Any, Action::Log)
+        .unwrap();
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+
+    let pcs: Vec<u16> = vm.watchpoints.log.iter().map(|h| h.pc).collect();
+    assert_eq!(pcs, vec![0x0008, 0x000C, 0x0014]);
+}
+
+#[test]
+fn log_only_watchpoint_does_not_stop() {
+    let mut vm = load_program(PROGRAM);
+    vm.watchpoints
+        .watch_memory(0x0200, 2, Access::Write, Action::Log)
+        .unwrap();
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+    assert_eq!(vm.watchpoints.log.len(), 2);
+    assert_eq!(
+        vm.watchpoints.log[1].to_string(),
+        "watchpoint 1: write 0x01FF at PC 0x0010, 0x0011 -> 0x2222"
+    );
+}
+
+#[test]
+fn log_keeps_only_the_latest_hits() {
+    let mut vm = load_program(
+        "    movimm r0 2000\n    movimm r1 1\n    movimm r2 0\nloop:\n    store 0x0200 r0\n    sub r0 r1\n    cmp r0 r2\n    jmpnz loop\n    halt",
+    );
+    vm.watchpoints
+        .watch_memory(0x0200, 2, Access::Write, Action::Log)
+        .unwrap();
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+    assert_eq!(vm.watchpoints.log.len(), LOG_LIMIT);
+    assert_eq!(vm.watchpoints.log.back().unwrap().new, 1);
+
+    let hits = vm.watchpoints.take_log();
+    assert_eq!(hits[0].new, LOG_LIMIT as u16);
+    assert!(vm.watchpoints.log.is_empty());
+}
+
+#[test]
+fn empty_range_is_rejected() {
+    let mut vm = VM::new();
+
+    assert!(
+        vm.watchpoints
+            .watch_memory(0x0200, 0, Access::Write, Action::Stop)
+            .is_err()
+    );
+    assert!(vm.watchpoints.is_empty());
+}
+
+#[test]
+fn faulting_access_is_not_a_hit() {
+    let mut vm = load_program("movimm r0 1\nstore 0x0FFF r0");
+    vm.watchpoints
+        .watch_memory(0x0FFF, 1, Access::Any, Action::Stop)
+        .unwrap();
+
+    assert!(matches!(vm.run(), ExitReason::Faulted(_)));
+    assert!(vm.watchpoints.log.is_empty());
+}
+
+//
+// ---------- registers ----------
+//
+
+#[test]
+fn register_watchpoint_stops_on_change() {
+    let mut vm = load_program(PROGRAM);
+    vm.watchpoints.watch_register(2, Action::Stop).unwrap();
+
+    match vm.run() {
+        ExitReason::WatchpointHit(hit) => {
+            assert_eq!(hit.pc, 0x000C);
+            assert_eq!(hit.event, Event::Register(2));
+            assert_eq!((hit.old, hit.new), (0x0000, 0x1111));
+        }
+        other => panic!("expected a watchpoint, got {:?}", other),
+    }
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+    assert!(vm.watchpoints.watch_register(16, Action::Stop).is_err());
+}
+
+#[test]
+fn rewriting_the_same_value_is_not_a_change() {
+    let mut vm = load_program("movimm r1 0\nmovimm r1 5\nmovimm r1 5\nhalt");
+    vm.watchpoints.watch_register(1, Action::Log).unwrap();
+
+    vm.run();
+    assert_eq!(vm.watchpoints.log.len(), 1);
+}
+
+#[test]
+fn remove_watchpoint() {
+    let mut vm = load_program(PROGRAM);
+    let id = vm.watchpoints.watch_register(0, Action::Stop).unwrap();
+
+    assert!(vm.watchpoints.remove(id));
+    assert!(!vm.watchpoints.remove(id));
+
+    assert_eq!(vm.run(), ExitReason::Halted);
+}
+
+#[test]
+fn single_step_does_not_leave_a_stale_stop() {
+    let mut vm = load_program(PROGRAM);
+    vm.watchpoints.watch_register(0, Action::Stop).unwrap();
+
+    vm.step();
+    assert_eq!(vm.watchpoints.log.len(), 1);
+
+    // the hit from the step above was already seen
+    assert_eq!(vm.run(), ExitReason::Halted);
+}
+
+//
+// ---------- debugger ----------
+//
+
+#[test]
+fn debugger_watch_commands() {
+    let mut dbg = Debugger::new(PROGRAM).unwrap();
+
+    assert_eq!(
+        dbg.command("watch 0x0200").unwrap(),
+        "Watchpoint 1: writes to 0x0200 (2 bytes)"
+    );
+    assert_eq!(
+        dbg.command("awatch 0x0202 2 log").unwrap(),
+        "Watchpoint 2: accesses to 0x0202 (2 bytes), log only"
+    );
+    assert_eq!(
+        dbg.command("watch r2").unwrap(),
+        "Watchpoint 3: changes to r2"
+    );
+    assert!(dbg.command("rwatch r2").is_err());
+    assert_eq!(dbg.command("watch").unwrap().lines().count(), 3);
+
+    let reply = dbg.command("c").unwrap();
+    assert!(reply.starts_with("watchpoint 1: write 0x0200 at PC 0x0008, 0x0000 -> 0x1111"));
+    assert!(reply.ends_with("line 5: load r2 0x0200"));
+
+    dbg.command("unwatch 1").unwrap();
+    let reply = dbg.command("c").unwrap();
+    assert!(reply.starts_with("watchpoint 3: r2 changed at PC 0x000C, 0x0000 -> 0x1111"));
+
+    let reply = dbg.command("c").unwrap();
+    let lines: Vec<&str> = reply.lines().collect();
+    assert_eq!(
+        lines[0],
+        "watchpoint 2: read 0x0202 at PC 0x0014, value 0x0000"
+    );
+    assert_eq!(lines[1], "Program halted.");
+
+    assert!(dbg.command("unwatch 1").is_err());
+    dbg.command("unwatch").unwrap();
+    assert_eq!(dbg.command("watch").unwrap(), "No watchpoints.");
+}